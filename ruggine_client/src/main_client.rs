@@ -1,57 +1,160 @@
 use eframe::egui::{self, Align, Color32, Frame, Layout, Margin, Rounding, Stroke, Vec2};
 use futures_util::{stream::StreamExt, SinkExt};
+use reqwest::StatusCode;
 use reqwest::{header, Client as HttpClient};
-use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::fmt::format;
+use ruggine_protocol::{
+    find_mentions, Attachment, ChangePasswordPayload, CreateGroupPayload, CreateInviteLinkPayload,
+    DeleteAccountPayload, DirectConversation, DirectMessageSent, DirectoryPage, EditMessagePayload,
+    ErrorResponse, Group, GroupDirectoryQuery, GroupMember, GroupProfileChange, GroupProfileField,
+    GroupRole, GroupVisibility, Invitation, InviteLink, InviteToGroupPayload, JoinRequest,
+    LoginPayload, LoginResponse, MarkReadPayload, MemberLeftReason, Mention, MentionPage,
+    MentionQuery, MessageHistoryQuery, MessagePage, MessageSearchQuery, MessageThread,
+    PasswordResetToken, PresenceStatus, RefreshPayload, RefreshResponse, RegisterUserPayload,
+    ReplyPreview, ResetPasswordPayload, SearchHit, SearchPage, SendDirectMessagePayload,
+    TransferOwnershipPayload, UnreadCount, UpdateGroupPayload, UpdateGroupVisibilityPayload,
+    UpdateMemberRolePayload, UpdateProfilePayload, User, WsMuxCommand, WsMuxEvent, WsServerEvent,
+    WsServerMessage, ALLOWED_ATTACHMENT_TYPES, ALLOWED_AVATAR_TYPES, CLOSE_SESSION_REVOKED,
+    MAX_ATTACHMENT_SIZE, MAX_AVATAR_SIZE, MAX_BIO_CHARS, MAX_DISPLAY_NAME_CHARS,
+    MAX_GROUP_DESCRIPTION_CHARS, MAX_GROUP_TOPIC_CHARS, MAX_STATUS_MESSAGE_CHARS, MIN_PASSWORD_LEN,
+    TYPING_EXPIRY_MS, TYPING_MIN_INTERVAL_MS, WS_PROTOCOL_VERSION,
+};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
+use time::OffsetDateTime;
+use tokio::net::TcpStream;
 use tokio::runtime::Runtime;
 use tokio::sync::mpsc::{self, Receiver, Sender};
-use tokio_tungstenite::connect_async;
+use tokio::sync::watch;
 use tokio_tungstenite::tungstenite::Message as WsMessage;
+use tokio_tungstenite::{connect_async, MaybeTlsStream, WebSocketStream};
 use uuid::Uuid;
-use reqwest::StatusCode;
 
 const API_BASE_URL: &str = "http://127.0.0.1:3000";
+// Per quanto mostrare "sta scrivendo…" dopo l'ultimo evento, e ogni quanto inviarlo (mai più spesso di quanto il server accetti)
+const TYPING_DISPLAY_TIMEOUT: Duration = Duration::from_millis(TYPING_EXPIRY_MS);
+const TYPING_SEND_INTERVAL: Duration = Duration::from_millis(TYPING_MIN_INTERVAL_MS + 1_000);
+// L'access token dura 15 minuti: lo rinnoviamo con un buon margine
+const TOKEN_REFRESH_INTERVAL: Duration = Duration::from_secs(10 * 60);
+// Per quanto resta evidenziato un messaggio raggiunto dalla ricerca
+const HIGHLIGHT_DURATION: Duration = Duration::from_secs(3);
+// Emoji proposte nel menu delle reazioni
+const QUICK_REACTIONS: &[&str] = &["👍", "❤", "😂", "😮", "😢", "🎉"];
+// Attesa prima di riconnettere il WebSocket: raddoppia a ogni tentativo fallito, fino al massimo
+const RECONNECT_BASE_DELAY: Duration = Duration::from_secs(1);
+const RECONNECT_MAX_DELAY: Duration = Duration::from_secs(30);
+// Dopo quanto tempo senza input l'utente risulta assente agli altri
+const AWAY_AFTER: Duration = Duration::from_secs(5 * 60);
 
 // --- Data Structures ---
 
-#[derive(Deserialize, Debug, Clone)]
-struct User {
-    id: Uuid,
-    username: String,
+/// Ricerca nei messaggi: testo digitato e ultima pagina di risultati ricevuta.
+#[derive(Default)]
+struct SearchState {
+    input: String,
+    only_current_group: bool,
+    /// Query e gruppo dei risultati mostrati, per chiedere le pagine successive.
+    active: Option<(String, Option<Uuid>)>,
+    hits: Vec<SearchHit>,
+    has_more: bool,
+    loading: bool,
+}
+
+/// Casella delle menzioni: pagine già caricate e menzioni arrivate mentre era chiusa.
+#[derive(Default)]
+struct MentionsState {
+    open: bool,
+    items: Vec<Mention>,
+    has_more: bool,
+    loading: bool,
+    unread: usize,
+}
+
+/// Elenco dei gruppi pubblici: filtro digitato e pagine già caricate.
+#[derive(Default)]
+struct DirectoryState {
+    open: bool,
+    input: String,
+    /// Filtro dei risultati mostrati, per chiedere le pagine successive.
+    active: String,
+    page: Option<DirectoryPage>,
+    loading: bool,
+    // Nome esatto di un gruppo privato in cui chiedere di entrare
+    private_name: String,
+}
+
+/// Finestra dei link di invito di un gruppo, aperta da un admin.
+#[derive(Default)]
+struct InviteLinksState {
+    group_id: Option<Uuid>,
+    links: Vec<InviteLink>,
+    loading: bool,
+    // Campi del modulo per un nuovo link: vuoti significa senza limite
+    expires_in_hours: String,
+    max_uses: String,
 }
 
-#[derive(Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
-struct Group {
-    id: Uuid,
+/// Finestra del profilo di un gruppo: campi in modifica e cronologia delle modifiche.
+#[derive(Default)]
+struct GroupProfileState {
+    group_id: Option<Uuid>,
     name: String,
+    description: String,
+    topic: String,
+    history: Vec<GroupProfileChange>,
+    loading: bool,
+}
+
+/// Finestra per modificare il proprio profilo.
+#[derive(Default)]
+struct MyProfileState {
+    open: bool,
+    display_name: String,
+    status_message: String,
+    bio: String,
+    old_password: String,
+    new_password: String,
+    confirm_password: String,
 }
 
-#[derive(Deserialize, Debug, Clone)]
-struct Invitation {
-    id: Uuid,
-    group_name: String,
-    inviter_username: String,
+/// Azione irreversibile in attesa di conferma da parte del proprietario.
+enum PendingConfirmation {
+    // Il nome del gruppo va riscritto in `typed_name` per abilitare l'eliminazione
+    DeleteGroup { group_id: Uuid, name: String, typed_name: String },
+    TransferOwnership { group_id: Uuid, member_id: Uuid, username: String },
+    // La password attuale conferma l'eliminazione dell'account
+    DeleteAccount { password: String },
 }
 
-#[derive(Serialize)]
-struct WsClientMessage {
-    content: String,
+/// Elemento della cronologia mostrata in chat.
+#[derive(Debug, Clone)]
+enum ChatItem {
+    Message(WsServerMessage),
+    Notice(String), // Avvisi locali (ingressi, uscite...), non salvati sul server
 }
 
-#[derive(Deserialize, Debug, Clone)]
-struct WsServerMessage {
-    sender_id: Uuid,
-    sender_username: String,
-    content: String,
+/// Stato della connessione WebSocket, mostrato nella barra laterale.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ConnectionStatus {
+    Connecting,
+    Connected,
+    Reconnecting { attempt: u32, retry_in: Duration },
 }
 
-#[derive(Deserialize)]
-struct LoginResponse {
-    token: String,
-    user: User,
-    groups: Vec<Group>,
+/// Ultimo messaggio visto per ogni gruppo, da cui riprendere dopo una riconnessione.
+type MessageCursors = Arc<Mutex<HashMap<Uuid, Uuid>>>;
+
+/// Azione richiesta da una bolla della chat.
+enum BubbleAction {
+    Edit(Uuid, String),
+    Delete(Uuid),
+    Download(Attachment),
+    LoadImage(Uuid), // Anteprima di un allegato non ancora scaricata
+    React(Uuid, String, bool), // true per aggiungere la reazione, false per toglierla
+    Reply(ReplyPreview),
+    OpenThread(Uuid),
+    JumpTo(Uuid), // Clic sulla citazione: porta al messaggio originale
+    ShowProfile(Uuid),
 }
 
 // --- Messages between UI and Backend Thread ---
@@ -60,38 +163,123 @@ enum ToBackend {
     Register(String, String),
     Login(String, String),
     Logout,
-    CreateGroup(String),
-    JoinGroup(Group),
+    ResetPassword(String, String), // Token di reset e nuova password, senza login
+    ChangePassword(String, String),
+    DeleteAccount(String),
+    IssuePasswordReset(Uuid),
+    RefreshSession,
+    CreateGroup(String, GroupVisibility),
+    SetGroupVisibility(Uuid, GroupVisibility),
+    UpdateGroupProfile(Uuid, UpdateGroupPayload),
+    ChangeGroupAvatar(Uuid),
+    FetchGroupHistory(Uuid),
+    FetchGroupAvatar(Uuid, String),
+    UpdateProfile(UpdateProfilePayload),
+    ChangeProfileAvatar,
+    FetchUserProfile(Uuid),
+    FetchUserAvatar(Uuid, String),
+    FetchDirectory(String, u32),
+    JoinPublicGroup(Uuid),
+    RequestToJoin(String),
+    FetchJoinRequests(Uuid),
+    AnswerJoinRequest(Uuid, Uuid, bool),
     LeaveGroup(Uuid),
+    DeleteGroup(Uuid),
+    UpdateMemberRole(Uuid, Uuid, GroupRole),
+    TransferOwnership(Uuid, Uuid),
+    KickMember(Uuid, Uuid),
+    BanMember(Uuid, Uuid),
     InviteUser(Uuid, String),
-    SendMessage(Uuid, String),
+    FetchInviteLinks(Uuid),
+    CreateInviteLink(Uuid, CreateInviteLinkPayload),
+    RevokeInviteLink(Uuid, String),
+    JoinWithInviteCode(String),
+    SendMessage(Uuid, String, Option<Uuid>),
+    SendAttachment(Uuid, String, Option<Uuid>),
+    FetchAttachment(Uuid, Uuid),
+    DownloadAttachment(Uuid, Attachment),
+    StartDirectConversation(String),
+    SendDirectMessage(Uuid, String),
+    SendTyping(Uuid),
+    SetAway(bool),
+    MarkRead(Uuid, Uuid),
+    EditMessage(Uuid, Uuid, String),
+    DeleteMessage(Uuid, Uuid),
+    SetReaction(Uuid, Uuid, String, bool),
     FetchInvitations,
     AcceptInvitation(Uuid),
     DeclineInvitation(Uuid),
     FetchGroupMessages(Uuid),
+    FetchOlderMessages(Uuid, Uuid),
+    SearchMessages(String, Option<Uuid>, u32),
     FetchGroupMembers(Uuid),
+    FetchThread(Uuid, Uuid),
+    FetchMentions(u32),
 }
 
 #[derive(Debug)]
 enum FromBackend {
-    LoggedIn(User, String, Vec<Group>),
+    LoggedIn(User, String, Vec<Group>, Vec<DirectConversation>, Vec<UnreadCount>, bool),
+    SessionRefreshed(String),
+    SessionExpired,
     Registered,
+    PasswordReset,
+    PasswordChanged,
+    AccountDeleted,
+    PasswordResetIssued(Uuid, PasswordResetToken),
     GroupJoined(Group),
     GroupLeft(Uuid),
+    GroupDeleted(Uuid),
+    GroupDeletedByOwner(Uuid, String),
+    OwnershipTransferred(Uuid, Uuid, String),
+    RemovedFromGroup(Uuid),
     NewMessage(Uuid, WsServerMessage),
+    AttachmentFetched(Uuid, Vec<u8>),
+    DirectPeerFound(User),
+    DirectMessage(DirectConversation, WsServerMessage),
+    MemberJoined(Uuid, Uuid, String),
+    MemberLeft(Uuid, Uuid, String, MemberLeftReason, Option<String>),
+    UserTyping(Uuid, Uuid, String),
+    PresenceChanged(Uuid, PresenceStatus, Option<OffsetDateTime>),
+    MessageEdited(Uuid, Uuid, String, OffsetDateTime),
+    MessageDeleted(Uuid, Uuid),
+    ThreadLoaded(Uuid, MessageThread),
+    ReadUpTo(Uuid, Uuid, Uuid),
+    ReactionAdded(Uuid, Uuid, Uuid, String),
+    ReactionRemoved(Uuid, Uuid, Uuid, String),
     Info(String),
     Error(String),
     InvitationsFetched(Vec<Invitation>),
+    InviteLinksFetched(Uuid, Vec<InviteLink>),
+    DirectoryFetched(String, u32, DirectoryPage),
+    GroupUpdated(Group),
+    GroupProfileUpdated(Group, String),
+    GroupHistoryFetched(Uuid, Vec<GroupProfileChange>),
+    AvatarFetched(String, Vec<u8>),
+    UserUpdated(User),
+    UserProfileFetched(User),
+    JoinRequestsFetched(Uuid, Vec<JoinRequest>),
+    JoinRequestReceived(JoinRequest),
+    InvitationReceived(Invitation),
+    InvitationAnswered(String, String, bool),
+    ConnectionChanged(ConnectionStatus),
+    MessagesReplayed(Uuid, Vec<WsServerMessage>),
+    HistoryStale(Uuid),
     InvitationDeclined(Uuid),
     GroupCreated(Group),
-    GroupMessagesFetched(Uuid, Vec<WsServerMessage>),
-    GroupMembersFetched(Uuid, Vec<User>),
+    GroupMessagesFetched(Uuid, MessagePage),
+    OlderMessagesFetched(Uuid, MessagePage),
+    SearchResults(String, Option<Uuid>, u32, SearchPage),
+    MentionsFetched(u32, MentionPage),
+    Mentioned(Mention),
+    GroupMembersFetched(Uuid, Vec<GroupMember>),
 }
 
 #[derive(PartialEq)]
 enum AuthState {
     Login,
     Register,
+    ResetPassword,
 }
 
 // --- Application State ---
@@ -99,20 +287,67 @@ enum AuthState {
 struct RuggineApp {
     username_input: String,
     password_input: String,
+    reset_token_input: String,
     create_group_input: String,
+    create_group_public: bool,
+    directory: DirectoryState,
+    // Richieste di ingresso al gruppo selezionato, visibili agli admin
+    join_requests: Vec<JoinRequest>,
     invite_user_input: String,
+    invite_code_input: String,
+    invite_links: InviteLinksState,
+    group_profile: GroupProfileState,
+    pending_confirmation: Option<PendingConfirmation>,
+    my_profile: MyProfileState,
+    // Scheda del profilo di un altro utente, aperta dal suo nome
+    profile_card: Option<User>,
+    // Ultimo token di reset emesso (solo amministratori), mostrato nella scheda dell'utente
+    issued_reset_token: Option<(Uuid, PasswordResetToken)>,
     chat_message_input: String,
+    editing_message_id: Option<Uuid>,
+    replying_to: Option<ReplyPreview>,
+    // Discussione aperta nella finestra laterale, col gruppo a cui appartiene
+    open_thread: Option<(Uuid, MessageThread)>,
     error_message: Option<String>,
     info_message: Option<String>,
     auth_state: AuthState,
     current_user: Option<User>,
+    is_admin: bool, // Amministratore del server, non di un gruppo
     auth_token: Option<String>,
     user_groups: Vec<Group>,
+    direct_conversations: Vec<DirectConversation>,
+    direct_peer_input: String,
+    // Destinatario di una conversazione non ancora creata: nasce col primo messaggio
+    draft_direct_peer: Option<User>,
     selected_group_id: Option<Uuid>,
-    selected_group_members: Option<Vec<User>>,
-    messages: HashMap<Uuid, Vec<WsServerMessage>>,
+    selected_group_members: Option<Vec<GroupMember>>,
+    messages: HashMap<Uuid, Vec<ChatItem>>,
+    history_has_more: HashMap<Uuid, bool>,
+    loading_older: HashSet<Uuid>,
+    scroll_anchor: Option<Uuid>,
+    search: SearchState,
+    mentions: MentionsState,
+    // Risultato di ricerca da raggiungere nella cronologia (gruppo, messaggio)
+    jump_target: Option<(Uuid, Uuid)>,
+    highlighted_message: Option<(Uuid, Instant)>,
+    // Byte delle immagini allegate già scaricate, per le anteprime nelle bolle
+    attachment_images: HashMap<Uuid, Arc<[u8]>>,
+    requested_images: HashSet<Uuid>,
+    // Avatar di gruppi e utenti già scaricati, per SHA-256
+    avatars: HashMap<String, Arc<[u8]>>,
+    requested_avatars: HashSet<String>,
+    unread_counts: HashMap<Uuid, u32>,
+    // Ultimo messaggio segnalato come letto al server, per non ripetere la richiesta
+    marked_read: HashMap<Uuid, Uuid>,
+    typing_users: HashMap<Uuid, HashMap<Uuid, (String, Instant)>>,
+    presence: HashMap<Uuid, PresenceStatus>,
+    // Ultimo input dell'utente, per segnalarlo assente quando si allontana
+    last_activity: Instant,
+    away: bool,
+    connection_status: ConnectionStatus,
+    last_typing_sent: Instant,
     pending_invitations: Vec<Invitation>,
-    last_invitation_fetch: Instant,
+    last_token_refresh: Instant,
     to_backend_tx: Sender<ToBackend>,
     from_backend_rx: Receiver<FromBackend>,
     _runtime: Runtime,
@@ -124,6 +359,7 @@ impl RuggineApp {
         let (from_backend_tx, from_backend_rx) = mpsc::channel(32);
 
         configure_styles(&cc.egui_ctx);
+        egui_extras::install_image_loaders(&cc.egui_ctx);
 
         let runtime = tokio::runtime::Builder::new_multi_thread()
             .enable_all()
@@ -133,9 +369,13 @@ impl RuggineApp {
         let egui_ctx = cc.egui_ctx.clone();
         runtime.spawn(async move {
             let mut client = HttpClient::new();
-            let mut ws_senders: HashMap<Uuid, Sender<WsMessage>> = HashMap::new();
+            // Unica connessione WebSocket, multiplexata su tutti i gruppi dell'utente
+            let mut ws_sender: Option<Sender<WsMuxCommand>> = None;
+            // Token usato dalla connessione per riconnettersi, aggiornato a ogni rinnovo
+            let mut ws_token: Option<watch::Sender<String>> = None;
+            let cursors = MessageCursors::default();
             let mut _current_user: Option<User> = None;
-            let mut current_token: Option<String> = None;
+            let mut current_refresh_token: Option<String> = None;
 
             while let Some(action) = to_backend_rx.recv().await {
                 match action {
@@ -145,26 +385,21 @@ impl RuggineApp {
                     }
                     ToBackend::Login(username, password) => {
                         match handle_login(username, password).await {
-                            Ok((from_backend_msg, authenticated_client)) => {
+                            Ok((from_backend_msg, refresh_token, authenticated_client)) => {
                                 client = authenticated_client;
-                                if let FromBackend::LoggedIn(ref user, ref token, ref groups) = from_backend_msg {
+                                current_refresh_token = Some(refresh_token);
+                                if let FromBackend::LoggedIn(ref user, ref token, ..) = from_backend_msg {
                                     
                                     _current_user = Some(user.clone());
-                                    current_token = Some(token.clone());
-
-                                    // Chiudi le connessioni WebSocket precedenti
-                                    for (_, sender) in ws_senders.drain() {
-                                        let _ = sender.send(WsMessage::Close(None)).await;
-                                    }
 
-                                    // Subscribe to all groups upon login
-                                    for group in groups {
-                                        if let Some(token) = &current_token {
-                                            if let Ok(ws_tx) = handle_join_group(group.clone(), token.clone(), from_backend_tx.clone()).await {
-                                                ws_senders.insert(group.id, ws_tx);
-                                            }
-                                        }
-                                    }
+                                    // Il server iscrive la connessione a tutti i gruppi dell'utente;
+                                    // sostituire il canale chiude la connessione precedente
+                                    cursors.lock().unwrap().clear();
+                                    let (token_tx, token_rx) = watch::channel(token.clone());
+                                    let (command_tx, command_rx) = mpsc::channel(32);
+                                    tokio::spawn(run_user_socket(token_rx, user.id, command_rx, cursors.clone(), from_backend_tx.clone()));
+                                    ws_sender = Some(command_tx);
+                                    ws_token = Some(token_tx);
                                 }
                                 let _ = from_backend_tx.send(from_backend_msg).await;
                             }
@@ -174,68 +409,296 @@ impl RuggineApp {
                         }
                     }
                     ToBackend::Logout => {
-                       // Chiudi correttamente ogni WebSocket
-                        for (_, sender) in ws_senders.drain() {
-                            let _ = sender.send(WsMessage::Close(None)).await;
-                        }
+                        // Chiudendo il canale dei comandi il WebSocket viene chiuso correttamente
+                        ws_sender = None;
+                        ws_token = None;
+
+                        // Revoca la sessione lato server; se fallisce il token scadrà comunque
+                        handle_logout(&client).await;
 
                         _current_user = None;
-                        current_token = None;
+                        current_refresh_token = None;
                         client = HttpClient::new();
 
                         let _ = from_backend_tx.send(FromBackend::Info("Logout effettuato.".into())).await;
                     }
-                    ToBackend::CreateGroup(group_name) => {
-                        match handle_create_group(&client, group_name).await {
-                            Ok(group) => {
-                                if let Some(token) = &current_token {
-                                    if let Ok(ws_tx) = handle_join_group(group.clone(), token.clone(), from_backend_tx.clone()).await {
-                                        ws_senders.insert(group.id, ws_tx);
-                                    }
-                                    let _ = from_backend_tx.send(FromBackend::GroupCreated(group.clone())).await;
-                                    let users = handle_fetch_group_members(&client, group.id).await;
-                                    let _ = from_backend_tx.send(users).await;
+                    ToBackend::ResetPassword(token, new_password) => {
+                        let res = handle_reset_password(token, new_password).await;
+                        let _ = from_backend_tx.send(res).await;
+                    }
+                    ToBackend::ChangePassword(old_password, new_password) => {
+                        let res = handle_change_password(&client, old_password, new_password).await;
+                        let _ = from_backend_tx.send(res).await;
+                    }
+                    ToBackend::DeleteAccount(password) => {
+                        let res = handle_delete_account(&client, password).await;
+                        if matches!(res, FromBackend::AccountDeleted) {
+                            // Come al logout: la sessione non esiste più sul server
+                            ws_sender = None;
+                            ws_token = None;
+                            _current_user = None;
+                            current_refresh_token = None;
+                            client = HttpClient::new();
+                        }
+                        let _ = from_backend_tx.send(res).await;
+                    }
+                    ToBackend::IssuePasswordReset(user_id) => {
+                        let res = handle_issue_password_reset(&client, user_id).await;
+                        let _ = from_backend_tx.send(res).await;
+                    }
+                    ToBackend::RefreshSession => {
+                        let Some(refresh_token) = current_refresh_token.clone() else { continue };
+                        match handle_refresh(refresh_token).await {
+                            Ok((refreshed, authenticated_client)) => {
+                                client = authenticated_client;
+                                current_refresh_token = Some(refreshed.refresh_token);
+                                if let Some(token_tx) = &ws_token {
+                                    let _ = token_tx.send(refreshed.token.clone());
                                 }
+                                let _ = from_backend_tx.send(FromBackend::SessionRefreshed(refreshed.token)).await;
                             }
                             Err(e) => {
+                                ws_sender = None;
+                                ws_token = None;
+                                _current_user = None;
+                                current_refresh_token = None;
+                                client = HttpClient::new();
                                 let _ = from_backend_tx.send(e).await;
                             }
                         }
                     }
-                    ToBackend::JoinGroup(group) => {
-                         if let Some(token) = &current_token {
-                             match handle_join_group(group.clone(), token.clone(), from_backend_tx.clone()).await {
-                                Ok(ws_tx) => {
-                                    ws_senders.insert(group.id, ws_tx);
-                                    let _ = from_backend_tx.send(FromBackend::GroupJoined(group.clone())).await;
-                                    let _ = from_backend_tx.send(FromBackend::Info(format!("Entrato in '{}'", group.name))).await;
-                                }
-                                Err(e) => {
-                                    let _ = from_backend_tx.send(e).await;
-                                }
+                    ToBackend::SetGroupVisibility(group_id, visibility) => {
+                        let res = handle_set_group_visibility(&client, group_id, visibility).await;
+                        let _ = from_backend_tx.send(res).await;
+                    }
+                    ToBackend::UpdateGroupProfile(group_id, payload) => {
+                        let res = handle_update_group(&client, group_id, &payload).await;
+                        let _ = from_backend_tx.send(res).await;
+                    }
+                    ToBackend::ChangeGroupAvatar(group_id) => {
+                        // Il selettore di file non deve bloccare gli altri comandi
+                        let client = client.clone();
+                        let from_backend_tx = from_backend_tx.clone();
+                        let egui_ctx = egui_ctx.clone();
+                        tokio::spawn(async move {
+                            if let Some(res) = handle_change_group_avatar(&client, group_id).await {
+                                let _ = from_backend_tx.send(res).await;
+                                egui_ctx.request_repaint();
+                            }
+                        });
+                    }
+                    ToBackend::FetchGroupHistory(group_id) => {
+                        let res = handle_fetch_group_history(&client, group_id).await;
+                        let _ = from_backend_tx.send(res).await;
+                    }
+                    ToBackend::FetchGroupAvatar(group_id, sha256) => {
+                        let res = handle_fetch_group_avatar(&client, group_id, sha256).await;
+                        let _ = from_backend_tx.send(res).await;
+                    }
+                    ToBackend::UpdateProfile(payload) => {
+                        let res = handle_update_profile(&client, &payload).await;
+                        let _ = from_backend_tx.send(res).await;
+                    }
+                    ToBackend::ChangeProfileAvatar => {
+                        let client = client.clone();
+                        let from_backend_tx = from_backend_tx.clone();
+                        let egui_ctx = egui_ctx.clone();
+                        tokio::spawn(async move {
+                            if let Some(res) = handle_change_profile_avatar(&client).await {
+                                let _ = from_backend_tx.send(res).await;
+                                egui_ctx.request_repaint();
+                            }
+                        });
+                    }
+                    ToBackend::FetchUserProfile(user_id) => {
+                        let res = handle_fetch_user_profile(&client, user_id).await;
+                        let _ = from_backend_tx.send(res).await;
+                    }
+                    ToBackend::FetchUserAvatar(user_id, sha256) => {
+                        let res = handle_fetch_user_avatar(&client, user_id, sha256).await;
+                        let _ = from_backend_tx.send(res).await;
+                    }
+                    ToBackend::FetchDirectory(query, offset) => {
+                        let res = handle_fetch_directory(&client, query, offset).await;
+                        let _ = from_backend_tx.send(res).await;
+                    }
+                    ToBackend::JoinPublicGroup(group_id) => {
+                        match handle_join_public_group(&client, group_id).await {
+                            Ok(group) => {
+                                send_ws_command(&ws_sender, WsMuxCommand::Subscribe { group_id: group.id, after: None }).await;
+                                let _ = from_backend_tx.send(FromBackend::GroupJoined(group)).await;
+                            }
+                            Err(e) => {
+                                let _ = from_backend_tx.send(e).await;
+                            }
+                        }
+                    }
+                    ToBackend::RequestToJoin(name) => {
+                        let res = handle_request_to_join(&client, name).await;
+                        let _ = from_backend_tx.send(res).await;
+                    }
+                    ToBackend::FetchJoinRequests(group_id) => {
+                        let res = handle_fetch_join_requests(&client, group_id).await;
+                        let _ = from_backend_tx.send(res).await;
+                    }
+                    ToBackend::AnswerJoinRequest(group_id, request_id, approve) => {
+                        let res = match handle_answer_join_request(&client, group_id, request_id, approve).await {
+                            Ok(()) => handle_fetch_join_requests(&client, group_id).await,
+                            Err(e) => e,
+                        };
+                        let _ = from_backend_tx.send(res).await;
+                    }
+                    ToBackend::CreateGroup(group_name, visibility) => {
+                        match handle_create_group(&client, group_name, visibility).await {
+                            Ok(group) => {
+                                send_ws_command(&ws_sender, WsMuxCommand::Subscribe { group_id: group.id, after: None }).await;
+                                let _ = from_backend_tx.send(FromBackend::GroupCreated(group.clone())).await;
+                                let users = handle_fetch_group_members(&client, group.id).await;
+                                let _ = from_backend_tx.send(users).await;
+                            }
+                            Err(e) => {
+                                let _ = from_backend_tx.send(e).await;
                             }
                         }
                     }
                     ToBackend::LeaveGroup(group_id) => {
                         let res = handle_leave_group(&client, group_id).await;
                         if let FromBackend::GroupLeft(id) = res {
-                            if let Some(sender) = ws_senders.remove(&id) {
-                                let _ = sender.send(WsMessage::Close(None)).await;
-                            }
+                            send_ws_command(&ws_sender, WsMuxCommand::Unsubscribe { group_id: id }).await;
                         }
                         let _ = from_backend_tx.send(res).await;
                     }
+                    ToBackend::DeleteGroup(group_id) => {
+                        let res = handle_delete_group(&client, group_id).await;
+                        if let FromBackend::GroupDeleted(id) = res {
+                            send_ws_command(&ws_sender, WsMuxCommand::Unsubscribe { group_id: id }).await;
+                        }
+                        let _ = from_backend_tx.send(res).await;
+                    }
+                    ToBackend::UpdateMemberRole(group_id, user_id, role) => {
+                        let res = handle_update_member_role(&client, group_id, user_id, role).await;
+                        let _ = from_backend_tx.send(res).await;
+                        let members = handle_fetch_group_members(&client, group_id).await;
+                        let _ = from_backend_tx.send(members).await;
+                    }
+                    ToBackend::TransferOwnership(group_id, user_id) => {
+                        let res = handle_transfer_ownership(&client, group_id, user_id).await;
+                        let _ = from_backend_tx.send(res).await;
+                        let members = handle_fetch_group_members(&client, group_id).await;
+                        let _ = from_backend_tx.send(members).await;
+                    }
+                    ToBackend::KickMember(group_id, user_id) => {
+                        let res = handle_remove_member(&client, group_id, user_id, false).await;
+                        let _ = from_backend_tx.send(res).await;
+                        let members = handle_fetch_group_members(&client, group_id).await;
+                        let _ = from_backend_tx.send(members).await;
+                    }
+                    ToBackend::BanMember(group_id, user_id) => {
+                        let res = handle_remove_member(&client, group_id, user_id, true).await;
+                        let _ = from_backend_tx.send(res).await;
+                        let members = handle_fetch_group_members(&client, group_id).await;
+                        let _ = from_backend_tx.send(members).await;
+                    }
                     ToBackend::InviteUser(group_id, username_to_invite) => {
                         let res = handle_invite(&client, group_id, username_to_invite).await;
                         let _ = from_backend_tx.send(res).await;
                     }
-                    ToBackend::SendMessage(group_id, content) => {
-                        if let Some(sender) = ws_senders.get(&group_id) {
-                            let msg = WsClientMessage { content };
-                            let json_msg = serde_json::to_string(&msg).unwrap();
-                            if sender.send(WsMessage::Text(json_msg)).await.is_err() {
-                                let _ = from_backend_tx.send(FromBackend::Error("Connessione persa.".into())).await;
+                    ToBackend::FetchInviteLinks(group_id) => {
+                        let res = handle_fetch_invite_links(&client, group_id).await;
+                        let _ = from_backend_tx.send(res).await;
+                    }
+                    ToBackend::CreateInviteLink(group_id, payload) => {
+                        let res = match handle_create_invite_link(&client, group_id, &payload).await {
+                            Ok(()) => handle_fetch_invite_links(&client, group_id).await,
+                            Err(e) => e,
+                        };
+                        let _ = from_backend_tx.send(res).await;
+                    }
+                    ToBackend::RevokeInviteLink(group_id, code) => {
+                        let res = match handle_revoke_invite_link(&client, group_id, &code).await {
+                            Ok(()) => handle_fetch_invite_links(&client, group_id).await,
+                            Err(e) => e,
+                        };
+                        let _ = from_backend_tx.send(res).await;
+                    }
+                    ToBackend::JoinWithInviteCode(code) => {
+                        match handle_join_with_invite_code(&client, &code).await {
+                            Ok(group) => {
+                                send_ws_command(&ws_sender, WsMuxCommand::Subscribe { group_id: group.id, after: None }).await;
+                                let _ = from_backend_tx.send(FromBackend::GroupJoined(group)).await;
+                            }
+                            Err(e) => {
+                                let _ = from_backend_tx.send(e).await;
+                            }
+                        }
+                    }
+                    ToBackend::SendMessage(group_id, content, reply_to) => {
+                        if !send_ws_command(&ws_sender, WsMuxCommand::SendMessage { group_id, content, reply_to }).await {
+                            let _ = from_backend_tx.send(FromBackend::Error("Connessione persa.".into())).await;
+                        }
+                    }
+                    ToBackend::SendAttachment(group_id, content, reply_to) => {
+                        // Il selettore di file resta aperto a lungo: non blocca gli altri comandi
+                        let client = client.clone();
+                        let from_backend_tx = from_backend_tx.clone();
+                        let egui_ctx = egui_ctx.clone();
+                        tokio::spawn(async move {
+                            if let Some(res) = handle_send_attachment(&client, group_id, content, reply_to).await {
+                                let _ = from_backend_tx.send(res).await;
+                                egui_ctx.request_repaint();
+                            }
+                        });
+                    }
+                    ToBackend::FetchAttachment(group_id, attachment_id) => {
+                        let res = handle_fetch_attachment(&client, group_id, attachment_id).await;
+                        let _ = from_backend_tx.send(res).await;
+                    }
+                    ToBackend::DownloadAttachment(group_id, attachment) => {
+                        let client = client.clone();
+                        let from_backend_tx = from_backend_tx.clone();
+                        let egui_ctx = egui_ctx.clone();
+                        tokio::spawn(async move {
+                            if let Some(res) = handle_download_attachment(&client, group_id, attachment).await {
+                                let _ = from_backend_tx.send(res).await;
+                                egui_ctx.request_repaint();
                             }
+                        });
+                    }
+                    ToBackend::StartDirectConversation(username) => {
+                        let res = handle_find_user(&client, username).await;
+                        let _ = from_backend_tx.send(res).await;
+                    }
+                    ToBackend::SendDirectMessage(peer_id, content) => {
+                        let res = handle_send_direct_message(&client, peer_id, content).await;
+                        if let FromBackend::DirectMessage(ref conversation, ref message) = res {
+                            cursors.lock().unwrap().insert(conversation.id, message.id);
+                        }
+                        let _ = from_backend_tx.send(res).await;
+                    }
+                    ToBackend::SendTyping(group_id) => {
+                        send_ws_command(&ws_sender, WsMuxCommand::Typing { group_id }).await;
+                    }
+                    ToBackend::SetAway(away) => {
+                        send_ws_command(&ws_sender, WsMuxCommand::SetAway { away }).await;
+                    }
+                    ToBackend::MarkRead(group_id, message_id) => {
+                        if let Err(e) = handle_mark_read(&client, group_id, message_id).await {
+                            let _ = from_backend_tx.send(e).await;
+                        }
+                    }
+                    ToBackend::EditMessage(group_id, message_id, content) => {
+                        if let Err(e) = handle_edit_message(&client, group_id, message_id, content).await {
+                            let _ = from_backend_tx.send(e).await;
+                        }
+                    }
+                    ToBackend::DeleteMessage(group_id, message_id) => {
+                        if let Err(e) = handle_delete_message(&client, group_id, message_id).await {
+                            let _ = from_backend_tx.send(e).await;
+                        }
+                    }
+                    ToBackend::SetReaction(group_id, message_id, emoji, add) => {
+                        if let Err(e) = handle_set_reaction(&client, group_id, message_id, &emoji, add).await {
+                            let _ = from_backend_tx.send(e).await;
                         }
                     }
                     ToBackend::FetchInvitations => {
@@ -245,12 +708,8 @@ impl RuggineApp {
                     ToBackend::AcceptInvitation(id) => {
                          match handle_accept_invitation(&client, id).await {
                             Ok(group) => {
-                                if let Some(token) = &current_token {
-                                    if let Ok(ws_tx) = handle_join_group(group.clone(), token.clone(), from_backend_tx.clone()).await {
-                                        ws_senders.insert(group.id, ws_tx);
-                                    }
-                                    let _ = from_backend_tx.send(FromBackend::GroupJoined(group)).await;
-                                }
+                                send_ws_command(&ws_sender, WsMuxCommand::Subscribe { group_id: group.id, after: None }).await;
+                                let _ = from_backend_tx.send(FromBackend::GroupJoined(group)).await;
                             }
                             Err(e) => {
                                 let _ = from_backend_tx.send(e).await;
@@ -262,12 +721,34 @@ impl RuggineApp {
                         let _ = from_backend_tx.send(res).await;
                     }
                     ToBackend::FetchGroupMessages(group_id) => {
-                        let res = handle_fetch_group_messages(&client, group_id).await;
+                        let res = handle_fetch_group_messages(&client, group_id, None).await;
+                        // La cronologia fa da punto di ripresa finché non arrivano messaggi in tempo reale
+                        if let FromBackend::GroupMessagesFetched(group_id, ref page) = res {
+                            if let Some(last) = page.messages.last() {
+                                cursors.lock().unwrap().entry(group_id).or_insert(last.id);
+                            }
+                        }
+                        let _ = from_backend_tx.send(res).await;
+                    }
+                    ToBackend::FetchOlderMessages(group_id, before) => {
+                        let res = handle_fetch_group_messages(&client, group_id, Some(before)).await;
+                        let _ = from_backend_tx.send(res).await;
+                    }
+                    ToBackend::SearchMessages(query, group_id, offset) => {
+                        let res = handle_search(&client, query, group_id, offset).await;
                         let _ = from_backend_tx.send(res).await;
                     }
                     ToBackend::FetchGroupMembers(group_id) =>{
                         let res = handle_fetch_group_members(&client, group_id).await;
-                        from_backend_tx.send(res).await;
+                        let _ = from_backend_tx.send(res).await;
+                    }
+                    ToBackend::FetchMentions(offset) => {
+                        let res = handle_fetch_mentions(&client, offset).await;
+                        let _ = from_backend_tx.send(res).await;
+                    }
+                    ToBackend::FetchThread(group_id, message_id) => {
+                        let res = handle_fetch_thread(&client, group_id, message_id).await;
+                        let _ = from_backend_tx.send(res).await;
                     }
                 }
                 egui_ctx.request_repaint();
@@ -277,20 +758,57 @@ impl RuggineApp {
         Self {
             username_input: String::new(),
             password_input: String::new(),
+            reset_token_input: String::new(),
             create_group_input: String::new(),
+            create_group_public: false,
+            directory: DirectoryState::default(),
+            join_requests: Vec::new(),
             invite_user_input: String::new(),
+            invite_code_input: String::new(),
+            invite_links: InviteLinksState::default(),
+            group_profile: GroupProfileState::default(),
+            pending_confirmation: None,
+            my_profile: MyProfileState::default(),
+            profile_card: None,
+            issued_reset_token: None,
             chat_message_input: String::new(),
+            editing_message_id: None,
+            replying_to: None,
+            open_thread: None,
             error_message: None,
             info_message: None,
             auth_state: AuthState::Login,
             current_user: None,
+            is_admin: false,
             auth_token: None,
             user_groups: Vec::new(),
+            direct_conversations: Vec::new(),
+            direct_peer_input: String::new(),
+            draft_direct_peer: None,
             selected_group_id: None,
             selected_group_members:None,
             messages: HashMap::new(),
+            history_has_more: HashMap::new(),
+            loading_older: HashSet::new(),
+            scroll_anchor: None,
+            search: SearchState::default(),
+            mentions: MentionsState::default(),
+            jump_target: None,
+            highlighted_message: None,
+            attachment_images: HashMap::new(),
+            requested_images: HashSet::new(),
+            avatars: HashMap::new(),
+            requested_avatars: HashSet::new(),
+            unread_counts: HashMap::new(),
+            marked_read: HashMap::new(),
+            typing_users: HashMap::new(),
+            presence: HashMap::new(),
+            last_activity: Instant::now(),
+            away: false,
+            connection_status: ConnectionStatus::Connecting,
+            last_typing_sent: Instant::now(),
             pending_invitations: Vec::new(),
-            last_invitation_fetch: Instant::now() - Duration::from_secs(60),
+            last_token_refresh: Instant::now(),
             to_backend_tx,
             from_backend_rx,
             _runtime: runtime,
@@ -303,9 +821,11 @@ impl eframe::App for RuggineApp {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
         self.handle_backend_messages();
         if self.current_user.is_some() {
-            if self.last_invitation_fetch.elapsed() > Duration::from_secs(15) {
-                self.to_backend_tx.try_send(ToBackend::FetchInvitations).ok();
-                self.last_invitation_fetch = Instant::now();
+            self.mark_selected_read();
+            self.update_away(ctx);
+            if self.last_token_refresh.elapsed() > TOKEN_REFRESH_INTERVAL {
+                self.to_backend_tx.try_send(ToBackend::RefreshSession).ok();
+                self.last_token_refresh = Instant::now();
             }
             self.draw_main_view(ctx);
         } else {
@@ -321,21 +841,53 @@ impl RuggineApp {
             self.error_message = None;
             self.info_message = None;
             match msg {
-                FromBackend::LoggedIn(user, token, groups) => {
+                FromBackend::LoggedIn(user, token, groups, direct_conversations, unread, is_admin) => {
                                 self.current_user = Some(user);
+                                self.is_admin = is_admin;
                                 self.auth_token = Some(token);
+                                self.last_token_refresh = Instant::now();
                                 self.user_groups = groups.clone();
-                                if let Some(first_group) = groups.get(0) {
+                                self.direct_conversations = direct_conversations;
+                                self.unread_counts = unread.into_iter().map(|u| (u.group_id, u.unread_count)).collect();
+                                if let Some(first_group) = groups.first() {
                                     self.selected_group_id = Some(first_group.id);
                                     self.to_backend_tx.try_send(ToBackend::FetchGroupMessages(first_group.id)).ok();
                                     self.to_backend_tx.try_send(ToBackend::FetchGroupMembers(first_group.id)).ok();
 
                                 }
                             }
+                FromBackend::SessionRefreshed(token) => {
+                                self.auth_token = Some(token);
+                            }
+                FromBackend::SessionExpired => {
+                                self.reset_session_state();
+                                self.error_message = Some("Sessione scaduta, effettua di nuovo il login.".into());
+                            }
                 FromBackend::Registered => {
                                 self.info_message = Some("Registrazione avvenuta! Ora puoi effettuare il login.".into());
                                 self.auth_state = AuthState::Login;
                             }
+                FromBackend::PasswordReset => {
+                                self.info_message = Some("Password reimpostata! Ora puoi effettuare il login.".into());
+                                self.reset_token_input.clear();
+                                self.password_input.clear();
+                                self.auth_state = AuthState::Login;
+                            }
+                FromBackend::PasswordChanged => {
+                                self.info_message = Some("Password cambiata: le altre sessioni sono state chiuse.".into());
+                                self.my_profile.old_password.clear();
+                                self.my_profile.new_password.clear();
+                                self.my_profile.confirm_password.clear();
+                            }
+                FromBackend::AccountDeleted => {
+                                self.reset_session_state();
+                                // La connessione chiusa dal server può aver già segnalato la sessione scaduta
+                                self.error_message = None;
+                                self.info_message = Some("Account eliminato.".into());
+                            }
+                FromBackend::PasswordResetIssued(user_id, token) => {
+                                self.issued_reset_token = Some((user_id, token));
+                            }
                 FromBackend::GroupJoined(group) => {
                                 self.info_message = Some(format!("Entrato in '{}'", group.name));
                                 self.selected_group_id = Some(group.id);
@@ -353,102 +905,1319 @@ impl RuggineApp {
                                 self.messages.insert(self.selected_group_id.unwrap(), vec![]);
                             }
                 FromBackend::GroupLeft(group_id) => {
-                                self.info_message = Some(format!("Hai lasciato un gruppo."));
-                                self.user_groups.retain(|g| g.id != group_id);
-                                self.messages.remove(&group_id);
+                                self.remove_group_locally(group_id);
+                                self.info_message = Some("Hai lasciato un gruppo.".to_string());
+                            }
+                FromBackend::GroupDeleted(group_id) => {
+                                self.remove_group_locally(group_id);
+                                self.info_message = Some("Gruppo eliminato.".to_string());
+                            }
+                FromBackend::GroupDeletedByOwner(group_id, by_username) => {
+                                let Some(name) = self.user_groups.iter().find(|g| g.id == group_id).map(|g| g.name.clone()) else { continue };
+                                self.remove_group_locally(group_id);
+                                self.info_message = Some(format!("{} ha eliminato il gruppo '{}'.", by_username, name));
+                            }
+                FromBackend::OwnershipTransferred(group_id, new_owner_id, new_owner_username) => {
+                                let notice = if self.current_user.as_ref().is_some_and(|me| me.id == new_owner_id) {
+                                    "Ora sei il proprietario del gruppo.".to_string()
+                                } else {
+                                    format!("{} è il nuovo proprietario del gruppo.", new_owner_username)
+                                };
+                                self.messages.entry(group_id).or_default().push(ChatItem::Notice(notice));
                                 if self.selected_group_id == Some(group_id) {
-                                    self.selected_group_id = self.user_groups.get(0).map(|g| g.id);
-                                    if let Some(id) = self.selected_group_id {
-                                         self.to_backend_tx.try_send(ToBackend::FetchGroupMessages(id)).ok();
-                                         self.to_backend_tx.try_send(ToBackend::FetchGroupMembers(id)).ok();
-
+                                    self.to_backend_tx.try_send(ToBackend::FetchGroupMembers(group_id)).ok();
+                                }
+                            }
+                FromBackend::RemovedFromGroup(group_id) => {
+                                let Some(name) = self.user_groups.iter().find(|g| g.id == group_id).map(|g| g.name.clone()) else { continue };
+                                self.remove_group_locally(group_id);
+                                self.error_message = Some(format!("Sei stato rimosso dal gruppo '{}'.", name));
+                            }
+                FromBackend::NewMessage(group_id, msg) => self.add_message(group_id, msg),
+                FromBackend::AttachmentFetched(attachment_id, bytes) => {
+                    self.attachment_images.insert(attachment_id, bytes.into());
+                }
+                FromBackend::DirectPeerFound(peer) => {
+                                if self.current_user.as_ref().is_some_and(|me| me.id == peer.id) {
+                                    self.error_message = Some("Non puoi scrivere a te stesso.".into());
+                                    continue;
+                                }
+                                match self.direct_conversations.iter().find(|c| c.peer_id == peer.id) {
+                                    Some(conversation) => self.open_group(conversation.id),
+                                    None => {
+                                        self.selected_group_id = None;
+                                        self.selected_group_members = None;
+                                        self.draft_direct_peer = Some(peer);
+                                    }
+                                }
+                            }
+                FromBackend::DirectMessage(conversation, msg) => {
+                                let conversation_id = conversation.id;
+                                let is_draft = self.draft_direct_peer.as_ref().is_some_and(|peer| peer.id == conversation.peer_id);
+                                if !self.direct_conversations.iter().any(|c| c.id == conversation_id) {
+                                    self.direct_conversations.push(conversation);
+                                    // Una conversazione appena nata non ha altra cronologia da scaricare
+                                    self.messages.entry(conversation_id).or_default();
+                                }
+                                self.add_message(conversation_id, msg);
+                                if is_draft {
+                                    self.open_group(conversation_id);
+                                }
+                            }
+                FromBackend::MessagesReplayed(group_id, replayed) => {
+                                for msg in replayed {
+                                    match self.find_message_mut(group_id, msg.id) {
+                                        Some(existing) => *existing = msg,
+                                        None => self.messages.entry(group_id).or_default().push(ChatItem::Message(msg)),
+                                    }
+                                }
+                            }
+                FromBackend::HistoryStale(group_id) => {
+                                // Il gruppo selezionato si ricarica subito, gli altri quando vengono aperti
+                                if self.selected_group_id == Some(group_id) {
+                                    self.to_backend_tx.try_send(ToBackend::FetchGroupMessages(group_id)).ok();
+                                } else {
+                                    self.messages.remove(&group_id);
+                                }
+                            }
+                FromBackend::ConnectionChanged(status) => {
+                                // Senza connessione non arriverebbe lo stop degli indicatori "sta scrivendo"
+                                if status != ConnectionStatus::Connected {
+                                    self.typing_users.clear();
+                                } else {
+                                    // I nuovi inviti arrivano sul WebSocket: si ricarica solo quanto arrivato mentre era chiuso
+                                    self.to_backend_tx.try_send(ToBackend::FetchInvitations).ok();
+                                    if self.away {
+                                        // Il server riparte da "online" a ogni nuova connessione
+                                        self.to_backend_tx.try_send(ToBackend::SetAway(true)).ok();
                                     }
                                 }
+                                self.connection_status = status;
+                            }
+                FromBackend::MemberJoined(group_id, user_id, username) => {
+                                self.presence.insert(user_id, PresenceStatus::Online);
+                                self.messages.entry(group_id).or_default().push(ChatItem::Notice(format!("{} è entrato nel gruppo.", username)));
+                                if self.selected_group_id == Some(group_id) {
+                                    self.to_backend_tx.try_send(ToBackend::FetchGroupMembers(group_id)).ok();
+                                }
+                            }
+                FromBackend::MemberLeft(group_id, user_id, username, reason, by_username) => {
+                                if let Some(typing) = self.typing_users.get_mut(&group_id) {
+                                    typing.remove(&user_id);
+                                }
+                                let by = by_username.map(|by| format!(" da {}", by)).unwrap_or_default();
+                                let notice = match reason {
+                                    MemberLeftReason::Left => format!("{} ha lasciato il gruppo.", username),
+                                    MemberLeftReason::Kicked => format!("{} è stato rimosso dal gruppo{}.", username, by),
+                                    MemberLeftReason::Banned => format!("{} è stato bannato dal gruppo{}.", username, by),
+                                };
+                                self.messages.entry(group_id).or_default().push(ChatItem::Notice(notice));
+                                if self.selected_group_id == Some(group_id) {
+                                    self.to_backend_tx.try_send(ToBackend::FetchGroupMembers(group_id)).ok();
+                                }
+                            }
+                FromBackend::UserTyping(group_id, user_id, username) => {
+                                self.typing_users.entry(group_id).or_default().insert(user_id, (username, Instant::now()));
+                            }
+                FromBackend::PresenceChanged(user_id, status, last_seen_at) => {
+                                self.presence.insert(user_id, status);
+                                if let Some(member) = self.selected_group_members.iter_mut().flatten().find(|m| m.id == user_id) {
+                                    member.presence = status;
+                                    if last_seen_at.is_some() {
+                                        member.last_seen_at = last_seen_at;
+                                    }
+                                }
+                            }
+                FromBackend::MessageEdited(group_id, message_id, content, edited_at) => {
+                                if let Some(msg) = self.find_message_mut(group_id, message_id) {
+                                    msg.content = content;
+                                    msg.edited_at = Some(edited_at);
+                                }
+                                self.refresh_quotes(group_id, message_id);
+                            }
+                FromBackend::MessageDeleted(group_id, message_id) => {
+                                if let Some(msg) = self.find_message_mut(group_id, message_id) {
+                                    msg.content.clear();
+                                    msg.deleted = true;
+                                }
+                                self.refresh_quotes(group_id, message_id);
+                                if self.replying_to.as_ref().is_some_and(|r| r.message_id == message_id) {
+                                    self.replying_to = None;
+                                }
+                                if self.editing_message_id == Some(message_id) {
+                                    self.editing_message_id = None;
+                                    self.chat_message_input.clear();
+                                }
+                            }
+                FromBackend::ThreadLoaded(group_id, thread) => self.open_thread = Some((group_id, thread)),
+                FromBackend::ReadUpTo(group_id, user_id, message_id) => {
+                    if self.current_user.as_ref().is_some_and(|me| me.id == user_id) {
+                        // Letto da un altro dispositivo
+                        self.unread_counts.remove(&group_id);
+                        self.marked_read.insert(group_id, message_id);
+                    } else if self.selected_group_id == Some(group_id) {
+                        if let Some(member) = self.selected_group_members.iter_mut().flatten().find(|m| m.id == user_id) {
+                            member.last_read_message_id = Some(message_id);
+                        }
+                    }
+                }
+                FromBackend::ReactionAdded(group_id, message_id, user_id, emoji) => {
+                    if let Some(msg) = self.find_message_mut(group_id, message_id) {
+                        msg.add_reaction(user_id, &emoji);
+                    }
+                }
+                FromBackend::ReactionRemoved(group_id, message_id, user_id, emoji) => {
+                    if let Some(msg) = self.find_message_mut(group_id, message_id) {
+                        msg.remove_reaction(user_id, &emoji);
+                    }
+                }
+                FromBackend::Error(err) => {
+                                // Una pagina di cronologia o di ricerca fallita può essere richiesta di nuovo
+                                self.loading_older.clear();
+                                self.search.loading = false;
+                                self.mentions.loading = false;
+                                self.jump_target = None;
+                                self.error_message = Some(err);
                             }
-                FromBackend::NewMessage(group_id, msg) => {
-                                self.messages.entry(group_id).or_default().push(msg);
-                            },
-                FromBackend::Error(err) => self.error_message = Some(err),
                 FromBackend::Info(info) => self.info_message = Some(info),
                 FromBackend::InvitationsFetched(invitations) => {
                                 self.pending_invitations = invitations;
                             }
+                FromBackend::DirectoryFetched(query, offset, page) => {
+                                // Ignora risposte a filtri ormai sostituiti
+                                if self.directory.active != query { continue; }
+                                match &mut self.directory.page {
+                                    Some(current) if offset > 0 => {
+                                        current.groups.extend(page.groups);
+                                        current.has_more = page.has_more;
+                                    }
+                                    _ => self.directory.page = Some(page),
+                                }
+                                self.directory.loading = false;
+                            }
+                FromBackend::GroupUpdated(group) => {
+                                if let Some(existing) = self.user_groups.iter_mut().find(|g| g.id == group.id) {
+                                    *existing = group;
+                                }
+                            }
+                FromBackend::GroupProfileUpdated(group, by_username) => {
+                                let Some(existing) = self.user_groups.iter_mut().find(|g| g.id == group.id) else { continue };
+                                let mut changed = Vec::new();
+                                if existing.name != group.name { changed.push("il nome"); }
+                                if existing.description != group.description { changed.push("la descrizione"); }
+                                if existing.topic != group.topic { changed.push("l'argomento"); }
+                                if existing.avatar_sha256 != group.avatar_sha256 { changed.push("l'immagine"); }
+                                let group_id = group.id;
+                                *existing = group;
+                                if let Some((last, rest)) = changed.split_last() {
+                                    let what = if rest.is_empty() { last.to_string() } else { format!("{} e {}", rest.join(", "), last) };
+                                    self.messages.entry(group_id).or_default().push(ChatItem::Notice(format!("{} ha cambiato {} del gruppo.", by_username, what)));
+                                }
+                                if self.group_profile.group_id == Some(group_id) {
+                                    self.to_backend_tx.try_send(ToBackend::FetchGroupHistory(group_id)).ok();
+                                }
+                            }
+                FromBackend::GroupHistoryFetched(group_id, history) => {
+                                if self.group_profile.group_id == Some(group_id) {
+                                    self.group_profile.history = history;
+                                    self.group_profile.loading = false;
+                                }
+                            }
+                FromBackend::AvatarFetched(sha256, bytes) => {
+                                self.avatars.insert(sha256, bytes.into());
+                            }
+                FromBackend::UserUpdated(user) => self.apply_user_update(user),
+                FromBackend::UserProfileFetched(user) => self.profile_card = Some(user),
+                FromBackend::JoinRequestsFetched(group_id, requests) => {
+                                if self.selected_group_id == Some(group_id) {
+                                    self.join_requests = requests;
+                                }
+                            }
+                FromBackend::JoinRequestReceived(request) => {
+                                self.info_message = Some(format!("{} chiede di entrare in '{}'.", request.username, request.group_name));
+                                if self.selected_group_id == Some(request.group_id) && !self.join_requests.iter().any(|r| r.id == request.id) {
+                                    self.join_requests.push(request);
+                                }
+                            }
+                FromBackend::InviteLinksFetched(group_id, links) => {
+                                if self.invite_links.group_id == Some(group_id) {
+                                    self.invite_links.links = links;
+                                    self.invite_links.loading = false;
+                                }
+                            }
+                FromBackend::InvitationReceived(invitation) => {
+                                if !self.pending_invitations.iter().any(|inv| inv.id == invitation.id) {
+                                    self.info_message = Some(format!("{} ti ha invitato in '{}'.", invitation.inviter_username, invitation.group_name));
+                                    self.pending_invitations.push(invitation);
+                                }
+                            }
+                FromBackend::InvitationAnswered(group_name, invited_username, accepted) => {
+                                let verb = if accepted { "accettato" } else { "rifiutato" };
+                                self.info_message = Some(format!("{} ha {} il tuo invito in '{}'.", invited_username, verb, group_name));
+                            }
                 FromBackend::InvitationDeclined(id) => {
                                 self.pending_invitations.retain(|inv| inv.id != id);
                                 self.info_message = Some("Invito rifiutato.".into());
                             }
-                FromBackend::GroupMessagesFetched(group_id, history) => {
-                                self.messages.insert(group_id, history);
+                FromBackend::GroupMessagesFetched(group_id, page) => {
+                                self.history_has_more.insert(group_id, page.has_more);
+                                self.loading_older.remove(&group_id);
+                                self.messages.insert(group_id, page.messages.into_iter().map(ChatItem::Message).collect());
+                                self.continue_jump();
+                            }
+                FromBackend::OlderMessagesFetched(group_id, page) => {
+                                self.history_has_more.insert(group_id, page.has_more);
+                                self.loading_older.remove(&group_id);
+                                let items = self.messages.entry(group_id).or_default();
+                                // Dopo l'inserimento la vista resta ancorata al messaggio che era in cima
+                                self.scroll_anchor = items.iter().find_map(|item| match item {
+                                    ChatItem::Message(m) => Some(m.id),
+                                    ChatItem::Notice(_) => None,
+                                });
+                                let older: Vec<ChatItem> = page
+                                    .messages
+                                    .into_iter()
+                                    .filter(|msg| !items.iter().any(|item| matches!(item, ChatItem::Message(m) if m.id == msg.id)))
+                                    .map(ChatItem::Message)
+                                    .collect();
+                                items.splice(0..0, older);
+                                self.continue_jump();
+                            }
+                FromBackend::SearchResults(query, group_id, offset, page) => {
+                                // Ignora risposte a ricerche ormai sostituite
+                                if self.search.active != Some((query, group_id)) { continue; }
+                                if offset == 0 {
+                                    self.search.hits.clear();
+                                }
+                                self.search.hits.extend(page.hits);
+                                self.search.has_more = page.has_more;
+                                self.search.loading = false;
+                            }
+                FromBackend::MentionsFetched(offset, page) => {
+                    if offset == 0 {
+                        self.mentions.items.clear();
+                    }
+                    self.mentions.items.extend(page.mentions);
+                    self.mentions.has_more = page.has_more;
+                    self.mentions.loading = false;
+                }
+                FromBackend::Mentioned(mention) => {
+                    self.info_message = Some(format!("{} ti ha menzionato in {}.", mention.message.sender_name(), mention.group_name));
+                    if self.mentions.open {
+                        self.mentions.items.insert(0, mention);
+                    } else {
+                        self.mentions.unread += 1;
+                    }
+                }
+                FromBackend::GroupMembersFetched(group_id, members) => {
+                                // Ignora risposte arrivate dopo che l'utente ha cambiato gruppo
+                                if self.selected_group_id == Some(group_id) {
+                                    self.presence.extend(members.iter().map(|m| (m.id, m.presence)));
+                                    self.selected_group_members = Some(members);
+                                    self.join_requests.clear();
+                                    if self.my_role() >= Some(GroupRole::Admin) {
+                                        self.to_backend_tx.try_send(ToBackend::FetchJoinRequests(group_id)).ok();
+                                    }
+                                }
                             }
-                FromBackend::GroupMembersFetched(uuid, members) => self.selected_group_members = Some(members),
             }
         }
     }
 
-    fn draw_auth_view(&mut self, ctx: &egui::Context) {
-        egui::CentralPanel::default().show(ctx, |ui| {
-            ui.with_layout(Layout::top_down(Align::Center), |ui| {
-                ui.add_space(ui.available_height() * 0.2);
-                ui.heading("Benvenuto in Ruggine");
-                ui.add_space(20.0);
-                Frame::none().inner_margin(Margin::same(20.0)).fill(ui.style().visuals.widgets.noninteractive.bg_fill).rounding(Rounding::same(8.0)).show(ui, |ui| {
-                    ui.set_width(300.0);
-                    ui.vertical_centered_justified(|ui| {
-                        ui.horizontal(|ui| {
-                            ui.selectable_value(&mut self.auth_state, AuthState::Login, "Login");
-                            ui.selectable_value(&mut self.auth_state, AuthState::Register, "Registrati");
-                        });
-                        ui.add_space(15.0);
-                        ui.label("Username");
-                        ui.text_edit_singleline(&mut self.username_input);
-                        ui.add_space(10.0);
-                        ui.label("Password");
-                        ui.add(egui::TextEdit::singleline(&mut self.password_input).password(true));
-                        ui.add_space(20.0);
-                        let button_text = if self.auth_state == AuthState::Login { "Login" } else { "Registrati" };
-                        if ui.button(button_text).clicked() {
-                            let action = if self.auth_state == AuthState::Login {
-                                ToBackend::Login(self.username_input.clone(), self.password_input.clone())
-                            } else {
-                                ToBackend::Register(self.username_input.clone(), self.password_input.clone())
-                            };
-                            let _ = self.to_backend_tx.try_send(action);
-                        }
-                    });
-                });
-                self.draw_info_error_messages(ui);
-            });
-        });
+    /// Toglie un gruppo dalla sidebar e, se era quello aperto, seleziona il primo rimasto.
+    fn remove_group_locally(&mut self, group_id: Uuid) {
+        self.user_groups.retain(|g| g.id != group_id);
+        self.messages.remove(&group_id);
+        if self.selected_group_id == Some(group_id) {
+            self.selected_group_members = None;
+            self.selected_group_id = self.user_groups.first().map(|g| g.id);
+            if let Some(id) = self.selected_group_id {
+                 self.to_backend_tx.try_send(ToBackend::FetchGroupMessages(id)).ok();
+                 self.to_backend_tx.try_send(ToBackend::FetchGroupMembers(id)).ok();
+            }
+        }
     }
 
-    fn draw_main_view(&mut self, ctx: &egui::Context) {
-        egui::SidePanel::left("side_panel").min_width(250.0).default_width(250.0).show(ctx, |ui| {
+    /// Aggiunge un messaggio arrivato in tempo reale alla cronologia del gruppo.
+    fn add_message(&mut self, group_id: Uuid, msg: WsServerMessage) {
+        // Un messaggio arrivato chiude l'indicatore "sta scrivendo" del mittente
+        if let Some(typing) = self.typing_users.get_mut(&group_id) {
+            typing.remove(&msg.sender_id);
+        }
+        // Una nuova risposta compare anche nella discussione aperta
+        if let Some((thread_group, thread)) = &mut self.open_thread {
+            let in_thread = msg.reply_to.as_ref().is_some_and(|r| r.message_id == thread.root.id);
+            if *thread_group == group_id && in_thread && !thread.replies.iter().any(|m| m.id == msg.id) {
+                thread.replies.push(msg.clone());
+            }
+        }
+        // Dopo una riconnessione lo stesso messaggio può arrivare due volte
+        match self.find_message_mut(group_id, msg.id) {
+            Some(existing) => *existing = msg,
+            None => {
+                let from_me = self.current_user.as_ref().is_some_and(|me| me.id == msg.sender_id);
+                if !from_me && self.selected_group_id != Some(group_id) {
+                    *self.unread_counts.entry(group_id).or_default() += 1;
+                }
+                self.messages.entry(group_id).or_default().push(ChatItem::Message(msg));
+            }
+        }
+    }
+
+    /// Titolo della chat aperta: `# nome` per i gruppi, `@ utente` per le conversazioni dirette.
+    fn chat_title(&self, group_id: Uuid) -> Option<String> {
+        if let Some(group) = self.user_groups.iter().find(|g| g.id == group_id) {
+            return Some(format!("# {}", group.name));
+        }
+        self.direct_conversations
+            .iter()
+            .find(|c| c.id == group_id)
+            .map(|c| format!("@ {}", c.peer_username))
+    }
+
+    /// Avatar (se già scaricato) e argomento del gruppo, mostrati nell'intestazione della chat.
+    fn group_header(&mut self, group_id: Uuid) -> (Option<egui::Image<'static>>, Option<String>) {
+        let Some(group) = self.user_groups.iter().find(|g| g.id == group_id) else { return (None, None) };
+        let topic = group.topic.clone();
+        let avatar = group
+            .avatar_sha256
+            .clone()
+            .and_then(|sha256| self.avatar_image(&sha256, ToBackend::FetchGroupAvatar(group_id, sha256.clone())));
+        (avatar, topic)
+    }
+
+    /// Immagine di un avatar già scaricato; se manca ne chiede il download con `fetch`.
+    fn avatar_image(&mut self, sha256: &str, fetch: ToBackend) -> Option<egui::Image<'static>> {
+        match self.avatars.get(sha256) {
+            Some(bytes) => Some(egui::Image::from_bytes(format!("bytes://avatar/{}", sha256), bytes.clone())),
+            None => {
+                if self.requested_avatars.insert(sha256.to_string()) {
+                    self.to_backend_tx.try_send(fetch).ok();
+                }
+                None
+            }
+        }
+    }
+
+    fn user_avatar(&mut self, user_id: Uuid, sha256: Option<&str>) -> Option<egui::Image<'static>> {
+        let sha256 = sha256?;
+        self.avatar_image(sha256, ToBackend::FetchUserAvatar(user_id, sha256.to_string()))
+    }
+
+    /// Nome scelto da un membro del gruppo aperto, se presente nella lista membri.
+    fn member_name(&self, user_id: Uuid) -> Option<String> {
+        let member = self.selected_group_members.as_ref()?.iter().find(|m| m.id == user_id)?;
+        Some(member.name().to_string())
+    }
+
+    /// Applica un profilo aggiornato (il nostro o quello di un membro) ovunque compaia.
+    fn apply_user_update(&mut self, user: User) {
+        if let Some(member) = self.selected_group_members.iter_mut().flatten().find(|m| m.id == user.id) {
+            member.display_name = user.display_name.clone();
+            member.status_message = user.status_message.clone();
+            member.avatar_sha256 = user.avatar_sha256.clone();
+        }
+        for item in self.messages.values_mut().flatten() {
+            if let ChatItem::Message(msg) = item {
+                if msg.sender_id == user.id {
+                    msg.sender_display_name = user.display_name.clone();
+                }
+            }
+        }
+        if self.profile_card.as_ref().is_some_and(|card| card.id == user.id) {
+            self.profile_card = Some(user.clone());
+        }
+        if self.current_user.as_ref().is_some_and(|me| me.id == user.id) {
+            self.current_user = Some(user);
+        }
+    }
+
+    fn find_message_mut(&mut self, group_id: Uuid, message_id: Uuid) -> Option<&mut WsServerMessage> {
+        self.messages.get_mut(&group_id)?.iter_mut().find_map(|item| match item {
+            ChatItem::Message(m) if m.id == message_id => Some(m),
+            _ => None,
+        })
+    }
+
+    /// Aggiorna le citazioni di un messaggio modificato o eliminato nelle risposte già caricate.
+    fn refresh_quotes(&mut self, group_id: Uuid, message_id: Uuid) {
+        let Some(items) = self.messages.get_mut(&group_id) else { return };
+        let Some(preview) = items.iter().find_map(|item| match item {
+            ChatItem::Message(m) if m.id == message_id => Some(m.preview()),
+            _ => None,
+        }) else { return };
+        for item in items.iter_mut() {
+            if let ChatItem::Message(m) = item {
+                if m.reply_to.as_ref().is_some_and(|r| r.message_id == message_id) {
+                    m.reply_to = Some(Box::new(preview.clone()));
+                }
+            }
+        }
+    }
+
+    /// Segnala al server come letto l'ultimo messaggio del gruppo aperto, se non l'ha già fatto.
+    fn presence_dot(&self, user_id: Uuid) -> &'static str {
+        match self.presence.get(&user_id) {
+            Some(PresenceStatus::Online) => "🟢",
+            Some(PresenceStatus::Away) => "🌙",
+            _ => "⚪",
+        }
+    }
+
+    /// Segnala al server quando l'utente smette di usare l'app e quando torna.
+    fn update_away(&mut self, ctx: &egui::Context) {
+        if ctx.input(|i| !i.events.is_empty() || i.pointer.is_moving()) {
+            self.last_activity = Instant::now();
+        }
+        let away = self.last_activity.elapsed() > AWAY_AFTER;
+        if away != self.away {
+            self.away = away;
+            self.to_backend_tx.try_send(ToBackend::SetAway(away)).ok();
+        }
+    }
+
+    fn mark_selected_read(&mut self) {
+        let Some(group_id) = self.selected_group_id else { return };
+        let latest = self.messages.get(&group_id).and_then(|items| {
+            items.iter().rev().find_map(|item| match item {
+                ChatItem::Message(m) => Some(m.id),
+                ChatItem::Notice(_) => None,
+            })
+        });
+        self.unread_counts.remove(&group_id);
+        if let Some(message_id) = latest {
+            if self.marked_read.get(&group_id) != Some(&message_id) {
+                self.marked_read.insert(group_id, message_id);
+                self.to_backend_tx.try_send(ToBackend::MarkRead(group_id, message_id)).ok();
+            }
+        }
+    }
+
+    /// Chi ha già letto l'ultimo messaggio inviato dall'utente nella cronologia mostrata: (messaggio, nomi).
+    fn seen_by(&self, items: &[ChatItem]) -> Option<(Uuid, Vec<String>)> {
+        let me = self.current_user.as_ref()?;
+        let positions: HashMap<Uuid, usize> = items
+            .iter()
+            .enumerate()
+            .filter_map(|(i, item)| match item {
+                ChatItem::Message(m) => Some((m.id, i)),
+                ChatItem::Notice(_) => None,
+            })
+            .collect();
+        let (mine_at, mine) = items.iter().enumerate().rev().find_map(|(i, item)| match item {
+            ChatItem::Message(m) if m.sender_id == me.id && !m.deleted => Some((i, m.id)),
+            _ => None,
+        })?;
+        // Una posizione di lettura fuori dalla cronologia caricata è più vecchia del messaggio
+        let names: Vec<String> = self
+            .selected_group_members
+            .as_ref()?
+            .iter()
+            .filter(|member| member.id != me.id)
+            .filter(|member| member.last_read_message_id.and_then(|id| positions.get(&id)).is_some_and(|&at| at >= mine_at))
+            .map(|member| member.name().to_string())
+            .collect();
+        (!names.is_empty()).then_some((mine, names))
+    }
+
+    /// Etichetta di un gruppo nella barra laterale, con i messaggi non letti.
+    fn with_unread_badge(&self, group_id: Uuid, label: String) -> String {
+        match self.unread_counts.get(&group_id) {
+            Some(&count) if count > 0 => format!("{}  ({})", label, count),
+            _ => label,
+        }
+    }
+
+    /// Nomi di chi sta scrivendo nel gruppo, escluso l'utente corrente.
+    fn typing_names(&self, group_id: Uuid) -> Vec<String> {
+        let my_id = self.current_user.as_ref().map(|u| u.id);
+        self.typing_users
+            .get(&group_id)
+            .map(|typing| {
+                typing
+                    .iter()
+                    .filter(|(id, (_, at))| Some(**id) != my_id && at.elapsed() < TYPING_DISPLAY_TIMEOUT)
+                    .map(|(id, (name, _))| self.member_name(*id).unwrap_or_else(|| name.clone()))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Ruolo dell'utente corrente nel gruppo selezionato, ricavato dalla lista membri.
+    fn my_role(&self) -> Option<GroupRole> {
+        let me = self.current_user.as_ref()?;
+        self.selected_group_members
+            .as_ref()?
+            .iter()
+            .find(|m| m.id == me.id)
+            .map(|m| m.role)
+    }
+
+    /// Seleziona un gruppo e ne scarica cronologia e membri.
+    fn open_group(&mut self, group_id: Uuid) {
+        self.draft_direct_peer = None;
+        self.replying_to = None;
+        self.selected_group_id = Some(group_id);
+        self.selected_group_members = None;
+        self.join_requests.clear();
+        self.to_backend_tx.try_send(ToBackend::FetchGroupMessages(group_id)).ok();
+        self.to_backend_tx.try_send(ToBackend::FetchGroupMembers(group_id)).ok();
+    }
+
+    /// Avvicina la cronologia al risultato di ricerca scelto: carica pagine più vecchie
+    /// finché il messaggio non è presente, poi lo porta in vista evidenziandolo.
+    fn continue_jump(&mut self) {
+        let Some((group_id, message_id)) = self.jump_target else { return };
+        let Some(items) = self.messages.get(&group_id) else { return }; // Cronologia non ancora arrivata
+        if items.iter().any(|item| matches!(item, ChatItem::Message(m) if m.id == message_id)) {
+            self.scroll_anchor = Some(message_id);
+            self.highlighted_message = Some((message_id, Instant::now()));
+            self.jump_target = None;
+            return;
+        }
+        let oldest = items.iter().find_map(|item| match item {
+            ChatItem::Message(m) => Some(m.id),
+            ChatItem::Notice(_) => None,
+        });
+        let has_more = self.history_has_more.get(&group_id).copied().unwrap_or(false);
+        match oldest {
+            Some(before) if has_more => {
+                if self.loading_older.insert(group_id) {
+                    self.to_backend_tx.try_send(ToBackend::FetchOlderMessages(group_id, before)).ok();
+                }
+            }
+            _ => {
+                self.jump_target = None;
+                self.error_message = Some("Messaggio non trovato nella cronologia.".into());
+            }
+        }
+    }
+
+    fn start_search(&mut self) {
+        let query = self.search.input.trim().to_string();
+        if query.is_empty() { return; }
+        let group_id = if self.search.only_current_group { self.selected_group_id } else { None };
+        self.search.active = Some((query.clone(), group_id));
+        self.search.hits.clear();
+        self.search.has_more = false;
+        self.search.loading = true;
+        self.to_backend_tx.try_send(ToBackend::SearchMessages(query, group_id, 0)).ok();
+    }
+
+    fn draw_search_results(&mut self, ctx: &egui::Context) {
+        let Some((query, group_id)) = self.search.active.clone() else { return };
+        let mut open = true;
+        let mut jump_to = None;
+        egui::Window::new(format!("🔍 \"{}\"", query)).open(&mut open).default_width(380.0).show(ctx, |ui| {
+            egui::ScrollArea::vertical().max_height(420.0).show(ui, |ui| {
+                for hit in &self.search.hits {
+                    let response = Frame::none()
+                        .inner_margin(Margin::same(8.0))
+                        .rounding(Rounding::same(6.0))
+                        .fill(ui.style().visuals.widgets.noninteractive.bg_fill)
+                        .show(ui, |ui| {
+                            ui.set_width(ui.available_width());
+                            ui.label(egui::RichText::new(format!("# {} · {}", hit.group_name, hit.message.sender_name())).small().color(Color32::GRAY));
+                            ui.label(highlighted_snippet(&hit.snippet, &hit.highlights));
+                        })
+                        .response
+                        .interact(egui::Sense::click());
+                    if response.on_hover_text("Mostra nella chat").clicked() {
+                        jump_to = Some((hit.group_id, hit.message.id));
+                    }
+                    ui.add_space(4.0);
+                }
+                if self.search.loading {
+                    ui.vertical_centered(|ui| ui.spinner());
+                } else if self.search.hits.is_empty() {
+                    ui.label("Nessun risultato.");
+                } else if self.search.has_more && ui.button("Altri risultati").clicked() {
+                    self.search.loading = true;
+                    let offset = self.search.hits.len() as u32;
+                    self.to_backend_tx.try_send(ToBackend::SearchMessages(query.clone(), group_id, offset)).ok();
+                }
+            });
+        });
+        if !open {
+            self.search.active = None;
+        }
+        if let Some((group_id, message_id)) = jump_to {
+            self.jump_to_message(group_id, message_id);
+        }
+    }
+
+    /// Apre il gruppo (se serve) e porta in vista il messaggio.
+    fn jump_to_message(&mut self, group_id: Uuid, message_id: Uuid) {
+        self.jump_target = Some((group_id, message_id));
+        if self.selected_group_id == Some(group_id) && self.messages.contains_key(&group_id) {
+            self.continue_jump();
+        } else {
+            self.open_group(group_id);
+        }
+    }
+
+    fn open_mentions(&mut self) {
+        self.mentions.open = true;
+        self.mentions.unread = 0;
+        self.mentions.loading = true;
+        self.to_backend_tx.try_send(ToBackend::FetchMentions(0)).ok();
+    }
+
+    /// Finestra con i messaggi che menzionano l'utente, dal più recente.
+    fn draw_mentions(&mut self, ctx: &egui::Context) {
+        if !self.mentions.open { return; }
+        let mut open = true;
+        let mut jump_to = None;
+        egui::Window::new("@ Menzioni").open(&mut open).default_width(380.0).show(ctx, |ui| {
+            egui::ScrollArea::vertical().max_height(420.0).show(ui, |ui| {
+                for mention in &self.mentions.items {
+                    let response = Frame::none()
+                        .inner_margin(Margin::same(8.0))
+                        .rounding(Rounding::same(6.0))
+                        .fill(ui.style().visuals.widgets.noninteractive.bg_fill)
+                        .show(ui, |ui| {
+                            ui.set_width(ui.available_width());
+                            ui.label(egui::RichText::new(format!("{} · {}", mention.group_name, mention.message.sender_name())).small().color(Color32::GRAY));
+                            ui.label(&mention.message.content);
+                        })
+                        .response
+                        .interact(egui::Sense::click());
+                    if response.on_hover_text("Mostra nella chat").clicked() {
+                        jump_to = Some((mention.group_id, mention.message.id));
+                    }
+                    ui.add_space(4.0);
+                }
+                if self.mentions.loading {
+                    ui.vertical_centered(|ui| ui.spinner());
+                } else if self.mentions.items.is_empty() {
+                    ui.label("Nessuno ti ha ancora menzionato.");
+                } else if self.mentions.has_more && ui.button("Altre menzioni").clicked() {
+                    self.mentions.loading = true;
+                    let offset = self.mentions.items.len() as u32;
+                    self.to_backend_tx.try_send(ToBackend::FetchMentions(offset)).ok();
+                }
+            });
+        });
+        if !open {
+            self.mentions.open = false;
+        }
+        if let Some((group_id, message_id)) = jump_to {
+            self.jump_to_message(group_id, message_id);
+        }
+    }
+
+    fn open_directory(&mut self) {
+        self.directory.open = true;
+        self.search_directory();
+    }
+
+    fn search_directory(&mut self) {
+        self.directory.active = self.directory.input.trim().to_string();
+        self.directory.loading = true;
+        self.to_backend_tx.try_send(ToBackend::FetchDirectory(self.directory.active.clone(), 0)).ok();
+    }
+
+    /// Finestra con i gruppi pubblici, in cui si entra direttamente, e la richiesta di ingresso a quelli privati.
+    fn draw_directory(&mut self, ctx: &egui::Context) {
+        if !self.directory.open { return; }
+        let mut open = true;
+        egui::Window::new("🌐 Gruppi pubblici").open(&mut open).default_width(380.0).show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                let response = ui.add(egui::TextEdit::singleline(&mut self.directory.input).hint_text("Filtra per nome").desired_width(220.0));
+                if ui.button("🔍").clicked() || (response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter))) {
+                    self.search_directory();
+                }
+            });
+            ui.separator();
+            egui::ScrollArea::vertical().max_height(320.0).show(ui, |ui| {
+                if let Some(page) = &self.directory.page {
+                    for group in &page.groups {
+                        ui.horizontal(|ui| {
+                            ui.label(egui::RichText::new(&group.name).strong());
+                            ui.label(egui::RichText::new(format!("{} membri", group.member_count)).small().color(Color32::GRAY));
+                            if group.is_member {
+                                ui.label(egui::RichText::new("già membro").small().italics().color(Color32::GRAY));
+                            } else if ui.small_button("Entra").clicked() {
+                                self.to_backend_tx.try_send(ToBackend::JoinPublicGroup(group.id)).ok();
+                            }
+                        });
+                    }
+                    if page.groups.is_empty() && !self.directory.loading {
+                        ui.label("Nessun gruppo pubblico trovato.");
+                    } else if page.has_more && !self.directory.loading && ui.button("Altri gruppi").clicked() {
+                        self.directory.loading = true;
+                        let offset = page.groups.len() as u32;
+                        self.to_backend_tx.try_send(ToBackend::FetchDirectory(self.directory.active.clone(), offset)).ok();
+                    }
+                }
+                if self.directory.loading {
+                    ui.vertical_centered(|ui| ui.spinner());
+                }
+            });
+            ui.separator();
+            ui.label("Gruppo privato:");
+            ui.horizontal(|ui| {
+                ui.add(egui::TextEdit::singleline(&mut self.directory.private_name).hint_text("Nome esatto").desired_width(220.0));
+                if ui.button("✋ Chiedi di entrare").clicked() && !self.directory.private_name.trim().is_empty() {
+                    self.to_backend_tx.try_send(ToBackend::RequestToJoin(self.directory.private_name.trim().to_string())).ok();
+                    self.directory.private_name.clear();
+                }
+            });
+        });
+        if !open {
+            self.directory.open = false;
+        }
+    }
+
+    fn open_invite_links(&mut self, group_id: Uuid) {
+        self.invite_links = InviteLinksState { group_id: Some(group_id), loading: true, ..Default::default() };
+        self.to_backend_tx.try_send(ToBackend::FetchInviteLinks(group_id)).ok();
+    }
+
+    /// Finestra per creare, copiare e revocare i link di invito del gruppo.
+    fn draw_invite_links(&mut self, ctx: &egui::Context) {
+        let Some(group_id) = self.invite_links.group_id else { return };
+        let group_name = self.user_groups.iter().find(|g| g.id == group_id).map(|g| g.name.clone()).unwrap_or_default();
+        let mut open = true;
+        egui::Window::new(format!("🔗 Link di invito di '{}'", group_name)).open(&mut open).default_width(380.0).show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                ui.add(egui::TextEdit::singleline(&mut self.invite_links.expires_in_hours).hint_text("Scade dopo (ore)").desired_width(110.0));
+                ui.add(egui::TextEdit::singleline(&mut self.invite_links.max_uses).hint_text("Utilizzi massimi").desired_width(110.0));
+                if ui.button("➕ Genera").clicked() {
+                    // Un campo vuoto (o non numerico) vuol dire nessun limite
+                    let payload = CreateInviteLinkPayload {
+                        expires_in_hours: self.invite_links.expires_in_hours.trim().parse().ok(),
+                        max_uses: self.invite_links.max_uses.trim().parse().ok(),
+                    };
+                    self.to_backend_tx.try_send(ToBackend::CreateInviteLink(group_id, payload)).ok();
+                    self.invite_links.expires_in_hours.clear();
+                    self.invite_links.max_uses.clear();
+                }
+            });
+            ui.separator();
+            egui::ScrollArea::vertical().max_height(320.0).show(ui, |ui| {
+                let now = OffsetDateTime::now_utc();
+                for link in self.invite_links.links.clone() {
+                    ui.horizontal(|ui| {
+                        let code = egui::RichText::new(&link.code).monospace();
+                        ui.label(if link.is_usable(now) { code } else { code.strikethrough().color(Color32::GRAY) });
+                        let uses = match link.max_uses {
+                            Some(max) => format!("{}/{} usi", link.uses, max),
+                            None => format!("{} usi", link.uses),
+                        };
+                        let details = match link.expires_at {
+                            Some(at) if at <= now => format!("{}, scaduto", uses),
+                            Some(at) => format!("{}, scade tra {} ore", uses, (at - now).whole_hours()),
+                            None => uses,
+                        };
+                        ui.label(egui::RichText::new(details).small().color(Color32::GRAY))
+                            .on_hover_text(format!("Creato da {}", link.created_by_username));
+                        if ui.small_button("📋").on_hover_text("Copia il codice").clicked() {
+                            ui.ctx().copy_text(link.code.clone());
+                            self.info_message = Some("Codice copiato.".into());
+                        }
+                        if ui.small_button("🗑").on_hover_text("Revoca").clicked() {
+                            self.to_backend_tx.try_send(ToBackend::RevokeInviteLink(group_id, link.code.clone())).ok();
+                        }
+                    });
+                }
+                if self.invite_links.loading {
+                    ui.vertical_centered(|ui| ui.spinner());
+                } else if self.invite_links.links.is_empty() {
+                    ui.label("Nessun link di invito.");
+                }
+            });
+        });
+        if !open {
+            self.invite_links.group_id = None;
+        }
+    }
+
+    fn open_group_profile(&mut self, group_id: Uuid) {
+        let Some(group) = self.user_groups.iter().find(|g| g.id == group_id) else { return };
+        self.group_profile = GroupProfileState {
+            group_id: Some(group_id),
+            name: group.name.clone(),
+            description: group.description.clone().unwrap_or_default(),
+            topic: group.topic.clone().unwrap_or_default(),
+            history: Vec::new(),
+            loading: true,
+        };
+        self.to_backend_tx.try_send(ToBackend::FetchGroupHistory(group_id)).ok();
+    }
+
+    /// Finestra con descrizione, argomento e immagine del gruppo, modificabili dagli admin, e la cronologia delle modifiche.
+    fn draw_group_profile(&mut self, ctx: &egui::Context) {
+        let Some(group_id) = self.group_profile.group_id else { return };
+        let Some(group) = self.user_groups.iter().find(|g| g.id == group_id).cloned() else {
+            self.group_profile = GroupProfileState::default();
+            return;
+        };
+        let can_edit = self.selected_group_id == Some(group_id) && self.my_role() >= Some(GroupRole::Admin);
+        let mut open = true;
+        egui::Window::new(format!("ℹ Profilo di '{}'", group.name)).open(&mut open).default_width(380.0).show(ctx, |ui| {
+            egui::Grid::new("group_profile_grid").num_columns(2).show(ui, |ui| {
+                ui.label("Nome:");
+                ui.add_enabled(can_edit, egui::TextEdit::singleline(&mut self.group_profile.name));
+                ui.end_row();
+                ui.label("Argomento:");
+                ui.add_enabled(can_edit, egui::TextEdit::singleline(&mut self.group_profile.topic).char_limit(MAX_GROUP_TOPIC_CHARS));
+                ui.end_row();
+                ui.label("Descrizione:");
+                ui.add_enabled(can_edit, egui::TextEdit::multiline(&mut self.group_profile.description).char_limit(MAX_GROUP_DESCRIPTION_CHARS).desired_rows(3));
+                ui.end_row();
+            });
+            if can_edit {
+                ui.horizontal(|ui| {
+                    if ui.button("💾 Salva").clicked() {
+                        // Invia solo i campi cambiati, per non registrare modifiche vuote
+                        let profile = &self.group_profile;
+                        let changed = |new: &str, old: Option<&str>| (new.trim() != old.unwrap_or_default()).then(|| new.trim().to_string());
+                        let payload = UpdateGroupPayload {
+                            name: changed(&profile.name, Some(&group.name)),
+                            description: changed(&profile.description, group.description.as_deref()),
+                            topic: changed(&profile.topic, group.topic.as_deref()),
+                            remove_avatar: false,
+                        };
+                        self.to_backend_tx.try_send(ToBackend::UpdateGroupProfile(group_id, payload)).ok();
+                    }
+                    if ui.button("🖼 Cambia immagine").clicked() {
+                        self.to_backend_tx.try_send(ToBackend::ChangeGroupAvatar(group_id)).ok();
+                    }
+                    if group.avatar_sha256.is_some() && ui.button("Rimuovi immagine").clicked() {
+                        let payload = UpdateGroupPayload { remove_avatar: true, ..Default::default() };
+                        self.to_backend_tx.try_send(ToBackend::UpdateGroupProfile(group_id, payload)).ok();
+                    }
+                });
+            }
+            ui.separator();
+            ui.label(egui::RichText::new("Modifiche").strong());
+            egui::ScrollArea::vertical().max_height(240.0).show(ui, |ui| {
+                for change in &self.group_profile.history {
+                    let who = change.changed_by_username.as_deref().unwrap_or("Un utente eliminato");
+                    let text = match change.field {
+                        GroupProfileField::Name => format!("{} ha rinominato il gruppo in '{}'", who, change.new_value.as_deref().unwrap_or_default()),
+                        GroupProfileField::Description => format!("{} ha cambiato la descrizione", who),
+                        GroupProfileField::Topic => match &change.new_value {
+                            Some(topic) => format!("{} ha cambiato l'argomento in '{}'", who, topic),
+                            None => format!("{} ha tolto l'argomento", who),
+                        },
+                        GroupProfileField::Avatar => match change.new_value {
+                            Some(_) => format!("{} ha cambiato l'immagine", who),
+                            None => format!("{} ha tolto l'immagine", who),
+                        },
+                    };
+                    ui.horizontal_wrapped(|ui| {
+                        ui.label(text);
+                        ui.label(egui::RichText::new(format_last_seen(change.created_at)).small().color(Color32::GRAY));
+                    });
+                }
+                if self.group_profile.loading {
+                    ui.vertical_centered(|ui| ui.spinner());
+                } else if self.group_profile.history.is_empty() {
+                    ui.label("Nessuna modifica.");
+                }
+            });
+        });
+        if !open {
+            self.group_profile.group_id = None;
+        }
+    }
+
+    fn open_profile_card(&mut self, user_id: Uuid) {
+        self.to_backend_tx.try_send(ToBackend::FetchUserProfile(user_id)).ok();
+    }
+
+    /// Scheda con avatar, nome, stato e biografia di un utente.
+    fn draw_profile_card(&mut self, ctx: &egui::Context) {
+        let Some(user) = self.profile_card.clone() else { return };
+        let avatar = self.user_avatar(user.id, user.avatar_sha256.as_deref());
+        let presence = self.presence.get(&user.id).copied().unwrap_or_default();
+        let mut open = true;
+        egui::Window::new(format!("👤 {}", user.name())).open(&mut open).collapsible(false).resizable(false).default_width(260.0).show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                if let Some(avatar) = avatar {
+                    ui.add(avatar.max_size(Vec2::splat(64.0)).rounding(Rounding::same(32.0)));
+                }
+                ui.vertical(|ui| {
+                    ui.heading(user.name());
+                    ui.label(egui::RichText::new(format!("@{}", user.username)).color(Color32::GRAY));
+                    ui.label(format!("{} {}", self.presence_dot(user.id), match presence {
+                        PresenceStatus::Online => "Online",
+                        PresenceStatus::Away => "Assente",
+                        PresenceStatus::Offline => "Offline",
+                    }));
+                });
+            });
+            if let Some(status) = &user.status_message {
+                ui.label(egui::RichText::new(status).italics());
+            }
+            if let Some(bio) = &user.bio {
+                ui.separator();
+                ui.label(bio);
+            }
+            let is_me = self.current_user.as_ref().is_some_and(|me| me.id == user.id);
+            if self.is_admin && !is_me {
+                ui.separator();
+                if ui.button("🔑 Token di reset password").on_hover_text("Da consegnare all'utente: vale una sola volta").clicked() {
+                    self.to_backend_tx.try_send(ToBackend::IssuePasswordReset(user.id)).ok();
+                }
+                if let Some((_, token)) = self.issued_reset_token.as_ref().filter(|(user_id, _)| *user_id == user.id) {
+                    ui.horizontal(|ui| {
+                        ui.monospace(&token.token);
+                        if ui.small_button("📋").on_hover_text("Copia il token").clicked() {
+                            ui.ctx().copy_text(token.token.clone());
+                        }
+                    });
+                    let hours = (token.expires_at - OffsetDateTime::now_utc()).whole_hours();
+                    ui.label(egui::RichText::new(format!("Scade tra {} ore", hours)).small().color(Color32::GRAY));
+                }
+            }
+        });
+        if !open {
+            self.profile_card = None;
+        }
+    }
+
+    fn open_my_profile(&mut self) {
+        let Some(me) = &self.current_user else { return };
+        self.my_profile = MyProfileState {
+            open: true,
+            display_name: me.display_name.clone().unwrap_or_default(),
+            status_message: me.status_message.clone().unwrap_or_default(),
+            bio: me.bio.clone().unwrap_or_default(),
+            ..Default::default()
+        };
+    }
+
+    /// Finestra per cambiare nome visualizzato, stato, biografia e avatar.
+    fn draw_my_profile(&mut self, ctx: &egui::Context) {
+        if !self.my_profile.open { return };
+        let Some(me) = self.current_user.clone() else { return };
+        let avatar = self.user_avatar(me.id, me.avatar_sha256.as_deref());
+        let mut open = true;
+        egui::Window::new("👤 Il tuo profilo").open(&mut open).default_width(360.0).show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                if let Some(avatar) = avatar {
+                    ui.add(avatar.max_size(Vec2::splat(64.0)).rounding(Rounding::same(32.0)));
+                }
+                ui.vertical(|ui| {
+                    ui.label(egui::RichText::new(format!("@{}", me.username)).strong());
+                    if ui.button("🖼 Cambia immagine").clicked() {
+                        self.to_backend_tx.try_send(ToBackend::ChangeProfileAvatar).ok();
+                    }
+                    if me.avatar_sha256.is_some() && ui.button("Rimuovi immagine").clicked() {
+                        let payload = UpdateProfilePayload { remove_avatar: true, ..Default::default() };
+                        self.to_backend_tx.try_send(ToBackend::UpdateProfile(payload)).ok();
+                    }
+                });
+            });
+            ui.separator();
+            egui::Grid::new("my_profile_grid").num_columns(2).show(ui, |ui| {
+                ui.label("Nome visualizzato:");
+                ui.add(egui::TextEdit::singleline(&mut self.my_profile.display_name).char_limit(MAX_DISPLAY_NAME_CHARS).hint_text(&me.username));
+                ui.end_row();
+                ui.label("Stato:");
+                ui.add(egui::TextEdit::singleline(&mut self.my_profile.status_message).char_limit(MAX_STATUS_MESSAGE_CHARS));
+                ui.end_row();
+                ui.label("Biografia:");
+                ui.add(egui::TextEdit::multiline(&mut self.my_profile.bio).char_limit(MAX_BIO_CHARS).desired_rows(3));
+                ui.end_row();
+            });
+            if ui.button("💾 Salva").clicked() {
+                // Un campo svuotato toglie il valore dal profilo
+                let payload = UpdateProfilePayload {
+                    display_name: Some(self.my_profile.display_name.clone()),
+                    bio: Some(self.my_profile.bio.clone()),
+                    status_message: Some(self.my_profile.status_message.clone()),
+                    remove_avatar: false,
+                };
+                self.to_backend_tx.try_send(ToBackend::UpdateProfile(payload)).ok();
+            }
+            ui.separator();
+            ui.collapsing("🔒 Cambia password", |ui| {
+                egui::Grid::new("change_password_grid").num_columns(2).show(ui, |ui| {
+                    ui.label("Password attuale:");
+                    ui.add(egui::TextEdit::singleline(&mut self.my_profile.old_password).password(true));
+                    ui.end_row();
+                    ui.label("Nuova password:");
+                    ui.add(egui::TextEdit::singleline(&mut self.my_profile.new_password).password(true));
+                    ui.end_row();
+                    ui.label("Ripeti la nuova:");
+                    ui.add(egui::TextEdit::singleline(&mut self.my_profile.confirm_password).password(true));
+                    ui.end_row();
+                });
+                let profile = &self.my_profile;
+                let mismatch = !profile.confirm_password.is_empty() && profile.new_password != profile.confirm_password;
+                if mismatch {
+                    ui.colored_label(Color32::LIGHT_RED, "Le due password non coincidono.");
+                }
+                let ready = !profile.old_password.is_empty() && profile.new_password.len() >= MIN_PASSWORD_LEN && profile.new_password == profile.confirm_password;
+                let button = ui.add_enabled(ready, egui::Button::new("Cambia password"));
+                if button.on_disabled_hover_text(format!("Almeno {} caratteri", MIN_PASSWORD_LEN)).clicked() {
+                    let command = ToBackend::ChangePassword(profile.old_password.clone(), profile.new_password.clone());
+                    self.to_backend_tx.try_send(command).ok();
+                }
+            });
+            ui.separator();
+            if ui.button(egui::RichText::new("🗑 Elimina account").color(Color32::LIGHT_RED)).clicked() {
+                self.pending_confirmation = Some(PendingConfirmation::DeleteAccount { password: String::new() });
+            }
+        });
+        if !open {
+            self.my_profile.open = false;
+        }
+    }
+
+    /// Chiede conferma prima di eliminare un gruppo o cederne la proprietà.
+    fn draw_confirmation(&mut self, ctx: &egui::Context) {
+        let Some(pending) = &mut self.pending_confirmation else { return };
+        let mut confirmed = false;
+        let mut cancelled = false;
+        egui::Window::new("⚠ Conferma").collapsible(false).resizable(false).anchor(egui::Align2::CENTER_CENTER, Vec2::ZERO).show(ctx, |ui| {
+            let ready = match pending {
+                PendingConfirmation::DeleteGroup { name, typed_name, .. } => {
+                    ui.label(format!("Il gruppo '{}' verrà eliminato per tutti i membri, con tutti i suoi messaggi.", name));
+                    ui.label("Scrivi il nome del gruppo per confermare:");
+                    ui.text_edit_singleline(typed_name);
+                    typed_name.trim() == name.as_str()
+                }
+                PendingConfirmation::TransferOwnership { username, .. } => {
+                    ui.label(format!("{} diventerà il proprietario del gruppo e tu resterai come admin.", username));
+                    true
+                }
+                PendingConfirmation::DeleteAccount { password } => {
+                    ui.label("Il tuo account verrà eliminato e uscirai da tutti i gruppi.");
+                    ui.label("I tuoi messaggi resteranno, firmati come utente eliminato.");
+                    ui.label("Inserisci la password per confermare:");
+                    ui.add(egui::TextEdit::singleline(password).password(true));
+                    !password.is_empty()
+                }
+            };
+            ui.horizontal(|ui| {
+                confirmed = ui.add_enabled(ready, egui::Button::new("Conferma")).clicked();
+                cancelled = ui.button("Annulla").clicked();
+            });
+        });
+        if confirmed {
+            let command = match self.pending_confirmation.take() {
+                Some(PendingConfirmation::DeleteGroup { group_id, .. }) => ToBackend::DeleteGroup(group_id),
+                Some(PendingConfirmation::TransferOwnership { group_id, member_id, .. }) => ToBackend::TransferOwnership(group_id, member_id),
+                Some(PendingConfirmation::DeleteAccount { password }) => ToBackend::DeleteAccount(password),
+                None => return,
+            };
+            self.to_backend_tx.try_send(command).ok();
+        } else if cancelled {
+            self.pending_confirmation = None;
+        }
+    }
+
+    /// Finestra con un messaggio e le sue risposte.
+    fn draw_thread(&mut self, ctx: &egui::Context) {
+        let Some((group_id, thread)) = &self.open_thread else { return };
+        let mut open = true;
+        let mut reply_to = None;
+        egui::Window::new(format!("🧵 Discussione di {}", thread.root.sender_name())).open(&mut open).default_width(380.0).show(ctx, |ui| {
+            egui::ScrollArea::vertical().max_height(420.0).show(ui, |ui| {
+                for (i, msg) in std::iter::once(&thread.root).chain(&thread.replies).enumerate() {
+                    Frame::none()
+                        .inner_margin(Margin::same(8.0))
+                        .rounding(Rounding::same(6.0))
+                        .fill(ui.style().visuals.widgets.noninteractive.bg_fill)
+                        .show(ui, |ui| {
+                            ui.set_width(ui.available_width());
+                            ui.label(egui::RichText::new(msg.sender_name()).small().strong().color(Color32::GRAY));
+                            if msg.deleted {
+                                ui.label(egui::RichText::new("🗑 Messaggio eliminato").italics().color(Color32::GRAY));
+                            } else {
+                                ui.label(&msg.content);
+                            }
+                            for attachment in &msg.attachments {
+                                ui.label(egui::RichText::new(format!("📄 {}", attachment.file_name)).small());
+                            }
+                        });
+                    if i == 0 {
+                        ui.separator();
+                        if thread.replies.is_empty() {
+                            ui.label("Nessuna risposta.");
+                        }
+                    }
+                    ui.add_space(4.0);
+                }
+            });
+            let can_reply = self.selected_group_id == Some(*group_id) && !thread.root.deleted;
+            if can_reply && ui.button("↩ Rispondi").clicked() {
+                reply_to = Some(thread.root.preview());
+            }
+        });
+        if reply_to.is_some() {
+            self.editing_message_id = None;
+            self.replying_to = reply_to;
+        }
+        if !open {
+            self.open_thread = None;
+        }
+    }
+
+    /// Dimentica utente, token e dati di chat: riporta l'app alla schermata di login.
+    fn reset_session_state(&mut self) {
+        self.current_user = None;
+        self.auth_token = None;
+        self.user_groups.clear();
+        self.direct_conversations.clear();
+        self.draft_direct_peer = None;
+        self.selected_group_id = None;
+        self.selected_group_members = None;
+        self.messages.clear();
+        self.history_has_more.clear();
+        self.loading_older.clear();
+        self.scroll_anchor = None;
+        self.search = SearchState::default();
+        self.mentions = MentionsState::default();
+        self.invite_links = InviteLinksState::default();
+        self.group_profile = GroupProfileState::default();
+        self.pending_confirmation = None;
+        self.my_profile = MyProfileState::default();
+        self.profile_card = None;
+        self.issued_reset_token = None;
+        self.is_admin = false;
+        self.directory = DirectoryState::default();
+        self.join_requests.clear();
+        self.invite_code_input.clear();
+        self.replying_to = None;
+        self.open_thread = None;
+        self.jump_target = None;
+        self.highlighted_message = None;
+        self.attachment_images.clear();
+        self.requested_images.clear();
+        self.avatars.clear();
+        self.requested_avatars.clear();
+        self.unread_counts.clear();
+        self.marked_read.clear();
+        self.typing_users.clear();
+        self.presence.clear();
+        self.away = false;
+        self.connection_status = ConnectionStatus::Connecting;
+        self.pending_invitations.clear();
+    }
+
+    fn draw_auth_view(&mut self, ctx: &egui::Context) {
+        egui::CentralPanel::default().show(ctx, |ui| {
+            ui.with_layout(Layout::top_down(Align::Center), |ui| {
+                ui.add_space(ui.available_height() * 0.2);
+                ui.heading("Benvenuto in Ruggine");
+                ui.add_space(20.0);
+                Frame::none().inner_margin(Margin::same(20.0)).fill(ui.style().visuals.widgets.noninteractive.bg_fill).rounding(Rounding::same(8.0)).show(ui, |ui| {
+                    ui.set_width(300.0);
+                    ui.vertical_centered_justified(|ui| {
+                        ui.horizontal(|ui| {
+                            ui.selectable_value(&mut self.auth_state, AuthState::Login, "Login");
+                            ui.selectable_value(&mut self.auth_state, AuthState::Register, "Registrati");
+                            ui.selectable_value(&mut self.auth_state, AuthState::ResetPassword, "Password dimenticata");
+                        });
+                        ui.add_space(15.0);
+                        if self.auth_state == AuthState::ResetPassword {
+                            ui.label("Token di reset");
+                            ui.add(egui::TextEdit::singleline(&mut self.reset_token_input).hint_text("Ricevuto da un amministratore"));
+                            ui.add_space(10.0);
+                            ui.label("Nuova password");
+                            ui.add(egui::TextEdit::singleline(&mut self.password_input).password(true));
+                            ui.add_space(20.0);
+                            if ui.button("Reimposta password").clicked() {
+                                let action = ToBackend::ResetPassword(self.reset_token_input.clone(), self.password_input.clone());
+                                let _ = self.to_backend_tx.try_send(action);
+                            }
+                            return;
+                        }
+                        ui.label("Username");
+                        ui.text_edit_singleline(&mut self.username_input);
+                        ui.add_space(10.0);
+                        ui.label("Password");
+                        ui.add(egui::TextEdit::singleline(&mut self.password_input).password(true));
+                        ui.add_space(20.0);
+                        let button_text = if self.auth_state == AuthState::Login { "Login" } else { "Registrati" };
+                        if ui.button(button_text).clicked() {
+                            let action = if self.auth_state == AuthState::Login {
+                                ToBackend::Login(self.username_input.clone(), self.password_input.clone())
+                            } else {
+                                ToBackend::Register(self.username_input.clone(), self.password_input.clone())
+                            };
+                            let _ = self.to_backend_tx.try_send(action);
+                        }
+                    });
+                });
+                self.draw_info_error_messages(ui);
+            });
+        });
+    }
+
+    fn draw_main_view(&mut self, ctx: &egui::Context) {
+        egui::SidePanel::left("side_panel").min_width(250.0).default_width(250.0).show(ctx, |ui| {
             ui.with_layout(Layout::top_down_justified(Align::LEFT), |ui| {
                 ui.add_space(10.0);
                 ui.horizontal(|ui| {
-                    ui.heading(format!("Ciao, {}!", self.current_user.as_ref().unwrap().username));
+                    ui.heading(format!("Ciao, {}!", self.current_user.as_ref().unwrap().name()));
                     ui.with_layout(Layout::right_to_left(Align::Center), |ui| {
                         if ui.button("🚪 Logout").on_hover_text("Esci dall'account").clicked() {
-                            self.current_user = None;
-                            self.auth_token = None;
-                            self.user_groups.clear();
-                            self.selected_group_id = None;
-                            self.messages.clear();
-                            self.pending_invitations.clear();
+                            let _ = self.to_backend_tx.try_send(ToBackend::Logout);
+                            self.reset_session_state();
+                        }
+                        if ui.button("👤").on_hover_text("Il tuo profilo").clicked() {
+                            self.open_my_profile();
                         }
                     });
                 });
+                match self.connection_status {
+                    ConnectionStatus::Connected => {
+                        ui.colored_label(Color32::from_rgb(80, 200, 120), "● Connesso");
+                    }
+                    ConnectionStatus::Connecting => {
+                        ui.colored_label(Color32::GRAY, "● Connessione in corso…");
+                    }
+                    ConnectionStatus::Reconnecting { attempt, retry_in } => {
+                        ui.colored_label(
+                            Color32::from_rgb(230, 160, 60),
+                            format!("● Connessione persa, nuovo tentativo ({}) tra {}s…", attempt, retry_in.as_secs()),
+                        );
+                    }
+                }
                 ui.add_space(20.0);
                 
                 // Sezione per la creazione di un nuovo gruppo
                 Frame::none().inner_margin(Margin::symmetric(10.0, 15.0)).show(ui, |ui| {
                     ui.label("Crea un nuovo Gruppo");
                     ui.text_edit_singleline(&mut self.create_group_input);
-                    if ui.button("➕ Crea").clicked() {
-                        if !self.create_group_input.is_empty() {
-                           let _ = self.to_backend_tx.try_send(ToBackend::CreateGroup(self.create_group_input.clone()));
-                           self.create_group_input.clear();
+                    ui.horizontal(|ui| {
+                        ui.checkbox(&mut self.create_group_public, "Pubblico").on_hover_text("Compare nell'elenco dei gruppi e chiunque può entrare");
+                        if ui.button("➕ Crea").clicked() && !self.create_group_input.is_empty() {
+                            let visibility = if self.create_group_public { GroupVisibility::Public } else { GroupVisibility::Private };
+                            let _ = self.to_backend_tx.try_send(ToBackend::CreateGroup(self.create_group_input.clone(), visibility));
+                            self.create_group_input.clear();
+                        }
+                    });
+                    if ui.button("🌐 Esplora gruppi").clicked() {
+                        self.open_directory();
+                    }
+                });
+
+                ui.separator();
+
+                // Ricerca nei messaggi di tutti i gruppi (o solo di quello aperto)
+                Frame::none().inner_margin(Margin::symmetric(10.0, 10.0)).show(ui, |ui| {
+                    ui.horizontal(|ui| {
+                        let response = ui.add(egui::TextEdit::singleline(&mut self.search.input).hint_text("Cerca nei messaggi…").desired_width(170.0));
+                        let submitted = response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+                        if ui.button("🔍").clicked() || submitted {
+                            self.start_search();
                         }
+                    });
+                    ui.checkbox(&mut self.search.only_current_group, "Solo nel gruppo aperto");
+                    let label = match self.mentions.unread {
+                        0 => "@ Menzioni".to_string(),
+                        n => format!("@ Menzioni ({})", n),
+                    };
+                    if ui.button(label).clicked() {
+                        self.open_mentions();
                     }
                 });
 
@@ -459,11 +2228,11 @@ impl RuggineApp {
                 // Modifica qui: usa `ui.push_id` per creare un contesto con ID univoco per lo ScrollArea
                 ui.push_id("my_groups_scroll_area", |ui| {
                     egui::ScrollArea::vertical().show(ui, |ui| {
+                        let my_role = self.my_role();
                         for group in self.user_groups.clone() {
                             let is_selected = self.selected_group_id == Some(group.id);
-                            if ui.selectable_value(&mut self.selected_group_id, Some(group.id), format!("# {}", group.name)).clicked() {
-                                self.to_backend_tx.try_send(ToBackend::FetchGroupMessages(group.id)).ok();
-                                self.to_backend_tx.try_send(ToBackend::FetchGroupMembers(group.id)).ok();
+                            if ui.selectable_label(is_selected, self.with_unread_badge(group.id, format!("# {}", group.name))).clicked() {
+                                self.open_group(group.id);
                             }
                             if is_selected {
                                 ui.add_space(5.0);
@@ -472,14 +2241,35 @@ impl RuggineApp {
                                     if ui.button("❌ Esci").clicked() {
                                         let _ = self.to_backend_tx.try_send(ToBackend::LeaveGroup(group.id));
                                     }
-                                    ui.add_space(10.0);
-                                    ui.label("Invita:");
-                                    ui.text_edit_singleline(&mut self.invite_user_input);
-                                    if ui.button("✉ Invia Invito").clicked() {
-                                        if !self.invite_user_input.is_empty() {
+                                    if ui.button("ℹ").on_hover_text("Profilo del gruppo").clicked() {
+                                        self.open_group_profile(group.id);
+                                    }
+                                    if my_role == Some(GroupRole::Owner) && ui.button("🗑 Elimina").on_hover_text("Elimina il gruppo per tutti").clicked() {
+                                        self.pending_confirmation = Some(PendingConfirmation::DeleteGroup {
+                                            group_id: group.id,
+                                            name: group.name.clone(),
+                                            typed_name: String::new(),
+                                        });
+                                    }
+                                    // Solo admin e proprietario possono invitare
+                                    if my_role >= Some(GroupRole::Admin) {
+                                        ui.add_space(10.0);
+                                        ui.label("Invita:");
+                                        ui.text_edit_singleline(&mut self.invite_user_input);
+                                        if ui.button("✉ Invia Invito").clicked() && !self.invite_user_input.is_empty() {
                                             self.to_backend_tx.try_send(ToBackend::InviteUser(group.id, self.invite_user_input.clone())).ok();
                                             self.invite_user_input.clear();
                                         }
+                                        if ui.button("🔗").on_hover_text("Link di invito").clicked() {
+                                            self.open_invite_links(group.id);
+                                        }
+                                        let (icon, hover, other) = match group.visibility {
+                                            GroupVisibility::Public => ("🌐", "Pubblico: rendi privato", GroupVisibility::Private),
+                                            GroupVisibility::Private => ("🔒", "Privato: rendi pubblico", GroupVisibility::Public),
+                                        };
+                                        if ui.button(icon).on_hover_text(hover).clicked() {
+                                            self.to_backend_tx.try_send(ToBackend::SetGroupVisibility(group.id, other)).ok();
+                                        }
                                     }
                                 });
                             }
@@ -489,6 +2279,42 @@ impl RuggineApp {
                 
                 ui.separator();
 
+                // Conversazioni dirette: ne basta lo username, nessun invito
+                ui.heading("Messaggi Diretti");
+                ui.horizontal(|ui| {
+                    ui.add(egui::TextEdit::singleline(&mut self.direct_peer_input).hint_text("Username").desired_width(150.0));
+                    if ui.button("💬 Scrivi").clicked() && !self.direct_peer_input.trim().is_empty() {
+                        let username = self.direct_peer_input.trim().to_string();
+                        match self.direct_conversations.iter().find(|c| c.peer_username == username) {
+                            Some(conversation) => {
+                                let id = conversation.id;
+                                self.open_group(id);
+                            }
+                            None => {
+                                self.to_backend_tx.try_send(ToBackend::StartDirectConversation(username)).ok();
+                            }
+                        }
+                        self.direct_peer_input.clear();
+                    }
+                });
+                ui.push_id("direct_conversations_scroll_area", |ui| {
+                    egui::ScrollArea::vertical().max_height(120.0).show(ui, |ui| {
+                        for conversation in self.direct_conversations.clone() {
+                            let is_selected = self.selected_group_id == Some(conversation.id);
+                            let dot = self.presence_dot(conversation.peer_id);
+                            let label = self.with_unread_badge(conversation.id, format!("{} @ {}", dot, conversation.peer_username));
+                            if ui.selectable_label(is_selected, label).clicked() {
+                                self.open_group(conversation.id);
+                            }
+                        }
+                        if let Some(peer) = &self.draft_direct_peer {
+                            let _ = ui.selectable_label(true, format!("✏ @ {}", peer.username));
+                        }
+                    });
+                });
+
+                ui.separator();
+
                 // Sezione: Lista membri del gruppo selezionato
                 if let Some(selected_id) = self.selected_group_id {
                     if let Some(group) = self.user_groups.iter().find(|g| g.id == selected_id) {
@@ -497,11 +2323,75 @@ impl RuggineApp {
                         // For simplicity, we fetch every time the group changes
                         // You may want to cache this in a real app
                         if let Some(members) = self.selected_group_members.clone(){
+                        let my_role = self.my_role();
                         egui::ScrollArea::vertical().max_height(120.0).show(ui, |ui| {
                             for member in members {
-                                ui.label(format!("• {}", member.username));
-                            }                            
+                                ui.horizontal(|ui| {
+                                    let badge = match member.role {
+                                        GroupRole::Owner => " 👑",
+                                        GroupRole::Admin => " ⭐",
+                                        GroupRole::Member => "",
+                                    };
+                                    let text = format!("{} {}{}", self.presence_dot(member.id), member.name(), badge);
+                                    let label = ui.add(egui::Label::new(text).sense(egui::Sense::click()));
+                                    let presence = match (self.presence.get(&member.id).copied().unwrap_or(member.presence), member.last_seen_at) {
+                                        (PresenceStatus::Online, _) => "Online".to_string(),
+                                        (PresenceStatus::Away, _) => "Assente".to_string(),
+                                        (PresenceStatus::Offline, Some(at)) => format!("Offline, visto {}", format_last_seen(at)),
+                                        (PresenceStatus::Offline, None) => "Offline".to_string(),
+                                    };
+                                    if label.on_hover_text(format!("@{} · {}", member.username, presence)).clicked() {
+                                        self.open_profile_card(member.id);
+                                    }
+                                    if let Some(status) = &member.status_message {
+                                        ui.label(egui::RichText::new(status).small().italics().color(Color32::GRAY));
+                                    }
+                                    // Il proprietario può promuovere o retrocedere gli altri membri
+                                    if my_role == Some(GroupRole::Owner) && member.role != GroupRole::Owner {
+                                        let (label, new_role) = if member.role == GroupRole::Admin {
+                                            ("⬇", GroupRole::Member)
+                                        } else {
+                                            ("⬆", GroupRole::Admin)
+                                        };
+                                        let hover = if new_role == GroupRole::Admin { "Rendi admin" } else { "Rendi membro" };
+                                        if ui.small_button(label).on_hover_text(hover).clicked() {
+                                            self.to_backend_tx.try_send(ToBackend::UpdateMemberRole(selected_id, member.id, new_role)).ok();
+                                        }
+                                        if ui.small_button("👑").on_hover_text("Cedi la proprietà").clicked() {
+                                            self.pending_confirmation = Some(PendingConfirmation::TransferOwnership {
+                                                group_id: selected_id,
+                                                member_id: member.id,
+                                                username: member.username.clone(),
+                                            });
+                                        }
+                                    }
+                                    // Admin e proprietario possono rimuovere chi ha un ruolo inferiore
+                                    if my_role.is_some_and(|role| role >= GroupRole::Admin && member.role < role) {
+                                        if ui.small_button("👢").on_hover_text("Rimuovi dal gruppo").clicked() {
+                                            self.to_backend_tx.try_send(ToBackend::KickMember(selected_id, member.id)).ok();
+                                        }
+                                        if ui.small_button("🚫").on_hover_text("Banna dal gruppo").clicked() {
+                                            self.to_backend_tx.try_send(ToBackend::BanMember(selected_id, member.id)).ok();
+                                        }
+                                    }
+                                });
+                            }
                         });
+                        // Richieste di ingresso in attesa, caricate solo per admin e proprietario
+                        if !self.join_requests.is_empty() {
+                            ui.label(egui::RichText::new(format!("Richieste di ingresso ({})", self.join_requests.len())).strong());
+                            for request in self.join_requests.clone() {
+                                ui.horizontal(|ui| {
+                                    ui.label(&request.username);
+                                    if ui.small_button("✅").on_hover_text("Approva").clicked() {
+                                        self.to_backend_tx.try_send(ToBackend::AnswerJoinRequest(selected_id, request.id, true)).ok();
+                                    }
+                                    if ui.small_button("❌").on_hover_text("Rifiuta").clicked() {
+                                        self.to_backend_tx.try_send(ToBackend::AnswerJoinRequest(selected_id, request.id, false)).ok();
+                                    }
+                                });
+                            }
+                        }
                     }
                 }
                 else{
@@ -513,35 +2403,176 @@ impl RuggineApp {
             });
         });
 
+        self.draw_search_results(ctx);
+        self.draw_mentions(ctx);
+        self.draw_invite_links(ctx);
+        self.draw_group_profile(ctx);
+        self.draw_confirmation(ctx);
+        self.draw_my_profile(ctx);
+        self.draw_profile_card(ctx);
+        self.draw_directory(ctx);
+        self.draw_thread(ctx);
+
         if let Some(selected_id) = self.selected_group_id {
-            let selected_group = self.user_groups.iter().find(|g| g.id == selected_id).cloned();
-            if let Some(group) = selected_group {
+            if let Some(title) = self.chat_title(selected_id) {
+                let typing_names = self.typing_names(selected_id);
                 egui::TopBottomPanel::bottom("chat_input_panel").resizable(false).min_height(40.0).show(ctx, |ui| {
                     ui.separator();
+                    if self.editing_message_id.is_some() {
+                        ui.horizontal(|ui| {
+                            ui.label(egui::RichText::new("✏ Modifica del messaggio").italics().color(egui::Color32::GRAY));
+                            if ui.small_button("Annulla").clicked() || ui.input(|i| i.key_pressed(egui::Key::Escape)) {
+                                self.editing_message_id = None;
+                                self.chat_message_input.clear();
+                            }
+                        });
+                    } else if let Some(reply) = &self.replying_to {
+                        let mut cancel = false;
+                        ui.horizontal(|ui| {
+                            ui.label(egui::RichText::new(format!("↩ Risposta a {}: {}", reply.sender_username, reply.content)).italics().color(egui::Color32::GRAY));
+                            cancel = ui.small_button("Annulla").clicked() || ui.input(|i| i.key_pressed(egui::Key::Escape));
+                        });
+                        if cancel {
+                            self.replying_to = None;
+                        }
+                    }
                     ui.with_layout(Layout::left_to_right(Align::Center), |ui| {
-                        let text_edit_response = ui.add_sized(ui.available_size(), egui::TextEdit::singleline(&mut self.chat_message_input).hint_text(format!("Messaggio in #{}", group.name)).frame(false));
+                        // Il testo già scritto accompagna il file come didascalia
+                        if self.editing_message_id.is_none() && ui.button("📎").on_hover_text("Allega un file").clicked() {
+                            let reply_to = self.replying_to.take().map(|r| r.message_id);
+                            self.to_backend_tx.try_send(ToBackend::SendAttachment(selected_id, self.chat_message_input.clone(), reply_to)).ok();
+                            self.chat_message_input.clear();
+                        }
+                        let text_edit_response = ui.add_sized(ui.available_size(), egui::TextEdit::singleline(&mut self.chat_message_input).hint_text(format!("Messaggio in {}", title)).frame(false));
+                        if text_edit_response.changed() && !self.chat_message_input.is_empty() && self.last_typing_sent.elapsed() > TYPING_SEND_INTERVAL {
+                            self.to_backend_tx.try_send(ToBackend::SendTyping(selected_id)).ok();
+                            self.last_typing_sent = Instant::now();
+                        }
                         if text_edit_response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter)) && !self.chat_message_input.is_empty() {
                             if let Some(group_id) = self.selected_group_id {
-                                let _ = self.to_backend_tx.try_send(ToBackend::SendMessage(group_id, self.chat_message_input.clone()));
+                                let action = match self.editing_message_id.take() {
+                                    Some(message_id) => ToBackend::EditMessage(group_id, message_id, self.chat_message_input.clone()),
+                                    None => {
+                                        let reply_to = self.replying_to.take().map(|r| r.message_id);
+                                        ToBackend::SendMessage(group_id, self.chat_message_input.clone(), reply_to)
+                                    }
+                                };
+                                let _ = self.to_backend_tx.try_send(action);
                             }
                             self.chat_message_input.clear();
                             text_edit_response.request_focus();
                         }
                     });
+                    if !typing_names.is_empty() {
+                        let verb = if typing_names.len() == 1 { "sta scrivendo…" } else { "stanno scrivendo…" };
+                        ui.label(egui::RichText::new(format!("{} {}", typing_names.join(", "), verb)).italics().small().color(egui::Color32::GRAY));
+                    }
                 });
+                let (avatar, topic) = self.group_header(selected_id);
                 egui::CentralPanel::default().show(ctx, |ui| {
-                    ui.with_layout(Layout::top_down(Align::Center), |ui| { ui.heading(format!("# {}", group.name)); });
+                    ui.with_layout(Layout::top_down(Align::Center), |ui| {
+                        if let Some(avatar) = avatar {
+                            ui.add(avatar.max_size(Vec2::splat(48.0)).rounding(Rounding::same(24.0)));
+                        }
+                        ui.heading(&title);
+                        if let Some(topic) = topic {
+                            ui.label(egui::RichText::new(topic).italics().color(Color32::GRAY));
+                        }
+                    });
                     ui.separator();
-                    egui::ScrollArea::vertical().stick_to_bottom(true).auto_shrink([false; 2]).show(ui, |ui| {
+                    let mut bubble_actions = Vec::new();
+                    let has_more = self.history_has_more.get(&selected_id).copied().unwrap_or(false);
+                    let scroll = egui::ScrollArea::vertical().id_source(selected_id).stick_to_bottom(true).auto_shrink([false; 2]).show(ui, |ui| {
                         ui.with_layout(Layout::top_down(Align::LEFT), |ui| {
                             ui.add_space(10.0);
-                            if let Some(messages) = self.messages.get(&selected_id) {
-                                for msg in messages { self.draw_message_bubble(ui, msg); }
+                            if self.loading_older.contains(&selected_id) {
+                                ui.vertical_centered(|ui| ui.spinner());
+                            } else if !has_more && self.messages.contains_key(&selected_id) {
+                                Self::draw_notice(ui, "Inizio della conversazione.");
+                            }
+                            let can_moderate = self.my_role() >= Some(GroupRole::Admin);
+                            if let Some(items) = self.messages.get(&selected_id) {
+                                let seen_by = self.seen_by(items);
+                                let mut reply_counts: HashMap<Uuid, usize> = HashMap::new();
+                                for item in items {
+                                    if let ChatItem::Message(WsServerMessage { reply_to: Some(reply), .. }) = item {
+                                        *reply_counts.entry(reply.message_id).or_default() += 1;
+                                    }
+                                }
+                                for item in items {
+                                    match item {
+                                        ChatItem::Message(msg) => {
+                                            if self.scroll_anchor == Some(msg.id) {
+                                                let top = ui.cursor().top();
+                                                let rect = egui::Rect::from_x_y_ranges(ui.max_rect().x_range(), top..=top + 1.0);
+                                                ui.scroll_to_rect(rect, Some(Align::TOP));
+                                            }
+                                            let reply_count = reply_counts.get(&msg.id).copied().unwrap_or(0);
+                                            bubble_actions.extend(self.draw_message_bubble(ui, msg, can_moderate, reply_count));
+                                            if let Some((_, names)) = seen_by.as_ref().filter(|(id, _)| *id == msg.id) {
+                                                ui.with_layout(Layout::right_to_left(Align::TOP), |ui| {
+                                                    ui.label(egui::RichText::new(format!("✓ Visto da {}", names.join(", "))).small().color(Color32::GRAY));
+                                                });
+                                            }
+                                        }
+                                        ChatItem::Notice(text) => Self::draw_notice(ui, text),
+                                    }
+                                }
                             }
                         });
                     });
+                    self.scroll_anchor = None;
+                    // Arrivati in cima alla chat carichiamo la pagina precedente della cronologia
+                    if scroll.state.offset.y <= 0.0 && has_more && !self.loading_older.contains(&selected_id) {
+                        let oldest = self.messages.get(&selected_id).and_then(|items| {
+                            items.iter().find_map(|item| match item {
+                                ChatItem::Message(m) => Some(m.id),
+                                ChatItem::Notice(_) => None,
+                            })
+                        });
+                        if let Some(before) = oldest {
+                            self.loading_older.insert(selected_id);
+                            self.to_backend_tx.try_send(ToBackend::FetchOlderMessages(selected_id, before)).ok();
+                        }
+                    }
+                    for action in bubble_actions {
+                        match action {
+                            BubbleAction::Edit(message_id, content) => {
+                                self.editing_message_id = Some(message_id);
+                                self.chat_message_input = content;
+                            }
+                            BubbleAction::Delete(message_id) => {
+                                self.to_backend_tx.try_send(ToBackend::DeleteMessage(selected_id, message_id)).ok();
+                            }
+                            BubbleAction::Download(attachment) => {
+                                self.to_backend_tx.try_send(ToBackend::DownloadAttachment(selected_id, attachment)).ok();
+                            }
+                            BubbleAction::LoadImage(attachment_id) => {
+                                if self.requested_images.insert(attachment_id) {
+                                    self.to_backend_tx.try_send(ToBackend::FetchAttachment(selected_id, attachment_id)).ok();
+                                }
+                            }
+                            BubbleAction::React(message_id, emoji, add) => {
+                                self.to_backend_tx.try_send(ToBackend::SetReaction(selected_id, message_id, emoji, add)).ok();
+                            }
+                            BubbleAction::Reply(preview) => {
+                                self.editing_message_id = None;
+                                self.replying_to = Some(preview);
+                            }
+                            BubbleAction::OpenThread(message_id) => {
+                                self.to_backend_tx.try_send(ToBackend::FetchThread(selected_id, message_id)).ok();
+                            }
+                            BubbleAction::JumpTo(message_id) => {
+                                self.jump_target = Some((selected_id, message_id));
+                                self.continue_jump();
+                            }
+                            BubbleAction::ShowProfile(user_id) => self.open_profile_card(user_id),
+                        }
+                    }
                 });
             }
+        } else if let Some(peer) = self.draft_direct_peer.clone() {
+            self.draw_draft_conversation(ctx, &peer);
         } else {
             egui::CentralPanel::default().show(ctx, |ui| {
                 ui.centered_and_justified(|ui| {
@@ -551,10 +2582,38 @@ impl RuggineApp {
         }
     }
 
+    /// Chat con un utente con cui non c'è ancora una conversazione: il primo messaggio la crea.
+    fn draw_draft_conversation(&mut self, ctx: &egui::Context, peer: &User) {
+        egui::TopBottomPanel::bottom("chat_input_panel").resizable(false).min_height(40.0).show(ctx, |ui| {
+            ui.separator();
+            ui.with_layout(Layout::left_to_right(Align::Center), |ui| {
+                let text_edit_response = ui.add_sized(ui.available_size(), egui::TextEdit::singleline(&mut self.chat_message_input).hint_text(format!("Messaggio a @{}", peer.username)).frame(false));
+                if text_edit_response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter)) && !self.chat_message_input.is_empty() {
+                    self.to_backend_tx.try_send(ToBackend::SendDirectMessage(peer.id, self.chat_message_input.clone())).ok();
+                    self.chat_message_input.clear();
+                    text_edit_response.request_focus();
+                }
+            });
+        });
+        egui::CentralPanel::default().show(ctx, |ui| {
+            ui.with_layout(Layout::top_down(Align::Center), |ui| { ui.heading(format!("@ {}", peer.username)); });
+            ui.separator();
+            Self::draw_notice(ui, "Inizio della conversazione.");
+        });
+    }
+
     fn draw_invitations_section(&mut self, ui: &mut egui::Ui) {
         ui.add_space(10.0);
         ui.heading("Inviti Pendenti");
         ui.add_space(5.0);
+        ui.horizontal(|ui| {
+            ui.add(egui::TextEdit::singleline(&mut self.invite_code_input).hint_text("Codice di invito").desired_width(150.0));
+            if ui.button("🔗 Entra").clicked() && !self.invite_code_input.trim().is_empty() {
+                self.to_backend_tx.try_send(ToBackend::JoinWithInviteCode(self.invite_code_input.trim().to_string())).ok();
+                self.invite_code_input.clear();
+            }
+        });
+        ui.add_space(5.0);
         if self.pending_invitations.is_empty() {
             ui.label("Nessun invito.");
         } else {
@@ -581,39 +2640,159 @@ impl RuggineApp {
         }
     }
 
-    fn draw_message_bubble(&self, ui: &mut egui::Ui, msg: &WsServerMessage) {
-        if msg.sender_id.is_nil() {
-            ui.add_space(4.0);
-            ui.with_layout(Layout::top_down(Align::Center), |ui| {
-                ui.label(
-                    egui::RichText::new(&msg.content)
-                        .italics()
-                        .color(egui::Color32::GRAY),
-                );
-            });
-            ui.add_space(4.0);
-            return;
-        }
+    fn draw_notice(ui: &mut egui::Ui, text: &str) {
+        ui.add_space(4.0);
+        ui.with_layout(Layout::top_down(Align::Center), |ui| {
+            ui.label(
+                egui::RichText::new(text)
+                    .italics()
+                    .color(egui::Color32::GRAY),
+            );
+        });
+        ui.add_space(4.0);
+    }
 
-        let is_my_message = self.current_user.as_ref().unwrap().id == msg.sender_id;
+    fn draw_message_bubble(&self, ui: &mut egui::Ui, msg: &WsServerMessage, can_moderate: bool, reply_count: usize) -> Vec<BubbleAction> {
+        let me = self.current_user.as_ref().unwrap();
+        let my_id = me.id;
+        let is_my_message = my_id == msg.sender_id;
+        let mentions_me = !is_my_message && find_mentions(&msg.content).iter().any(|(_, name)| *name == me.username);
+        let mut actions = Vec::new();
         let layout = if is_my_message { Layout::right_to_left(Align::TOP) } else { Layout::left_to_right(Align::TOP) };
         
         ui.with_layout(layout, |ui| {
+             let highlighted = self.highlighted_message.is_some_and(|(id, at)| id == msg.id && at.elapsed() < HIGHLIGHT_DURATION);
+             if highlighted {
+                 ui.ctx().request_repaint_after(HIGHLIGHT_DURATION);
+             }
              Frame::none()
                 .inner_margin(Margin::symmetric(12.0, 8.0))
+                .stroke(if highlighted {
+                    Stroke::new(2.0, Color32::from_rgb(238, 212, 159))
+                } else if mentions_me {
+                    Stroke::new(1.0, Color32::from_rgb(235, 203, 139))
+                } else {
+                    Stroke::NONE
+                })
                 .rounding(Rounding { nw: 12.0, ne: 12.0, sw: if is_my_message { 2.0 } else { 12.0 }, se: if is_my_message { 12.0 } else { 2.0 } })
                 .fill(if is_my_message { egui::Color32::from_rgb(136, 192, 208) } else { ui.style().visuals.widgets.noninteractive.bg_fill })
                 .show(ui, |ui| {
                     ui.set_max_width(ui.available_width() * 0.7);
                     ui.with_layout(Layout::top_down(Align::LEFT), |ui| {
                         if !is_my_message {
-                             ui.label(egui::RichText::new(&msg.sender_username).strong().color(egui::Color32::from_rgb(202, 211, 245)));
+                             let sender = ui.add(egui::Label::new(egui::RichText::new(msg.sender_name()).strong().color(egui::Color32::from_rgb(202, 211, 245))).sense(egui::Sense::click()));
+                             if sender.on_hover_text(format!("@{}", msg.sender_username)).clicked() {
+                                 actions.push(BubbleAction::ShowProfile(msg.sender_id));
+                             }
+                        }
+                        let text_color = if is_my_message { egui::Color32::from_gray(10) } else { egui::Color32::from_gray(220) };
+                        if let Some(reply) = &msg.reply_to {
+                            if Self::draw_quote(ui, reply, text_color).clicked() {
+                                actions.push(BubbleAction::JumpTo(reply.message_id));
+                            }
+                        }
+                        if msg.deleted {
+                            ui.label(egui::RichText::new("🗑 Messaggio eliminato").italics().color(egui::Color32::GRAY).size(15.0));
+                            return;
+                        }
+                        if !msg.content.is_empty() {
+                            ui.label(mention_text(&msg.content, &me.username, text_color));
+                        }
+                        for attachment in &msg.attachments {
+                            if let Some(action) = self.draw_attachment(ui, attachment, text_color) {
+                                actions.push(action);
+                            }
                         }
-                        ui.label(egui::RichText::new(&msg.content).color(if is_my_message { egui::Color32::from_gray(10) } else { egui::Color32::from_gray(220) }).size(15.0));
+                        if !msg.reactions.is_empty() {
+                            ui.horizontal_wrapped(|ui| {
+                                for reaction in &msg.reactions {
+                                    let mine = reaction.user_ids.contains(&my_id);
+                                    let chip = egui::RichText::new(format!("{} {}", reaction.emoji, reaction.count)).color(text_color);
+                                    if ui.selectable_label(mine, chip).clicked() {
+                                        actions.push(BubbleAction::React(msg.id, reaction.emoji.clone(), !mine));
+                                    }
+                                }
+                            });
+                        }
+                        ui.horizontal(|ui| {
+                            ui.menu_button("☺", |ui| {
+                                ui.horizontal(|ui| {
+                                    for emoji in QUICK_REACTIONS {
+                                        if ui.button(egui::RichText::new(*emoji).size(18.0)).clicked() {
+                                            let mine = msg.reactions.iter().any(|r| r.emoji == *emoji && r.user_ids.contains(&my_id));
+                                            actions.push(BubbleAction::React(msg.id, emoji.to_string(), !mine));
+                                            ui.close_menu();
+                                        }
+                                    }
+                                });
+                            })
+                            .response
+                            .on_hover_text("Reagisci");
+                            if ui.small_button("↩").on_hover_text("Rispondi").clicked() {
+                                actions.push(BubbleAction::Reply(msg.preview()));
+                            }
+                            if reply_count > 0 && ui.small_button(format!("🧵 {}", reply_count)).on_hover_text("Apri la discussione").clicked() {
+                                actions.push(BubbleAction::OpenThread(msg.id));
+                            }
+                            if msg.edited_at.is_some() {
+                                ui.label(egui::RichText::new("(modificato)").small().color(text_color));
+                            }
+                            if (is_my_message || can_moderate) && ui.small_button("✏").on_hover_text("Modifica").clicked() {
+                                actions.push(BubbleAction::Edit(msg.id, msg.content.clone()));
+                            }
+                            if (is_my_message || can_moderate) && ui.small_button("🗑").on_hover_text("Elimina").clicked() {
+                                actions.push(BubbleAction::Delete(msg.id));
+                            }
+                        });
                     });
                 });
-        });
-        ui.add_space(4.0);
+        });
+        ui.add_space(4.0);
+        actions
+    }
+
+    /// Citazione del messaggio a cui si risponde, sopra il testo della risposta.
+    fn draw_quote(ui: &mut egui::Ui, reply: &ReplyPreview, text_color: Color32) -> egui::Response {
+        let text = if reply.deleted { "Messaggio eliminato".to_string() } else { reply.content.clone() };
+        Frame::none()
+            .inner_margin(Margin::symmetric(6.0, 2.0))
+            .stroke(Stroke::new(1.0, text_color.gamma_multiply(0.5)))
+            .rounding(Rounding::same(4.0))
+            .show(ui, |ui| {
+                ui.label(egui::RichText::new(format!("↩ {}", reply.sender_username)).small().strong().color(text_color));
+                ui.label(egui::RichText::new(text).small().italics().color(text_color));
+            })
+            .response
+            .interact(egui::Sense::click())
+            .on_hover_text("Mostra il messaggio originale")
+    }
+
+    /// Anteprima per le immagini, pulsante di download per gli altri file.
+    fn draw_attachment(&self, ui: &mut egui::Ui, attachment: &Attachment, text_color: Color32) -> Option<BubbleAction> {
+        let label = format!("📄 {} ({})", attachment.file_name, format_size(attachment.size));
+        if !attachment.is_image() {
+            let clicked = ui.button(egui::RichText::new(label).color(text_color)).on_hover_text("Scarica").clicked();
+            return clicked.then(|| BubbleAction::Download(attachment.clone()));
+        }
+        match self.attachment_images.get(&attachment.id) {
+            Some(bytes) => {
+                let image = egui::Image::from_bytes(format!("bytes://attachment/{}", attachment.id), bytes.clone())
+                    .max_size(Vec2::new(240.0, 180.0))
+                    .rounding(Rounding::same(6.0))
+                    .sense(egui::Sense::click());
+                let clicked = ui.add(image).on_hover_text(format!("{} — clic per salvare", attachment.file_name)).clicked();
+                clicked.then(|| BubbleAction::Download(attachment.clone()))
+            }
+            // Scaricata la prima volta che la bolla viene disegnata
+            None if !self.requested_images.contains(&attachment.id) => Some(BubbleAction::LoadImage(attachment.id)),
+            None => {
+                ui.horizontal(|ui| {
+                    ui.spinner();
+                    ui.label(egui::RichText::new(label).small().color(text_color));
+                });
+                None
+            }
+        }
     }
 
     fn draw_info_error_messages(&self, ui: &mut egui::Ui) {
@@ -669,37 +2848,57 @@ fn configure_styles(ctx: &egui::Context) {
 
 
 // --- Network Logic ---
+
+/// Estrae il messaggio da una risposta di errore del server, con un testo di riserva
+/// se il corpo non è un `ErrorResponse` (es. proxy o server di un'altra versione).
+async fn error_message(res: reqwest::Response, fallback: &str) -> String {
+    match res.json::<ErrorResponse>().await {
+        Ok(body) => body.error,
+        Err(_) => fallback.to_string(),
+    }
+}
+
 async fn handle_register(client: &HttpClient, username: String, password: String) -> FromBackend {
     if username.is_empty() || password.is_empty() { return FromBackend::Error("Username e password non possono essere vuoti.".into()); }
-    let payload = serde_json::json!({ "username": username, "password": password });
+    let payload = RegisterUserPayload { username, password };
     match client.post(format!("{}/users/register", API_BASE_URL)).json(&payload).send().await {
         Ok(res) if res.status().is_success() => FromBackend::Registered,
         Ok(res) => {
             if res.status() == StatusCode::BAD_REQUEST {
-                return FromBackend::Error("La password è troppo corta.".into());
+                FromBackend::Error("La password è troppo corta.".into())
             }
             else if res.status() == StatusCode::CONFLICT {
-                return FromBackend::Error("Nome utente già in uso.".into());
+                FromBackend::Error("Nome utente già in uso.".into())
             }
             else {
-                return FromBackend::Error(
-                    res.text().await.unwrap_or_else(|_| "Errore sconosciuto.".into()),
-                );
+                FromBackend::Error(
+                    error_message(res, "Errore sconosciuto.").await,
+                )
             }
         }
         Err(_) => FromBackend::Error("Impossibile connettersi al server.".into()),
     }
 }
 
+/// Costruisce un client HTTP che invia l'access token in ogni richiesta.
+fn build_authenticated_client(token: &str) -> HttpClient {
+    let mut headers = header::HeaderMap::new();
+    headers.insert(
+        header::AUTHORIZATION,
+        header::HeaderValue::from_str(&format!("Bearer {}", token)).unwrap(),
+    );
+    HttpClient::builder().default_headers(headers).build().unwrap()
+}
+
 async fn handle_login(
     username: String,
     password: String,
-) -> Result<(FromBackend, HttpClient), FromBackend> {
+) -> Result<(FromBackend, String, HttpClient), FromBackend> {
     if username.is_empty() || password.is_empty() {
         return Err(FromBackend::Error("Username e password non possono essere vuoti.".into()));
     }
     let unauthed_client = HttpClient::new();
-    let payload = serde_json::json!({ "username": username, "password": password });
+    let payload = LoginPayload { username, password };
 
     match unauthed_client
         .post(format!("{}/users/login", API_BASE_URL))
@@ -713,29 +2912,25 @@ async fn handle_login(
                 .await
                 .map_err(|_| FromBackend::Error("Errore risposta server.".into()))?;
 
-            let mut headers = header::HeaderMap::new();
-            headers.insert(
-                header::AUTHORIZATION,
-                header::HeaderValue::from_str(&format!("Bearer {}", login_res.token)).unwrap(),
-            );
-            let authenticated_client = HttpClient::builder().default_headers(headers).build().unwrap();
+            let authenticated_client = build_authenticated_client(&login_res.token);
 
             Ok((
-                FromBackend::LoggedIn(login_res.user, login_res.token, login_res.groups),
+                FromBackend::LoggedIn(login_res.user, login_res.token, login_res.groups, login_res.direct_conversations, login_res.unread, login_res.is_admin),
+                login_res.refresh_token,
                 authenticated_client,
             ))
         }
         Ok(res) => {
             if res.status() == StatusCode::UNAUTHORIZED {
-                return Err(FromBackend::Error("Username e password errati.".into()));
+                Err(FromBackend::Error("Username e password errati.".into()))
             }
             else if res.status() == StatusCode::NOT_FOUND {
-                return Err(FromBackend::Error("Utente non trovato.".into()));
+                Err(FromBackend::Error("Utente non trovato.".into()))
             }
             else {
-                return Err(FromBackend::Error(
-                    res.text().await.unwrap_or_else(|_| "Errore sconosciuto.".into()),
-                ));
+                Err(FromBackend::Error(
+                    error_message(res, "Errore sconosciuto.").await,
+                ))
             }
         }
         Err(_) => Err(FromBackend::Error(
@@ -744,14 +2939,262 @@ async fn handle_login(
     }
 }
 
-async fn handle_create_group(client: &HttpClient, name: String) -> Result<Group, FromBackend> {
+async fn handle_refresh(refresh_token: String) -> Result<(RefreshResponse, HttpClient), FromBackend> {
+    let payload = RefreshPayload { refresh_token };
+    match HttpClient::new().post(format!("{}/users/refresh", API_BASE_URL)).json(&payload).send().await {
+        Ok(res) if res.status().is_success() => {
+            let refreshed = res
+                .json::<RefreshResponse>()
+                .await
+                .map_err(|_| FromBackend::Error("Errore risposta server.".into()))?;
+            let authenticated_client = build_authenticated_client(&refreshed.token);
+            Ok((refreshed, authenticated_client))
+        }
+        Ok(res) if res.status() == StatusCode::UNAUTHORIZED => Err(FromBackend::SessionExpired),
+        Ok(res) => Err(FromBackend::Error(error_message(res, "Errore sconosciuto.").await)),
+        Err(_) => Err(FromBackend::Error("Impossibile rinnovare la sessione.".into())),
+    }
+}
+
+async fn handle_reset_password(token: String, new_password: String) -> FromBackend {
+    if token.trim().is_empty() || new_password.is_empty() {
+        return FromBackend::Error("Token e nuova password non possono essere vuoti.".into());
+    }
+    let payload = ResetPasswordPayload { token, new_password };
+    match HttpClient::new().post(format!("{}/users/password_reset", API_BASE_URL)).json(&payload).send().await {
+        Ok(res) if res.status().is_success() => FromBackend::PasswordReset,
+        Ok(res) => FromBackend::Error(error_message(res, "Impossibile reimpostare la password.").await),
+        Err(_) => FromBackend::Error("Impossibile connettersi al server.".into()),
+    }
+}
+
+async fn handle_change_password(client: &HttpClient, old_password: String, new_password: String) -> FromBackend {
+    let payload = ChangePasswordPayload { old_password, new_password };
+    match client.put(format!("{}/users/me/password", API_BASE_URL)).json(&payload).send().await {
+        Ok(res) if res.status().is_success() => FromBackend::PasswordChanged,
+        Ok(res) => FromBackend::Error(error_message(res, "Impossibile cambiare la password.").await),
+        Err(_) => FromBackend::Error("Errore di connessione.".into()),
+    }
+}
+
+async fn handle_delete_account(client: &HttpClient, password: String) -> FromBackend {
+    let payload = DeleteAccountPayload { password };
+    match client.delete(format!("{}/users/me", API_BASE_URL)).json(&payload).send().await {
+        Ok(res) if res.status().is_success() => FromBackend::AccountDeleted,
+        Ok(res) => FromBackend::Error(error_message(res, "Impossibile eliminare l'account.").await),
+        Err(_) => FromBackend::Error("Errore di connessione.".into()),
+    }
+}
+
+async fn handle_issue_password_reset(client: &HttpClient, user_id: Uuid) -> FromBackend {
+    match client.post(format!("{}/users/{}/password_reset", API_BASE_URL, user_id)).send().await {
+        Ok(res) if res.status().is_success() => match res.json::<PasswordResetToken>().await {
+            Ok(token) => FromBackend::PasswordResetIssued(user_id, token),
+            Err(_) => FromBackend::Error("Errore nel decodificare il token di reset.".into()),
+        },
+        Ok(res) => FromBackend::Error(error_message(res, "Impossibile emettere il token di reset.").await),
+        Err(_) => FromBackend::Error("Errore di connessione.".into()),
+    }
+}
+
+async fn handle_logout(client: &HttpClient) {
+    let _ = client.post(format!("{}/users/logout", API_BASE_URL)).send().await;
+}
+
+async fn handle_create_group(client: &HttpClient, name: String, visibility: GroupVisibility) -> Result<Group, FromBackend> {
     if name.is_empty() { return Err(FromBackend::Error("Il nome del gruppo non può essere vuoto.".into())); }
-    let payload = serde_json::json!({ "name": name });
+    let payload = CreateGroupPayload { name, visibility };
     match client.post(format!("{}/groups", API_BASE_URL)).json(&payload).send().await {
         Ok(res) if res.status().is_success() => {
             res.json::<Group>().await.map_err(|_| FromBackend::Error("Errore decodifica gruppo creato.".into()))
         }
-        Ok(res) => Err(FromBackend::Error(res.text().await.unwrap_or_default())),
+        Ok(res) => Err(FromBackend::Error(error_message(res, "Errore sconosciuto.").await)),
+        Err(_) => Err(FromBackend::Error("Errore di connessione.".into())),
+    }
+}
+
+async fn handle_set_group_visibility(client: &HttpClient, group_id: Uuid, visibility: GroupVisibility) -> FromBackend {
+    let payload = UpdateGroupVisibilityPayload { visibility };
+    match client.put(format!("{}/groups/{}/visibility", API_BASE_URL, group_id)).json(&payload).send().await {
+        Ok(res) if res.status().is_success() => match res.json::<Group>().await {
+            Ok(group) => FromBackend::GroupUpdated(group),
+            Err(_) => FromBackend::Error("Errore decodifica gruppo.".into()),
+        },
+        Ok(res) => FromBackend::Error(error_message(res, "Impossibile cambiare la visibilità del gruppo.").await),
+        Err(_) => FromBackend::Error("Errore di connessione.".into()),
+    }
+}
+
+/// Risposta di `PATCH /groups/:group_id`: il gruppo aggiornato arriva a tutti, noi compresi, dal WebSocket.
+async fn group_update_result(res: reqwest::Result<reqwest::Response>) -> FromBackend {
+    match res {
+        Ok(res) if res.status().is_success() => FromBackend::Info("Profilo del gruppo aggiornato.".into()),
+        Ok(res) => FromBackend::Error(error_message(res, "Impossibile aggiornare il profilo del gruppo.").await),
+        Err(_) => FromBackend::Error("Errore di connessione.".into()),
+    }
+}
+
+async fn handle_update_group(client: &HttpClient, group_id: Uuid, payload: &UpdateGroupPayload) -> FromBackend {
+    group_update_result(client.patch(format!("{}/groups/{}", API_BASE_URL, group_id)).json(payload).send().await).await
+}
+
+/// Chiede un'immagine e la carica come avatar del gruppo; `None` se l'utente annulla.
+/// Chiede un'immagine da usare come avatar e la prepara per l'upload;
+/// `None` se l'utente annulla la scelta.
+async fn pick_avatar_form(title: &str) -> Option<Result<reqwest::multipart::Form, FromBackend>> {
+    let file = rfd::AsyncFileDialog::new()
+        .set_title(title)
+        .add_filter("Immagini", &["png", "jpg", "jpeg", "gif", "webp"])
+        .pick_file()
+        .await?;
+    let file_name = file.file_name();
+    let mime_type = mime_guess::from_path(&file_name).first_or_octet_stream().essence_str().to_string();
+    if !ALLOWED_AVATAR_TYPES.contains(&mime_type.as_str()) {
+        return Some(Err(FromBackend::Error(format!("Tipo di immagine non supportato ({}).", mime_type))));
+    }
+    let bytes = file.read().await;
+    if bytes.len() as u64 > MAX_AVATAR_SIZE {
+        return Some(Err(FromBackend::Error(format!("L'immagine supera il limite di {}.", format_size(MAX_AVATAR_SIZE)))));
+    }
+
+    let part = match reqwest::multipart::Part::bytes(bytes).file_name(file_name).mime_str(&mime_type) {
+        Ok(part) => part,
+        Err(_) => return Some(Err(FromBackend::Error("Tipo di file non valido.".into()))),
+    };
+    Some(Ok(reqwest::multipart::Form::new().part("avatar", part)))
+}
+
+async fn handle_change_group_avatar(client: &HttpClient, group_id: Uuid) -> Option<FromBackend> {
+    let form = match pick_avatar_form("Immagine del gruppo").await? {
+        Ok(form) => form,
+        Err(e) => return Some(e),
+    };
+    Some(group_update_result(client.patch(format!("{}/groups/{}", API_BASE_URL, group_id)).multipart(form).send().await).await)
+}
+
+async fn handle_fetch_group_history(client: &HttpClient, group_id: Uuid) -> FromBackend {
+    match client.get(format!("{}/groups/{}/history", API_BASE_URL, group_id)).send().await {
+        Ok(res) if res.status().is_success() => match res.json::<Vec<GroupProfileChange>>().await {
+            Ok(history) => FromBackend::GroupHistoryFetched(group_id, history),
+            Err(_) => FromBackend::Error("Errore nel decodificare la cronologia del gruppo.".into()),
+        },
+        Ok(res) => FromBackend::Error(error_message(res, "Impossibile recuperare la cronologia del gruppo.").await),
+        Err(_) => FromBackend::Error("Errore di connessione.".into()),
+    }
+}
+
+async fn handle_fetch_group_avatar(client: &HttpClient, group_id: Uuid, sha256: String) -> FromBackend {
+    fetch_avatar(client, format!("{}/groups/{}/avatar", API_BASE_URL, group_id), sha256).await
+}
+
+async fn fetch_avatar(client: &HttpClient, url: String, sha256: String) -> FromBackend {
+    match client.get(url).send().await {
+        Ok(res) if res.status().is_success() => match res.bytes().await {
+            Ok(bytes) => FromBackend::AvatarFetched(sha256, bytes.to_vec()),
+            Err(_) => FromBackend::Error("Download dell'immagine interrotto.".into()),
+        },
+        Ok(res) => FromBackend::Error(error_message(res, "Immagine non disponibile.").await),
+        Err(_) => FromBackend::Error("Errore di connessione.".into()),
+    }
+}
+
+/// Risposta di `PATCH /users/me`, col profilo aggiornato.
+async fn profile_update_result(res: reqwest::Result<reqwest::Response>) -> FromBackend {
+    match res {
+        Ok(res) if res.status().is_success() => match res.json::<User>().await {
+            Ok(user) => FromBackend::UserUpdated(user),
+            Err(_) => FromBackend::Error("Errore nel decodificare il profilo.".into()),
+        },
+        Ok(res) => FromBackend::Error(error_message(res, "Impossibile aggiornare il profilo.").await),
+        Err(_) => FromBackend::Error("Errore di connessione.".into()),
+    }
+}
+
+async fn handle_update_profile(client: &HttpClient, payload: &UpdateProfilePayload) -> FromBackend {
+    profile_update_result(client.patch(format!("{}/users/me", API_BASE_URL)).json(payload).send().await).await
+}
+
+/// Chiede un'immagine e la carica come avatar dell'utente; `None` se l'utente annulla.
+async fn handle_change_profile_avatar(client: &HttpClient) -> Option<FromBackend> {
+    let form = match pick_avatar_form("La tua immagine").await? {
+        Ok(form) => form,
+        Err(e) => return Some(e),
+    };
+    Some(profile_update_result(client.patch(format!("{}/users/me", API_BASE_URL)).multipart(form).send().await).await)
+}
+
+async fn handle_fetch_user_profile(client: &HttpClient, user_id: Uuid) -> FromBackend {
+    match client.get(format!("{}/users/{}", API_BASE_URL, user_id)).send().await {
+        Ok(res) if res.status().is_success() => match res.json::<User>().await {
+            Ok(user) => FromBackend::UserProfileFetched(user),
+            Err(_) => FromBackend::Error("Errore nel decodificare il profilo.".into()),
+        },
+        Ok(res) => FromBackend::Error(error_message(res, "Profilo non disponibile.").await),
+        Err(_) => FromBackend::Error("Errore di connessione.".into()),
+    }
+}
+
+async fn handle_fetch_user_avatar(client: &HttpClient, user_id: Uuid, sha256: String) -> FromBackend {
+    fetch_avatar(client, format!("{}/users/{}/avatar", API_BASE_URL, user_id), sha256).await
+}
+
+async fn handle_fetch_directory(client: &HttpClient, query: String, offset: u32) -> FromBackend {
+    let params = GroupDirectoryQuery {
+        q: Some(query.clone()).filter(|q| !q.is_empty()),
+        offset: Some(offset),
+        limit: None,
+    };
+    match client.get(format!("{}/groups/directory", API_BASE_URL)).query(&params).send().await {
+        Ok(res) if res.status().is_success() => match res.json::<DirectoryPage>().await {
+            Ok(page) => FromBackend::DirectoryFetched(query, offset, page),
+            Err(_) => FromBackend::Error("Errore nel decodificare l'elenco dei gruppi.".into()),
+        },
+        Ok(res) => FromBackend::Error(error_message(res, "Impossibile caricare l'elenco dei gruppi.").await),
+        Err(_) => FromBackend::Error("Errore di connessione.".into()),
+    }
+}
+
+async fn handle_join_public_group(client: &HttpClient, group_id: Uuid) -> Result<Group, FromBackend> {
+    match client.post(format!("{}/groups/{}/join", API_BASE_URL, group_id)).send().await {
+        Ok(res) if res.status().is_success() => res.json::<Group>().await.map_err(|_| FromBackend::Error("Errore decodifica gruppo.".into())),
+        Ok(res) => Err(FromBackend::Error(error_message(res, "Impossibile entrare nel gruppo.").await)),
+        Err(_) => Err(FromBackend::Error("Errore di connessione.".into())),
+    }
+}
+
+/// Cerca il gruppo per nome esatto e chiede di entrarvi.
+async fn handle_request_to_join(client: &HttpClient, name: String) -> FromBackend {
+    let group = match client.get(format!("{}/groups/by_name/{}", API_BASE_URL, name)).send().await {
+        Ok(res) if res.status().is_success() => match res.json::<Group>().await {
+            Ok(group) => group,
+            Err(_) => return FromBackend::Error("Errore decodifica gruppo.".into()),
+        },
+        Ok(_) => return FromBackend::Error(format!("Gruppo '{}' non trovato.", name)),
+        Err(_) => return FromBackend::Error("Errore di connessione.".into()),
+    };
+    match client.post(format!("{}/groups/{}/join_requests", API_BASE_URL, group.id)).send().await {
+        Ok(res) if res.status().is_success() => FromBackend::Info(format!("Richiesta inviata agli admin di '{}'.", group.name)),
+        Ok(res) => FromBackend::Error(error_message(res, "Impossibile inviare la richiesta.").await),
+        Err(_) => FromBackend::Error("Errore di connessione.".into()),
+    }
+}
+
+async fn handle_fetch_join_requests(client: &HttpClient, group_id: Uuid) -> FromBackend {
+    match client.get(format!("{}/groups/{}/join_requests", API_BASE_URL, group_id)).send().await {
+        Ok(res) if res.status().is_success() => match res.json::<Vec<JoinRequest>>().await {
+            Ok(requests) => FromBackend::JoinRequestsFetched(group_id, requests),
+            Err(_) => FromBackend::Error("Errore nel decodificare le richieste di ingresso.".into()),
+        },
+        Ok(res) => FromBackend::Error(error_message(res, "Impossibile caricare le richieste di ingresso.").await),
+        Err(_) => FromBackend::Error("Errore di connessione.".into()),
+    }
+}
+
+async fn handle_answer_join_request(client: &HttpClient, group_id: Uuid, request_id: Uuid, approve: bool) -> Result<(), FromBackend> {
+    let action = if approve { "approve" } else { "reject" };
+    match client.post(format!("{}/groups/{}/join_requests/{}/{}", API_BASE_URL, group_id, request_id, action)).send().await {
+        Ok(res) if res.status().is_success() => Ok(()),
+        Ok(res) => Err(FromBackend::Error(error_message(res, "Impossibile gestire la richiesta.").await)),
         Err(_) => Err(FromBackend::Error("Errore di connessione.".into())),
     }
 }
@@ -759,7 +3202,51 @@ async fn handle_create_group(client: &HttpClient, name: String) -> Result<Group,
 async fn handle_leave_group(client: &HttpClient, group_id: Uuid) -> FromBackend {
     match client.delete(format!("{}/groups/{}/leave", API_BASE_URL, group_id)).send().await {
         Ok(res) if res.status().is_success() => FromBackend::GroupLeft(group_id),
-        Ok(res) => FromBackend::Error(res.text().await.unwrap_or_else(|_| "Errore durante l'uscita dal gruppo.".into())),
+        Ok(res) => FromBackend::Error(error_message(res, "Errore durante l'uscita dal gruppo.").await),
+        Err(_) => FromBackend::Error("Errore di connessione.".into()),
+    }
+}
+
+async fn handle_delete_group(client: &HttpClient, group_id: Uuid) -> FromBackend {
+    match client.delete(format!("{}/groups/{}", API_BASE_URL, group_id)).send().await {
+        Ok(res) if res.status().is_success() => FromBackend::GroupDeleted(group_id),
+        Ok(res) if res.status() == StatusCode::FORBIDDEN => FromBackend::Error("Solo il proprietario può eliminare il gruppo.".into()),
+        Ok(res) => FromBackend::Error(error_message(res, "Errore durante l'eliminazione del gruppo.").await),
+        Err(_) => FromBackend::Error("Errore di connessione.".into()),
+    }
+}
+
+async fn handle_update_member_role(client: &HttpClient, group_id: Uuid, user_id: Uuid, role: GroupRole) -> FromBackend {
+    let payload = UpdateMemberRolePayload { role };
+    match client.put(format!("{}/groups/{}/members/{}/role", API_BASE_URL, group_id, user_id)).json(&payload).send().await {
+        Ok(res) if res.status().is_success() => FromBackend::Info("Ruolo aggiornato.".into()),
+        Ok(res) if res.status() == StatusCode::FORBIDDEN => FromBackend::Error("Solo il proprietario può cambiare i ruoli.".into()),
+        Ok(res) => FromBackend::Error(error_message(res, "Errore sconosciuto.").await),
+        Err(_) => FromBackend::Error("Errore di connessione.".into()),
+    }
+}
+
+async fn handle_transfer_ownership(client: &HttpClient, group_id: Uuid, user_id: Uuid) -> FromBackend {
+    let payload = TransferOwnershipPayload { new_owner_id: user_id };
+    match client.post(format!("{}/groups/{}/transfer_ownership", API_BASE_URL, group_id)).json(&payload).send().await {
+        Ok(res) if res.status().is_success() => FromBackend::Info("Proprietà del gruppo ceduta.".into()),
+        Ok(res) => FromBackend::Error(error_message(res, "Impossibile cedere la proprietà del gruppo.").await),
+        Err(_) => FromBackend::Error("Errore di connessione.".into()),
+    }
+}
+
+async fn handle_remove_member(client: &HttpClient, group_id: Uuid, user_id: Uuid, ban: bool) -> FromBackend {
+    let request = if ban {
+        client.put(format!("{}/groups/{}/bans/{}", API_BASE_URL, group_id, user_id))
+    } else {
+        client.delete(format!("{}/groups/{}/members/{}", API_BASE_URL, group_id, user_id))
+    };
+    match request.send().await {
+        Ok(res) if res.status().is_success() => {
+            FromBackend::Info(if ban { "Utente bannato.".into() } else { "Utente rimosso dal gruppo.".into() })
+        }
+        Ok(res) if res.status() == StatusCode::FORBIDDEN => FromBackend::Error("Non hai i permessi per rimuovere questo utente.".into()),
+        Ok(res) => FromBackend::Error(error_message(res, "Errore sconosciuto.").await),
         Err(_) => FromBackend::Error("Errore di connessione.".into()),
     }
 }
@@ -782,7 +3269,7 @@ async fn handle_invite(
         _ => return FromBackend::Error(format!("Utente '{}' non trovato.", username_to_invite)),
     };
 
-    let payload = serde_json::json!({ "user_to_invite_id": user_to_invite.id });
+    let payload = InviteToGroupPayload { user_to_invite_id: user_to_invite.id };
 
     match client
         .post(format!("{}/groups/{}/invite", API_BASE_URL, group_id))
@@ -793,26 +3280,134 @@ async fn handle_invite(
         Ok(res) if res.status().is_success() => {
             FromBackend::Info(format!("Invito inviato a {}.", username_to_invite))
         }
-        Ok(res) => FromBackend::Error(
+        Ok(res) => {
             if res.status() == StatusCode::FORBIDDEN {
-                return FromBackend::Error("Errore, l'utente che invita non è membro del gruppo.".into());
+                FromBackend::Error("Non hai i permessi per invitare in questo gruppo.".into())
             }
             else if res.status() == StatusCode::NOT_FOUND {
-                return FromBackend::Error("L'utente o il gruppo non esistono.".into());
+                FromBackend::Error("L'utente o il gruppo non esistono.".into())
             }
             else if res.status() == StatusCode::CONFLICT {
-                return FromBackend::Error("L'utente è già membro del gruppo.".into());
+                FromBackend::Error("L'utente è già membro del gruppo.".into())
             }
             else {
-                return FromBackend::Error(
-                    res.text().await.unwrap_or_else(|_| "Errore sconosciuto.".into()),
-                );
+                FromBackend::Error(
+                    error_message(res, "Errore sconosciuto.").await,
+                )
             }
-        ),
+        }
         Err(_) => FromBackend::Error("Errore di connessione durante l'invito.".into()),
     }
 }
 
+async fn handle_find_user(client: &HttpClient, username: String) -> FromBackend {
+    match client.get(format!("{}/users/by_username/{}", API_BASE_URL, username)).send().await {
+        Ok(res) if res.status().is_success() => match res.json::<User>().await {
+            Ok(user) => FromBackend::DirectPeerFound(user),
+            Err(_) => FromBackend::Error("Errore nel decodificare l'utente.".into()),
+        },
+        Ok(res) if res.status() == StatusCode::NOT_FOUND => FromBackend::Error(format!("Utente '{}' non trovato.", username)),
+        Ok(res) => FromBackend::Error(error_message(res, "Errore sconosciuto.").await),
+        Err(_) => FromBackend::Error("Errore di connessione.".into()),
+    }
+}
+
+/// Invia un messaggio diretto via REST: serve per il primo, che crea la conversazione.
+async fn handle_send_direct_message(client: &HttpClient, peer_id: Uuid, content: String) -> FromBackend {
+    let payload = SendDirectMessagePayload { content };
+    match client.post(format!("{}/direct/{}/messages", API_BASE_URL, peer_id)).json(&payload).send().await {
+        Ok(res) if res.status().is_success() => match res.json::<DirectMessageSent>().await {
+            Ok(sent) => FromBackend::DirectMessage(sent.conversation, sent.message),
+            Err(_) => FromBackend::Error("Errore nel decodificare il messaggio inviato.".into()),
+        },
+        Ok(res) => FromBackend::Error(error_message(res, "Impossibile inviare il messaggio.").await),
+        Err(_) => FromBackend::Error("Errore di connessione.".into()),
+    }
+}
+
+/// Chiede un file all'utente e lo invia nel gruppo; `None` se la selezione viene annullata.
+async fn handle_send_attachment(client: &HttpClient, group_id: Uuid, content: String, reply_to: Option<Uuid>) -> Option<FromBackend> {
+    let file = rfd::AsyncFileDialog::new().set_title("Allega un file").pick_file().await?;
+    let file_name = file.file_name();
+    let mime_type = mime_guess::from_path(&file_name).first_or_octet_stream().essence_str().to_string();
+    if !ALLOWED_ATTACHMENT_TYPES.contains(&mime_type.as_str()) {
+        return Some(FromBackend::Error(format!("Tipo di file non supportato ({}).", mime_type)));
+    }
+    let bytes = file.read().await;
+    if bytes.len() as u64 > MAX_ATTACHMENT_SIZE {
+        return Some(FromBackend::Error(format!("Il file supera il limite di {}.", format_size(MAX_ATTACHMENT_SIZE))));
+    }
+
+    let part = match reqwest::multipart::Part::bytes(bytes).file_name(file_name).mime_str(&mime_type) {
+        Ok(part) => part,
+        Err(_) => return Some(FromBackend::Error("Tipo di file non valido.".into())),
+    };
+    let mut form = reqwest::multipart::Form::new().text("content", content).part("file", part);
+    if let Some(reply_to) = reply_to {
+        form = form.text("reply_to", reply_to.to_string());
+    }
+    let res = match client.post(format!("{}/groups/{}/attachments", API_BASE_URL, group_id)).multipart(form).send().await {
+        // Il messaggio arriva anche dal WebSocket: i duplicati vengono riconosciuti dall'id
+        Ok(res) if res.status().is_success() => match res.json::<WsServerMessage>().await {
+            Ok(msg) => FromBackend::NewMessage(group_id, msg),
+            Err(_) => FromBackend::Error("Errore nel decodificare il messaggio inviato.".into()),
+        },
+        Ok(res) => FromBackend::Error(error_message(res, "Impossibile inviare il file.").await),
+        Err(_) => FromBackend::Error("Errore di connessione durante l'invio del file.".into()),
+    };
+    Some(res)
+}
+
+async fn fetch_attachment_bytes(client: &HttpClient, group_id: Uuid, attachment_id: Uuid) -> Result<Vec<u8>, FromBackend> {
+    match client.get(format!("{}/groups/{}/attachments/{}", API_BASE_URL, group_id, attachment_id)).send().await {
+        Ok(res) if res.status().is_success() => res
+            .bytes()
+            .await
+            .map(|bytes| bytes.to_vec())
+            .map_err(|_| FromBackend::Error("Download dell'allegato interrotto.".into())),
+        Ok(res) => Err(FromBackend::Error(error_message(res, "Allegato non disponibile.").await)),
+        Err(_) => Err(FromBackend::Error("Errore di connessione.".into())),
+    }
+}
+
+async fn handle_fetch_attachment(client: &HttpClient, group_id: Uuid, attachment_id: Uuid) -> FromBackend {
+    match fetch_attachment_bytes(client, group_id, attachment_id).await {
+        Ok(bytes) => FromBackend::AttachmentFetched(attachment_id, bytes),
+        Err(e) => e,
+    }
+}
+
+/// Chiede dove salvare l'allegato e lo scarica; `None` se l'utente annulla.
+async fn handle_download_attachment(client: &HttpClient, group_id: Uuid, attachment: Attachment) -> Option<FromBackend> {
+    let target = rfd::AsyncFileDialog::new().set_file_name(&attachment.file_name).save_file().await?;
+    let res = match fetch_attachment_bytes(client, group_id, attachment.id).await {
+        Ok(bytes) => match tokio::fs::write(target.path(), bytes).await {
+            Ok(()) => FromBackend::Info(format!("'{}' salvato.", attachment.file_name)),
+            Err(_) => FromBackend::Error("Impossibile salvare il file.".into()),
+        },
+        Err(e) => e,
+    };
+    Some(res)
+}
+
+/// Tempo trascorso dall'ultima disconnessione, in forma breve ("5 minuti fa").
+fn format_last_seen(at: OffsetDateTime) -> String {
+    let elapsed = OffsetDateTime::now_utc() - at;
+    match elapsed.whole_minutes() {
+        m if m < 1 => "poco fa".to_string(),
+        m if m < 60 => format!("{} min fa", m),
+        m if m < 24 * 60 => format!("{} ore fa", m / 60),
+        m => format!("{} giorni fa", m / (24 * 60)),
+    }
+}
+
+fn format_size(bytes: u64) -> String {
+    match bytes {
+        b if b >= 1024 * 1024 => format!("{:.1} MB", b as f64 / (1024.0 * 1024.0)),
+        b if b >= 1024 => format!("{:.0} KB", b as f64 / 1024.0),
+        b => format!("{} B", b),
+    }
+}
 
 async fn handle_fetch_invitations(client: &HttpClient) -> FromBackend {
     match client.get(format!("{}/invitations", API_BASE_URL)).send().await {
@@ -824,17 +3419,52 @@ async fn handle_fetch_invitations(client: &HttpClient) -> FromBackend {
     }
 }
 
+async fn handle_fetch_invite_links(client: &HttpClient, group_id: Uuid) -> FromBackend {
+    match client.get(format!("{}/groups/{}/invite_links", API_BASE_URL, group_id)).send().await {
+        Ok(res) if res.status().is_success() => match res.json::<Vec<InviteLink>>().await {
+            Ok(links) => FromBackend::InviteLinksFetched(group_id, links),
+            Err(_) => FromBackend::Error("Errore nel decodificare i link di invito.".into()),
+        },
+        Ok(res) => FromBackend::Error(error_message(res, "Impossibile recuperare i link di invito.").await),
+        Err(_) => FromBackend::Error("Errore di connessione.".into()),
+    }
+}
+
+async fn handle_create_invite_link(client: &HttpClient, group_id: Uuid, payload: &CreateInviteLinkPayload) -> Result<(), FromBackend> {
+    match client.post(format!("{}/groups/{}/invite_links", API_BASE_URL, group_id)).json(payload).send().await {
+        Ok(res) if res.status().is_success() => Ok(()),
+        Ok(res) => Err(FromBackend::Error(error_message(res, "Impossibile creare il link di invito.").await)),
+        Err(_) => Err(FromBackend::Error("Errore di connessione.".into())),
+    }
+}
+
+async fn handle_revoke_invite_link(client: &HttpClient, group_id: Uuid, code: &str) -> Result<(), FromBackend> {
+    match client.delete(format!("{}/groups/{}/invite_links/{}", API_BASE_URL, group_id, code)).send().await {
+        Ok(res) if res.status().is_success() => Ok(()),
+        Ok(res) => Err(FromBackend::Error(error_message(res, "Impossibile revocare il link di invito.").await)),
+        Err(_) => Err(FromBackend::Error("Errore di connessione.".into())),
+    }
+}
+
+async fn handle_join_with_invite_code(client: &HttpClient, code: &str) -> Result<Group, FromBackend> {
+    match client.post(format!("{}/invites/{}/join", API_BASE_URL, code)).send().await {
+        Ok(res) if res.status().is_success() => res.json::<Group>().await.map_err(|_| FromBackend::Error("Errore decodifica gruppo.".into())),
+        Ok(res) => Err(FromBackend::Error(error_message(res, "Codice di invito non valido.").await)),
+        Err(_) => Err(FromBackend::Error("Errore di connessione.".into())),
+    }
+}
+
 async fn handle_accept_invitation(client: &HttpClient, id: Uuid) -> Result<Group, FromBackend> {
     match client.post(format!("{}/invitations/{}/accept", API_BASE_URL, id)).send().await {
         Ok(res) if res.status().is_success() => res.json::<Group>().await.map_err(|_| FromBackend::Error("Errore decodifica gruppo.".into())),
         Ok(res) => {
             if res.status() == StatusCode::NOT_FOUND {
-                return Err(FromBackend::Error("Inviti non trovati.".into()));   
+                Err(FromBackend::Error("Inviti non trovati.".into()))
             }
             else {
-                return Err(FromBackend::Error(
-                    res.text().await.unwrap_or_else(|_| "Errore sconosciuto.".into()),
-                ));
+                Err(FromBackend::Error(
+                    error_message(res, "Errore sconosciuto.").await,
+                ))
             }
         }
         Err(_) => Err(FromBackend::Error("Errore di connessione.".into())),
@@ -846,82 +3476,349 @@ async fn handle_decline_invitation(client: &HttpClient, id: Uuid) -> FromBackend
         Ok(res) if res.status().is_success() => FromBackend::InvitationDeclined(id),
         Ok(res) => {
             if res.status() == StatusCode::NOT_FOUND {
-                return FromBackend::Error("Inviti non trovati.".into());   
+                FromBackend::Error("Inviti non trovati.".into())
             }
             else {
-                return FromBackend::Error(
-                    res.text().await.unwrap_or_else(|_| "Errore sconosciuto.".into()),
-                );
+                FromBackend::Error(
+                    error_message(res, "Errore sconosciuto.").await,
+                )
             }
         }
         Err(_) => FromBackend::Error("Errore di connessione.".into()),
     }
 }
 
-async fn handle_fetch_group_messages(client: &HttpClient, group_id: Uuid) -> FromBackend {
-    match client.get(format!("{}/groups/{}/messages", API_BASE_URL, group_id)).send().await {
+/// Scarica i messaggi più recenti del gruppo o, con `before`, la pagina che precede quel messaggio.
+async fn handle_fetch_group_messages(client: &HttpClient, group_id: Uuid, before: Option<Uuid>) -> FromBackend {
+    let query = MessageHistoryQuery { before, ..Default::default() };
+    match client.get(format!("{}/groups/{}/messages", API_BASE_URL, group_id)).query(&query).send().await {
         Ok(res) if res.status().is_success() => {
-            match res.json::<Vec<WsServerMessage>>().await {
-                Ok(messages) => FromBackend::GroupMessagesFetched(group_id, messages),
+            match res.json::<MessagePage>().await {
+                Ok(page) if before.is_some() => FromBackend::OlderMessagesFetched(group_id, page),
+                Ok(page) => FromBackend::GroupMessagesFetched(group_id, page),
                 Err(_) => FromBackend::Error("Errore nel decodificare la cronologia dei messaggi.".into()),
             }
         },
         Ok(res) => {
             if res.status() == StatusCode::FORBIDDEN {
-                return FromBackend::Error("Accesso negato, l'utente non è membro del gruppo.".into());
+                FromBackend::Error("Accesso negato, l'utente non è membro del gruppo.".into())
             }
             else {
-                return FromBackend::Error(
-                    res.text().await.unwrap_or_else(|_| "Errore sconosciuto.".into()),
-                );
+                FromBackend::Error(
+                    error_message(res, "Errore sconosciuto.").await,
+                )
             }
         }
         Err(_) => FromBackend::Error("Errore di connessione per la cronologia dei messaggi.".into()),
     }
 }
 
+async fn handle_edit_message(client: &HttpClient, group_id: Uuid, message_id: Uuid, content: String) -> Result<(), FromBackend> {
+    let payload = EditMessagePayload { content };
+    match client.patch(format!("{}/groups/{}/messages/{}", API_BASE_URL, group_id, message_id)).json(&payload).send().await {
+        Ok(res) if res.status().is_success() => Ok(()),
+        Ok(res) if res.status() == StatusCode::FORBIDDEN => Err(FromBackend::Error("Puoi modificare solo i tuoi messaggi.".into())),
+        Ok(res) => Err(FromBackend::Error(error_message(res, "Errore sconosciuto.").await)),
+        Err(_) => Err(FromBackend::Error("Errore di connessione.".into())),
+    }
+}
+
+async fn handle_delete_message(client: &HttpClient, group_id: Uuid, message_id: Uuid) -> Result<(), FromBackend> {
+    match client.delete(format!("{}/groups/{}/messages/{}", API_BASE_URL, group_id, message_id)).send().await {
+        Ok(res) if res.status().is_success() => Ok(()),
+        Ok(res) if res.status() == StatusCode::FORBIDDEN => Err(FromBackend::Error("Non puoi eliminare questo messaggio.".into())),
+        Ok(res) => Err(FromBackend::Error(error_message(res, "Errore sconosciuto.").await)),
+        Err(_) => Err(FromBackend::Error("Errore di connessione.".into())),
+    }
+}
+
+async fn handle_mark_read(client: &HttpClient, group_id: Uuid, message_id: Uuid) -> Result<(), FromBackend> {
+    let payload = MarkReadPayload { message_id };
+    match client.put(format!("{}/groups/{}/read", API_BASE_URL, group_id)).json(&payload).send().await {
+        Ok(res) if res.status().is_success() => Ok(()),
+        Ok(res) => Err(FromBackend::Error(error_message(res, "Impossibile aggiornare i messaggi letti.").await)),
+        Err(_) => Err(FromBackend::Error("Errore di connessione.".into())),
+    }
+}
+
+async fn handle_fetch_thread(client: &HttpClient, group_id: Uuid, message_id: Uuid) -> FromBackend {
+    match client.get(format!("{}/groups/{}/messages/{}/thread", API_BASE_URL, group_id, message_id)).send().await {
+        Ok(res) if res.status().is_success() => match res.json::<MessageThread>().await {
+            Ok(thread) => FromBackend::ThreadLoaded(group_id, thread),
+            Err(_) => FromBackend::Error("Errore nel decodificare la discussione.".into()),
+        },
+        Ok(res) => FromBackend::Error(error_message(res, "Impossibile aprire la discussione.").await),
+        Err(_) => FromBackend::Error("Errore di connessione.".into()),
+    }
+}
+
+async fn handle_set_reaction(client: &HttpClient, group_id: Uuid, message_id: Uuid, emoji: &str, add: bool) -> Result<(), FromBackend> {
+    let url = format!("{}/groups/{}/messages/{}/reactions/{}", API_BASE_URL, group_id, message_id, emoji);
+    let request = if add { client.put(url) } else { client.delete(url) };
+    match request.send().await {
+        Ok(res) if res.status().is_success() => Ok(()),
+        Ok(res) => Err(FromBackend::Error(error_message(res, "Impossibile aggiornare la reazione.").await)),
+        Err(_) => Err(FromBackend::Error("Errore di connessione.".into())),
+    }
+}
+
 async fn handle_fetch_group_members(client: &HttpClient, group_id: Uuid) -> FromBackend {
     match client.get(format!("{}/groups/{}/members", API_BASE_URL, group_id)).send().await {
         Ok(res) if res.status().is_success() => {
-            match res.json::<Vec<User>>().await {
+            match res.json::<Vec<GroupMember>>().await {
                 Ok(membri) => FromBackend::GroupMembersFetched(group_id, membri),
                 Err(_) => FromBackend::Error("Errore nel decodificare i membri del gruppo.".to_string()),
             }
         }
         Ok(res) => {
-            FromBackend::Error(res.text().await.unwrap_or_else(|_| "Errore sconosciuto.".to_string()))
+            FromBackend::Error(error_message(res, "Errore sconosciuto.").await)
         }
         Err(_) => FromBackend::Error("Errore richiesta handle fetch".to_string()),
     }
 }
 
-async fn handle_join_group(
-    group: Group,
-    token: String,
-    from_backend_tx: Sender<FromBackend>
-) -> Result<Sender<WsMessage>, FromBackend> {
-    let ws_url = format!("ws://127.0.0.1:3000/groups/{}/chat?token={}", group.id, token);
-    let ws_stream = match connect_async(&ws_url).await {
-        Ok((stream, _)) => stream,
-        Err(e) => return Err(FromBackend::Error(format!("Impossibile connettersi alla chat: {}", e))),
+async fn handle_fetch_mentions(client: &HttpClient, offset: u32) -> FromBackend {
+    let params = MentionQuery { offset: Some(offset), limit: None };
+    match client.get(format!("{}/users/me/mentions", API_BASE_URL)).query(&params).send().await {
+        Ok(res) if res.status().is_success() => match res.json::<MentionPage>().await {
+            Ok(page) => FromBackend::MentionsFetched(offset, page),
+            Err(_) => FromBackend::Error("Errore nel decodificare le menzioni.".into()),
+        },
+        Ok(res) => FromBackend::Error(error_message(res, "Impossibile caricare le menzioni.").await),
+        Err(_) => FromBackend::Error("Errore di connessione.".into()),
+    }
+}
+
+async fn handle_search(client: &HttpClient, query: String, group_id: Option<Uuid>, offset: u32) -> FromBackend {
+    let url = match group_id {
+        Some(group_id) => format!("{}/groups/{}/messages/search", API_BASE_URL, group_id),
+        None => format!("{}/messages/search", API_BASE_URL),
     };
-    
-    let (mut write, mut read) = ws_stream.split();
-    let (tx, mut rx) = mpsc::channel::<WsMessage>(32);
-    tokio::spawn(async move { while let Some(msg) = rx.recv().await { if write.send(msg).await.is_err() { break; } } });
+    let params = MessageSearchQuery { q: query.clone(), offset: Some(offset), limit: None };
+    match client.get(url).query(&params).send().await {
+        Ok(res) if res.status().is_success() => match res.json::<SearchPage>().await {
+            Ok(page) => FromBackend::SearchResults(query, group_id, offset, page),
+            Err(_) => FromBackend::Error("Errore nel decodificare i risultati della ricerca.".into()),
+        },
+        Ok(res) => FromBackend::Error(error_message(res, "Errore durante la ricerca.").await),
+        Err(_) => FromBackend::Error("Errore di connessione.".into()),
+    }
+}
 
-    let ui_tx = from_backend_tx.clone();
-    tokio::spawn(async move {
-        while let Some(Ok(msg)) = read.next().await {
-            if let WsMessage::Text(text) = msg {
-                if let Ok(server_msg) = serde_json::from_str::<WsServerMessage>(&text) {
-                    if ui_tx.send(FromBackend::NewMessage(group.id, server_msg)).await.is_err() { break; }
-                }
+/// Mostra un estratto di ricerca con i termini trovati evidenziati.
+fn highlighted_snippet(snippet: &str, highlights: &[std::ops::Range<usize>]) -> egui::text::LayoutJob {
+    let mut job = egui::text::LayoutJob::default();
+    let normal = egui::TextFormat { color: Color32::from_gray(220), ..Default::default() };
+    let marked = egui::TextFormat { color: Color32::from_gray(10), background: Color32::from_rgb(238, 212, 159), ..Default::default() };
+    let mut last = 0;
+    for range in highlights {
+        // Intervalli fuori posto (o non ordinati) vengono ignorati
+        let (Some(before), Some(term)) = (snippet.get(last..range.start), snippet.get(range.clone())) else { continue };
+        job.append(before, 0.0, normal.clone());
+        job.append(term, 0.0, marked.clone());
+        last = range.end;
+    }
+    job.append(&snippet[last..], 0.0, normal);
+    job
+}
+
+/// Testo di un messaggio con le menzioni in grassetto; quelle dell'utente corrente sono evidenziate.
+fn mention_text(content: &str, my_username: &str, color: Color32) -> egui::text::LayoutJob {
+    let font_id = egui::FontId::proportional(15.0);
+    let normal = egui::TextFormat { font_id: font_id.clone(), color, ..Default::default() };
+    let mention = egui::TextFormat { font_id: font_id.clone(), color: Color32::from_rgb(94, 129, 172), ..Default::default() };
+    let mine = egui::TextFormat { font_id, color: Color32::from_gray(10), background: Color32::from_rgb(235, 203, 139), ..Default::default() };
+    let mut job = egui::text::LayoutJob::default();
+    let mut last = 0;
+    for (range, name) in find_mentions(content) {
+        job.append(&content[last..range.start], 0.0, normal.clone());
+        let format = if name == my_username { mine.clone() } else { mention.clone() };
+        job.append(&content[range.clone()], 0.0, format);
+        last = range.end;
+    }
+    job.append(&content[last..], 0.0, normal);
+    job
+}
+
+/// Accoda un comando per la connessione WebSocket; restituisce `false` se non c'è una sessione attiva.
+async fn send_ws_command(ws_sender: &Option<Sender<WsMuxCommand>>, command: WsMuxCommand) -> bool {
+    let Some(sender) = ws_sender else { return false };
+    sender.send(command).await.is_ok()
+}
+
+/// Traduce un evento di un gruppo nel messaggio per la UI; `None` se non va mostrato.
+fn group_event_update(group_id: Uuid, my_id: Uuid, event: WsServerEvent) -> Option<FromBackend> {
+    let update = match event {
+        WsServerEvent::Welcome { .. } => return None, // Solo sulle connessioni per gruppo
+        WsServerEvent::Message(server_msg) => FromBackend::NewMessage(group_id, server_msg),
+        WsServerEvent::MemberJoined { user_id, username } => FromBackend::MemberJoined(group_id, user_id, username),
+        // Riguarda noi: siamo usciti (anche da un altro dispositivo) o siamo stati rimossi da un admin
+        WsServerEvent::MemberLeft { user_id, reason, .. } if user_id == my_id => match reason {
+            MemberLeftReason::Left => FromBackend::GroupLeft(group_id),
+            MemberLeftReason::Kicked | MemberLeftReason::Banned => FromBackend::RemovedFromGroup(group_id),
+        },
+        WsServerEvent::MemberLeft { user_id, username, reason, by_username } => {
+            FromBackend::MemberLeft(group_id, user_id, username, reason, by_username)
+        }
+        WsServerEvent::MessageEdited { message_id, content, edited_at } => {
+            FromBackend::MessageEdited(group_id, message_id, content, edited_at)
+        }
+        WsServerEvent::MessageDeleted { message_id } => FromBackend::MessageDeleted(group_id, message_id),
+        WsServerEvent::ReactionAdded { message_id, user_id, emoji } => FromBackend::ReactionAdded(group_id, message_id, user_id, emoji),
+        WsServerEvent::ReactionRemoved { message_id, user_id, emoji } => {
+            FromBackend::ReactionRemoved(group_id, message_id, user_id, emoji)
+        }
+        WsServerEvent::OwnershipTransferred { new_owner_id, new_owner_username, .. } => {
+            FromBackend::OwnershipTransferred(group_id, new_owner_id, new_owner_username)
+        }
+        WsServerEvent::GroupDeleted { by_username } => FromBackend::GroupDeletedByOwner(group_id, by_username),
+        WsServerEvent::UserUpdated { user } => FromBackend::UserUpdated(user),
+        WsServerEvent::GroupUpdated { group, by_username } => FromBackend::GroupProfileUpdated(group, by_username),
+        WsServerEvent::ReadUpTo { user_id, message_id } => FromBackend::ReadUpTo(group_id, user_id, message_id),
+        WsServerEvent::Typing { user_id, username } => FromBackend::UserTyping(group_id, user_id, username),
+        WsServerEvent::Presence { user_id, status, last_seen_at } => FromBackend::PresenceChanged(user_id, status, last_seen_at),
+        WsServerEvent::Error { message, .. } => FromBackend::Error(message),
+    };
+    Some(update)
+}
+
+/// Mantiene aperta la connessione unica `/ws`, che riceve gli eventi di tutti i gruppi dell'utente
+/// e i nuovi inviti. Se cade si riconnette con backoff esponenziale e recupera i messaggi persi;
+/// termina quando il backend chiude il canale dei comandi (logout o nuova sessione).
+async fn run_user_socket(
+    token_rx: watch::Receiver<String>,
+    my_id: Uuid,
+    mut commands: Receiver<WsMuxCommand>,
+    cursors: MessageCursors,
+    ui_tx: Sender<FromBackend>,
+) {
+    let mut attempt: u32 = 0;
+    let mut resuming = false;
+    loop {
+        let ws_url = format!("ws://127.0.0.1:3000/ws?token={}&protocol={}", *token_rx.borrow(), WS_PROTOCOL_VERSION);
+        if let Ok((stream, _)) = connect_async(&ws_url).await {
+            attempt = 0;
+            let _ = ui_tx.send(FromBackend::ConnectionChanged(ConnectionStatus::Connected)).await;
+            if !run_socket_session(stream, my_id, resuming, &mut commands, &cursors, &ui_tx).await {
+                return;
+            }
+            resuming = true;
+        }
+
+        let retry_in = RECONNECT_BASE_DELAY.saturating_mul(1 << attempt.min(5)).min(RECONNECT_MAX_DELAY);
+        attempt += 1;
+        let _ = ui_tx.send(FromBackend::ConnectionChanged(ConnectionStatus::Reconnecting { attempt, retry_in })).await;
+
+        let wait = tokio::time::sleep(retry_in);
+        tokio::pin!(wait);
+        loop {
+            tokio::select! {
+                _ = &mut wait => break,
+                command = commands.recv() => match command {
+                    None => return,
+                    Some(WsMuxCommand::SendMessage { .. }) => {
+                        let _ = ui_tx.send(FromBackend::Error("Connessione assente: il messaggio non è stato inviato.".into())).await;
+                    }
+                    // Le iscrizioni vengono ricostruite dal server alla riconnessione
+                    Some(_) => {}
+                },
             }
         }
-    });
-    
-    Ok(tx)
+    }
+}
+
+/// Gestisce una connessione aperta; restituisce `true` se è caduta e va ristabilita.
+async fn run_socket_session(
+    stream: WebSocketStream<MaybeTlsStream<TcpStream>>,
+    my_id: Uuid,
+    resuming: bool,
+    commands: &mut Receiver<WsMuxCommand>,
+    cursors: &MessageCursors,
+    ui_tx: &Sender<FromBackend>,
+) -> bool {
+    let (mut write, mut read) = stream.split();
+    loop {
+        let frame = tokio::select! {
+            command = commands.recv() => {
+                let Some(command) = command else {
+                    let _ = write.send(WsMessage::Close(None)).await;
+                    return false;
+                };
+                let json_msg = serde_json::to_string(&command).unwrap();
+                if write.send(WsMessage::Text(json_msg)).await.is_err() { return true; }
+                continue;
+            }
+            frame = read.next() => frame,
+        };
+
+        let text = match frame {
+            Some(Ok(WsMessage::Text(text))) => text,
+            // Sessione revocata (es. password cambiata altrove): riconnettersi non servirebbe
+            Some(Ok(WsMessage::Close(Some(close)))) if u16::from(close.code) == CLOSE_SESSION_REVOKED => {
+                let _ = ui_tx.send(FromBackend::SessionExpired).await;
+                return false;
+            }
+            Some(Ok(_)) => continue,
+            Some(Err(_)) | None => return true,
+        };
+        let event = match serde_json::from_str::<WsMuxEvent>(&text) {
+            Ok(event) => event,
+            Err(_) => continue, // Evento sconosciuto: il server parla una versione più recente
+        };
+
+        let update = match event {
+            WsMuxEvent::Welcome { protocol_version, groups, .. } => {
+                // Chiede i messaggi successivi all'ultimo visto; senza un punto di ripresa
+                // la cronologia del gruppo va ricaricata da capo
+                for group_id in groups {
+                    let after = cursors.lock().unwrap().get(&group_id).copied();
+                    if after.is_some() {
+                        let json_msg = serde_json::to_string(&WsMuxCommand::Subscribe { group_id, after }).unwrap();
+                        if write.send(WsMessage::Text(json_msg)).await.is_err() { return true; }
+                    } else if resuming && ui_tx.send(FromBackend::HistoryStale(group_id)).await.is_err() {
+                        return false;
+                    }
+                }
+                if protocol_version == WS_PROTOCOL_VERSION { continue; }
+                FromBackend::Error(format!("Il server usa il protocollo v{}, il client la v{}.", protocol_version, WS_PROTOCOL_VERSION))
+            }
+            WsMuxEvent::Subscribed { .. } | WsMuxEvent::Unsubscribed { .. } => continue,
+            WsMuxEvent::Replay { group_id, messages, has_more } => {
+                if has_more && ui_tx.send(FromBackend::HistoryStale(group_id)).await.is_err() {
+                    return false;
+                }
+                let Some(last) = messages.last() else { continue };
+                cursors.lock().unwrap().insert(group_id, last.id);
+                FromBackend::MessagesReplayed(group_id, messages)
+            }
+            WsMuxEvent::Group { group_id, event } => {
+                if let WsServerEvent::Message(msg) = &event {
+                    cursors.lock().unwrap().insert(group_id, msg.id);
+                }
+                match group_event_update(group_id, my_id, event) {
+                    Some(update) => update,
+                    None => continue,
+                }
+            }
+            WsMuxEvent::InvitationReceived(invitation) => FromBackend::InvitationReceived(invitation),
+            WsMuxEvent::InvitationAnswered { group_name, invited_username, accepted, .. } => {
+                FromBackend::InvitationAnswered(group_name, invited_username, accepted)
+            }
+            WsMuxEvent::JoinRequestReceived(request) => FromBackend::JoinRequestReceived(request),
+            // Il server ha già iscritto la connessione al gruppo
+            WsMuxEvent::JoinRequestApproved(group) => FromBackend::GroupJoined(group),
+            WsMuxEvent::JoinRequestRejected { group_name, .. } => {
+                FromBackend::Error(format!("La tua richiesta di entrare in '{}' è stata rifiutata.", group_name))
+            }
+            WsMuxEvent::Mentioned(mention) => FromBackend::Mentioned(mention),
+            WsMuxEvent::DirectConversationStarted { conversation, message } => {
+                cursors.lock().unwrap().insert(conversation.id, message.id);
+                FromBackend::DirectMessage(conversation, message)
+            }
+            WsMuxEvent::Error { message, .. } => FromBackend::Error(message),
+        };
+        if ui_tx.send(update).await.is_err() { return false; }
+    }
 }
 
 fn main() -> Result<(), eframe::Error> {
@@ -0,0 +1,45 @@
+use serde::{Deserialize, Serialize};
+
+/// Codici macchina degli errori restituiti dalle API REST, accanto al messaggio leggibile.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    Internal,
+    Unauthorized,
+    SessionRevoked,
+    InvalidInput,
+    WrongCredentials,
+    InvalidRefreshToken,
+    UsernameExists,
+    UserNotFound,
+    GroupNotFound,
+    UserOrGroupNotFound,
+    InvitationNotFound,
+    MessageNotFound,
+    AttachmentNotFound,
+    AttachmentTooLarge,
+    UnsupportedMediaType,
+    InvitationAlreadyExists,
+    UserAlreadyInGroup,
+    UserNotInGroup,
+    UserBanned,
+    BanNotFound,
+    MissingPermissions,
+    NotGroupMember,
+    InsufficientRole,
+    CannotInviteSelf,
+    InviteLinkNotFound,
+    InviteLinkExpired,
+    GroupNotPublic,
+    JoinRequestNotFound,
+    JoinRequestAlreadyExists,
+    WrongPassword,
+    InvalidResetToken,
+}
+
+/// Corpo JSON di ogni risposta di errore.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ErrorResponse {
+    pub code: ErrorCode,
+    pub error: String,
+}
@@ -0,0 +1,13 @@
+//! Tipi condivisi tra `ruggine_server` e `ruggine_client`.
+//!
+//! Tutto ciò che viaggia sulla rete (DTO delle API REST, eventi e comandi WebSocket,
+//! codici di errore) vive qui: una modifica al protocollo rompe la compilazione di
+//! entrambi i binari invece di far fallire in silenzio la deserializzazione.
+
+mod error;
+mod rest;
+mod ws;
+
+pub use error::*;
+pub use rest::*;
+pub use ws::*;
@@ -0,0 +1,561 @@
+use crate::{PresenceStatus, WsServerMessage};
+use serde::{Deserialize, Serialize};
+use std::ops::Range;
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+// --- Utenti e sessioni ---
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct User {
+    pub id: Uuid,
+    pub username: String,
+    #[serde(with = "time::serde::rfc3339")]
+    pub created_at: OffsetDateTime,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub display_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bio: Option<String>,
+    /// Stato scritto dall'utente ("in ferie fino a lunedì"), distinto dalla presenza.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status_message: Option<String>,
+    /// SHA-256 dell'immagine servita da `GET /users/:user_id/avatar`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub avatar_sha256: Option<String>,
+}
+
+impl User {
+    /// Nome da mostrare: quello scelto dall'utente, altrimenti lo username.
+    pub fn name(&self) -> &str {
+        self.display_name.as_deref().unwrap_or(&self.username)
+    }
+}
+
+/// Corpo di `PATCH /users/me`: cambiano solo i campi presenti, e un campo vuoto viene tolto.
+/// Come per i gruppi, un nuovo avatar va inviato in multipart nel campo `avatar`.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct UpdateProfilePayload {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub display_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bio: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status_message: Option<String>,
+    #[serde(default)]
+    pub remove_avatar: bool,
+}
+
+/// Lunghezze massime, in caratteri, dei campi del profilo utente.
+pub const MAX_DISPLAY_NAME_CHARS: usize = 50;
+pub const MAX_BIO_CHARS: usize = 300;
+pub const MAX_STATUS_MESSAGE_CHARS: usize = 80;
+
+#[derive(Serialize, Deserialize)]
+pub struct RegisterUserPayload {
+    pub username: String,
+    pub password: String,
+}
+
+/// Lunghezza minima di una password, alla registrazione come a ogni cambio.
+pub const MIN_PASSWORD_LEN: usize = 8;
+
+/// Nome mostrato al posto di un account eliminato, i cui messaggi restano nei gruppi.
+pub const DELETED_USER_NAME: &str = "Utente eliminato";
+
+/// Corpo di `PUT /users/me/password`: le altre sessioni dell'utente vengono revocate.
+#[derive(Serialize, Deserialize)]
+pub struct ChangePasswordPayload {
+    pub old_password: String,
+    pub new_password: String,
+}
+
+/// Corpo di `DELETE /users/me`: la password conferma l'eliminazione.
+#[derive(Serialize, Deserialize)]
+pub struct DeleteAccountPayload {
+    pub password: String,
+}
+
+/// Token di reset emesso da un amministratore: viene mostrato una sola volta.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PasswordResetToken {
+    pub token: String,
+    #[serde(with = "time::serde::rfc3339")]
+    pub expires_at: OffsetDateTime,
+}
+
+/// Corpo di `POST /users/password_reset`, utilizzabile senza login.
+#[derive(Serialize, Deserialize)]
+pub struct ResetPasswordPayload {
+    pub token: String,
+    pub new_password: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct LoginPayload {
+    pub username: String,
+    pub password: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct LoginResponse {
+    pub token: String,
+    pub refresh_token: String,
+    pub user: User, // Ottimizzazione: restituisce l'utente al login
+    pub groups: Vec<Group>,
+    #[serde(default)]
+    pub direct_conversations: Vec<DirectConversation>,
+    #[serde(default)]
+    pub unread: Vec<UnreadCount>,
+    // Solo gli amministratori del server possono emettere token di reset della password
+    #[serde(default)]
+    pub is_admin: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct RefreshPayload {
+    pub refresh_token: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct RefreshResponse {
+    pub token: String,
+    pub refresh_token: String,
+}
+
+// --- Gruppi ---
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Group {
+    pub id: Uuid,
+    pub name: String,
+    #[serde(with = "time::serde::rfc3339")]
+    pub created_at: OffsetDateTime,
+    #[serde(default)]
+    pub visibility: GroupVisibility,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// Argomento del momento, mostrato sopra la chat.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub topic: Option<String>,
+    /// SHA-256 dell'immagine servita da `GET /groups/:group_id/avatar`: cambia quando l'avatar viene sostituito.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub avatar_sha256: Option<String>,
+}
+
+/// Un gruppo pubblico compare nell'elenco e chiunque può entrarci; in uno privato si entra
+/// su invito o con una richiesta approvata da un admin. Salvato in `groups.visibility`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "sqlx", derive(sqlx::Type))]
+#[cfg_attr(feature = "sqlx", sqlx(type_name = "group_visibility", rename_all = "lowercase"))]
+#[serde(rename_all = "lowercase")]
+pub enum GroupVisibility {
+    Public,
+    #[default]
+    Private,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CreateGroupPayload {
+    pub name: String,
+    #[serde(default)]
+    pub visibility: GroupVisibility,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct UpdateGroupVisibilityPayload {
+    pub visibility: GroupVisibility,
+}
+
+/// Corpo di `PATCH /groups/:group_id`: cambiano solo i campi presenti, e una descrizione o un argomento
+/// vuoti vengono tolti. Per un nuovo avatar la richiesta va inviata come multipart, con gli stessi
+/// campi in forma di testo e l'immagine nel campo `avatar`.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct UpdateGroupPayload {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub topic: Option<String>,
+    #[serde(default)]
+    pub remove_avatar: bool,
+}
+
+/// Lunghezze massime, in caratteri, di descrizione e argomento di un gruppo.
+pub const MAX_GROUP_DESCRIPTION_CHARS: usize = 500;
+pub const MAX_GROUP_TOPIC_CHARS: usize = 120;
+
+/// Dimensione massima dell'avatar di un gruppo o di un utente, in byte.
+pub const MAX_AVATAR_SIZE: u64 = 1024 * 1024;
+
+/// Tipi MIME accettati per gli avatar.
+pub const ALLOWED_AVATAR_TYPES: &[&str] = &["image/png", "image/jpeg", "image/gif", "image/webp"];
+
+/// Campo del profilo di un gruppo, salvato in `group_profile_changes.field`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "sqlx", derive(sqlx::Type))]
+#[cfg_attr(feature = "sqlx", sqlx(type_name = "group_profile_field", rename_all = "lowercase"))]
+#[serde(rename_all = "lowercase")]
+pub enum GroupProfileField {
+    Name,
+    Description,
+    Topic,
+    Avatar,
+}
+
+/// Una modifica del profilo del gruppo, da `GET /groups/:group_id/history`. Per l'avatar
+/// i valori sono gli SHA-256 delle immagini; `None` indica un campo vuoto.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GroupProfileChange {
+    pub field: GroupProfileField,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub old_value: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub new_value: Option<String>,
+    /// Assente se l'utente non esiste più.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub changed_by_username: Option<String>,
+    #[serde(with = "time::serde::rfc3339")]
+    pub created_at: OffsetDateTime,
+}
+
+/// Ruolo di un utente all'interno di un gruppo, salvato in `group_members.role`.
+/// L'ordine delle varianti conta (Member < Admin < Owner): ogni ruolo include i permessi dei ruoli inferiori.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "sqlx", derive(sqlx::Type))]
+#[cfg_attr(feature = "sqlx", sqlx(type_name = "group_role", rename_all = "lowercase"))]
+#[serde(rename_all = "lowercase")]
+pub enum GroupRole {
+    Member,
+    Admin,
+    Owner,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GroupMember {
+    pub id: Uuid,
+    pub username: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub display_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status_message: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub avatar_sha256: Option<String>,
+    pub role: GroupRole,
+    /// Ultimo messaggio del gruppo letto dal membro.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_read_message_id: Option<Uuid>,
+    #[serde(default)]
+    pub presence: PresenceStatus,
+    /// Ultima disconnessione, assente se l'utente non si è mai collegato.
+    #[serde(default, with = "time::serde::rfc3339::option", skip_serializing_if = "Option::is_none")]
+    pub last_seen_at: Option<OffsetDateTime>,
+}
+
+impl GroupMember {
+    /// Nome da mostrare: quello scelto dal membro, altrimenti lo username.
+    pub fn name(&self) -> &str {
+        self.display_name.as_deref().unwrap_or(&self.username)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct UpdateMemberRolePayload {
+    pub role: GroupRole,
+}
+
+/// Corpo di `POST /groups/:group_id/transfer_ownership`: il nuovo proprietario deve essere già membro.
+#[derive(Serialize, Deserialize)]
+pub struct TransferOwnershipPayload {
+    pub new_owner_id: Uuid,
+}
+
+/// Gruppi e conversazioni dirette dell'utente, restituiti da `GET /users/me/groups`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct UserGroups {
+    pub groups: Vec<Group>,
+    pub direct_conversations: Vec<DirectConversation>,
+    pub unread: Vec<UnreadCount>,
+}
+
+// --- Elenco dei gruppi pubblici ---
+
+/// Parametri di `GET /groups/directory`: senza `q` elenca tutti i gruppi pubblici.
+#[derive(Serialize, Deserialize, Default)]
+pub struct GroupDirectoryQuery {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub q: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub offset: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub limit: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DirectoryGroup {
+    pub id: Uuid,
+    pub name: String,
+    pub member_count: u32,
+    /// Il chiamante ne fa già parte.
+    pub is_member: bool,
+    #[serde(with = "time::serde::rfc3339")]
+    pub created_at: OffsetDateTime,
+}
+
+/// Gruppi dal più numeroso al meno numeroso.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DirectoryPage {
+    pub groups: Vec<DirectoryGroup>,
+    pub has_more: bool,
+}
+
+/// Richiesta di `username` di entrare in un gruppo privato, in attesa che un admin la gestisca.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct JoinRequest {
+    pub id: Uuid,
+    pub group_id: Uuid,
+    pub group_name: String,
+    pub user_id: Uuid,
+    pub username: String,
+    #[serde(with = "time::serde::rfc3339")]
+    pub created_at: OffsetDateTime,
+}
+
+// --- Conferme di lettura ---
+
+/// Messaggi non letti di un gruppo (o conversazione diretta), esclusi quelli scritti dall'utente.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct UnreadCount {
+    pub group_id: Uuid,
+    pub unread_count: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_read_message_id: Option<Uuid>,
+}
+
+/// Corpo di `PUT /groups/:group_id/read`: la posizione di lettura avanza fino al messaggio indicato.
+#[derive(Serialize, Deserialize)]
+pub struct MarkReadPayload {
+    pub message_id: Uuid,
+}
+
+// --- Conversazioni dirette ---
+
+/// Conversazione privata con `peer_*`. L'id è quello del canale sottostante:
+/// cronologia, ricerca e comandi WebSocket lo usano come l'id di un gruppo.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct DirectConversation {
+    pub id: Uuid,
+    pub peer_id: Uuid,
+    pub peer_username: String,
+    #[serde(with = "time::serde::rfc3339")]
+    pub created_at: OffsetDateTime,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SendDirectMessagePayload {
+    pub content: String,
+}
+
+/// Risposta a `POST /direct/:user_id/messages`: la conversazione (creata se non esisteva) e il messaggio salvato.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DirectMessageSent {
+    pub conversation: DirectConversation,
+    pub message: WsServerMessage,
+}
+
+// --- Inviti ---
+
+#[derive(Serialize, Deserialize)]
+pub struct InviteToGroupPayload {
+    pub user_to_invite_id: Uuid,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Invitation {
+    pub id: Uuid,
+    pub group_id: Uuid,
+    pub group_name: String,
+    pub inviter_username: String,
+}
+
+// --- Link di invito ---
+
+/// Lunghezza dei codici di invito generati dal server.
+pub const INVITE_CODE_LENGTH: usize = 10;
+
+/// Corpo di `POST /groups/:group_id/invite_links`. Senza limiti il codice vale finché non viene revocato.
+#[derive(Serialize, Deserialize, Default)]
+pub struct CreateInviteLinkPayload {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_in_hours: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_uses: Option<u32>,
+}
+
+/// Codice che fa entrare nel gruppo chi lo usa con `POST /invites/:code/join`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct InviteLink {
+    pub code: String,
+    pub group_id: Uuid,
+    pub created_by_username: String,
+    #[serde(with = "time::serde::rfc3339")]
+    pub created_at: OffsetDateTime,
+    #[serde(default, with = "time::serde::rfc3339::option", skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<OffsetDateTime>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_uses: Option<u32>,
+    pub uses: u32,
+}
+
+impl InviteLink {
+    /// Scaduto o esaurito: il codice non fa più entrare nessuno.
+    pub fn is_usable(&self, now: OffsetDateTime) -> bool {
+        self.expires_at.is_none_or(|at| at > now) && self.max_uses.is_none_or(|max| self.uses < max)
+    }
+}
+
+// --- Messaggi ---
+
+/// Parametri di `GET /groups/:group_id/messages`. Senza cursori restituisce i messaggi più recenti;
+/// `before` e `after` (id di un messaggio, alternativi tra loro) scorrono la cronologia.
+#[derive(Serialize, Deserialize, Default)]
+pub struct MessageHistoryQuery {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub before: Option<Uuid>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub after: Option<Uuid>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub limit: Option<u32>,
+}
+
+/// Una pagina della cronologia, sempre dal messaggio più vecchio al più recente.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MessagePage {
+    pub messages: Vec<WsServerMessage>,
+    /// Ci sono altri messaggi oltre la pagina, nella direzione richiesta.
+    pub has_more: bool,
+}
+
+/// Caratteri del messaggio citato mostrati nell'anteprima di una risposta.
+pub const REPLY_PREVIEW_CHARS: usize = 120;
+
+/// Risposta a `GET /groups/:group_id/messages/:message_id/thread`: il messaggio e tutte le sue risposte dirette,
+/// dalla più vecchia alla più recente.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MessageThread {
+    pub root: WsServerMessage,
+    pub replies: Vec<WsServerMessage>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct EditMessagePayload {
+    pub content: String,
+}
+
+// --- Allegati ---
+
+/// Dimensione massima di un allegato, in byte.
+pub const MAX_ATTACHMENT_SIZE: u64 = 10 * 1024 * 1024;
+
+/// Tipi MIME accettati da `POST /groups/:group_id/attachments`.
+pub const ALLOWED_ATTACHMENT_TYPES: &[&str] = &[
+    "image/png",
+    "image/jpeg",
+    "image/gif",
+    "image/webp",
+    "application/pdf",
+    "application/zip",
+    "text/plain",
+];
+
+// --- Reazioni ---
+
+/// Lunghezza massima, in caratteri, di una reazione: basta per le emoji composte (bandiere, famiglie...).
+pub const MAX_REACTION_CHARS: usize = 16;
+
+/// Una reazione è un'emoji: non vuota, corta, senza spazi né lettere o cifre ASCII.
+pub fn is_valid_reaction(emoji: &str) -> bool {
+    !emoji.is_empty()
+        && emoji.chars().count() <= MAX_REACTION_CHARS
+        && !emoji.chars().any(|c| c.is_whitespace() || c.is_control() || c.is_ascii_alphanumeric())
+}
+
+// --- Menzioni ---
+
+/// Un messaggio che menziona l'utente, con il gruppo in cui è stato scritto.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Mention {
+    pub group_id: Uuid,
+    pub group_name: String,
+    pub message: WsServerMessage,
+}
+
+/// Parametri di `GET /users/me/mentions`.
+#[derive(Serialize, Deserialize, Default)]
+pub struct MentionQuery {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub offset: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub limit: Option<u32>,
+}
+
+/// Menzioni dalla più recente alla più vecchia.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MentionPage {
+    pub mentions: Vec<Mention>,
+    pub has_more: bool,
+}
+
+/// Trova le menzioni `@username` nel testo: posizione in byte (chiocciola compresa) e nome citato.
+/// La chiocciola deve aprire una parola, così gli indirizzi email non contano; il punto finale
+/// di una frase non fa parte del nome.
+pub fn find_mentions(content: &str) -> Vec<(Range<usize>, &str)> {
+    let is_name_char = |c: char| c.is_alphanumeric() || matches!(c, '_' | '-' | '.');
+    let mut mentions = Vec::new();
+    let mut prev: Option<char> = None;
+    for (start, c) in content.char_indices() {
+        let opens_word = prev.is_none_or(|p| !is_name_char(p) && p != '@');
+        prev = Some(c);
+        if c != '@' || !opens_word {
+            continue;
+        }
+        let rest = &content[start + 1..];
+        let len = rest.find(|c: char| !is_name_char(c)).unwrap_or(rest.len());
+        let name = rest[..len].trim_end_matches('.');
+        if !name.is_empty() {
+            mentions.push((start..start + 1 + name.len(), name));
+        }
+    }
+    mentions
+}
+
+// --- Ricerca ---
+
+/// Parametri della ricerca: ogni parola di `q` trova anche le parole che iniziano così.
+#[derive(Serialize, Deserialize)]
+pub struct MessageSearchQuery {
+    pub q: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub offset: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub limit: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SearchHit {
+    pub group_id: Uuid,
+    pub group_name: String,
+    pub message: WsServerMessage,
+    /// Estratto del messaggio, in testo semplice.
+    pub snippet: String,
+    /// Posizioni in byte, dentro `snippet`, dei termini trovati.
+    #[serde(default)]
+    pub highlights: Vec<Range<usize>>,
+}
+
+/// Risultati dal più recente al più vecchio.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SearchPage {
+    pub hits: Vec<SearchHit>,
+    pub has_more: bool,
+}
@@ -0,0 +1,327 @@
+use crate::{
+    DirectConversation, Group, Invitation, JoinRequest, Mention, User, REPLY_PREVIEW_CHARS,
+};
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+/// Versione più recente del protocollo WebSocket.
+pub const WS_PROTOCOL_VERSION: u32 = 1;
+/// Versione più vecchia ancora accettata dal server: i client che non la indicano partono da qui.
+pub const WS_MIN_PROTOCOL_VERSION: u32 = 1;
+
+/// Intervallo minimo tra due `Typing` dello stesso utente nello stesso gruppo: il server scarta quelli più ravvicinati.
+pub const TYPING_MIN_INTERVAL_MS: u64 = 2_000;
+/// Senza nuovi `Typing` entro questo tempo, l'utente non sta più scrivendo.
+pub const TYPING_EXPIRY_MS: u64 = 5_000;
+
+/// Codice di chiusura inviato a chi viene rimosso o bannato da un gruppo.
+pub const CLOSE_REMOVED_FROM_GROUP: u16 = 4003;
+/// Codice di chiusura inviato a tutte le connessioni di un gruppo eliminato dal proprietario.
+pub const CLOSE_GROUP_DELETED: u16 = 4004;
+/// Codice di chiusura inviato alle connessioni aperte con una sessione revocata (es. dopo un cambio password).
+pub const CLOSE_SESSION_REVOKED: u16 = 4005;
+
+/// Comandi inviati dal client sulla chat di un gruppo, distinti dal campo `type`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum WsClientCommand {
+    SendMessage {
+        content: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        reply_to: Option<Uuid>,
+    },
+    Typing,
+}
+
+/// File allegato a un messaggio, scaricabile da `GET /groups/:group_id/attachments/:id`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Attachment {
+    pub id: Uuid,
+    pub file_name: String,
+    pub mime_type: String,
+    pub size: u64,
+}
+
+impl Attachment {
+    pub fn is_image(&self) -> bool {
+        self.mime_type.starts_with("image/")
+    }
+}
+
+/// Reazioni con la stessa emoji a un messaggio, nell'ordine in cui sono state aggiunte.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Reaction {
+    pub emoji: String,
+    pub count: u32,
+    pub user_ids: Vec<Uuid>,
+}
+
+/// Messaggio citato da una risposta: `content` è troncato e vuoto se l'originale è stato eliminato.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ReplyPreview {
+    pub message_id: Uuid,
+    pub sender_username: String,
+    pub content: String,
+    pub deleted: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WsServerMessage {
+    pub id: Uuid,
+    pub sender_id: Uuid,
+    pub sender_username: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sender_display_name: Option<String>,
+    pub content: String,
+    #[serde(with = "time::serde::rfc3339")]
+    pub created_at: OffsetDateTime,
+    #[serde(with = "time::serde::rfc3339::option")]
+    pub edited_at: Option<OffsetDateTime>,
+    pub deleted: bool, // Se true, `content` è vuoto
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub attachments: Vec<Attachment>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub reactions: Vec<Reaction>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reply_to: Option<Box<ReplyPreview>>,
+}
+
+impl WsServerMessage {
+    /// Nome del mittente da mostrare: quello scelto, altrimenti lo username.
+    pub fn sender_name(&self) -> &str {
+        self.sender_display_name.as_deref().unwrap_or(&self.sender_username)
+    }
+
+    /// Anteprima con cui il messaggio compare citato nelle risposte.
+    pub fn preview(&self) -> ReplyPreview {
+        ReplyPreview {
+            message_id: self.id,
+            sender_username: self.sender_username.clone(),
+            content: if self.deleted { String::new() } else { self.content.chars().take(REPLY_PREVIEW_CHARS).collect() },
+            deleted: self.deleted,
+        }
+    }
+
+    /// Registra la reazione di `user_id`; non fa nulla se c'era già.
+    pub fn add_reaction(&mut self, user_id: Uuid, emoji: &str) {
+        match self.reactions.iter_mut().find(|r| r.emoji == emoji) {
+            Some(reaction) if reaction.user_ids.contains(&user_id) => {}
+            Some(reaction) => {
+                reaction.user_ids.push(user_id);
+                reaction.count += 1;
+            }
+            None => self.reactions.push(Reaction { emoji: emoji.to_string(), count: 1, user_ids: vec![user_id] }),
+        }
+    }
+
+    /// Toglie la reazione di `user_id`, eliminando l'emoji quando non resta nessuno.
+    pub fn remove_reaction(&mut self, user_id: Uuid, emoji: &str) {
+        if let Some(reaction) = self.reactions.iter_mut().find(|r| r.emoji == emoji) {
+            if let Some(pos) = reaction.user_ids.iter().position(|id| *id == user_id) {
+                reaction.user_ids.remove(pos);
+                reaction.count -= 1;
+            }
+        }
+        self.reactions.retain(|r| r.count > 0);
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum MemberLeftReason {
+    Left,
+    Kicked,
+    Banned,
+}
+
+/// Stato di un utente: è `Online` finché ha almeno una connessione aperta, `Away` se il client lo segnala inattivo.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum PresenceStatus {
+    Online,
+    Away,
+    #[default]
+    Offline,
+}
+
+/// Codici macchina per gli errori segnalati sul WebSocket.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum WsErrorCode {
+    UnsupportedProtocol,
+    InvalidCommand,
+    InvalidMessage,
+    NotGroupMember,
+    Internal,
+}
+
+/// Eventi inviati dal server sulla chat di un gruppo, distinti dal campo `type`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum WsServerEvent {
+    /// Primo evento di ogni connessione: conferma la versione di protocollo negoziata.
+    Welcome {
+        protocol_version: u32,
+        group_id: Uuid,
+        user_id: Uuid,
+    },
+    Message(WsServerMessage),
+    MemberJoined {
+        user_id: Uuid,
+        username: String,
+    },
+    MemberLeft {
+        user_id: Uuid,
+        username: String,
+        reason: MemberLeftReason,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        by_username: Option<String>,
+    },
+    /// Il proprietario ha ceduto il gruppo, o è uscito e il gruppo è passato a un altro membro.
+    /// Il vecchio proprietario, se resta nel gruppo, diventa admin.
+    OwnershipTransferred {
+        previous_owner_id: Uuid,
+        new_owner_id: Uuid,
+        new_owner_username: String,
+    },
+    /// Il proprietario ha eliminato il gruppo: è l'ultimo evento, poi le connessioni vengono chiuse.
+    GroupDeleted {
+        by_username: String,
+    },
+    /// Il profilo del gruppo (nome, descrizione, argomento o avatar) è cambiato.
+    GroupUpdated {
+        group: Group,
+        by_username: String,
+    },
+    MessageEdited {
+        message_id: Uuid,
+        content: String,
+        #[serde(with = "time::serde::rfc3339")]
+        edited_at: OffsetDateTime,
+    },
+    MessageDeleted {
+        message_id: Uuid,
+    },
+    ReactionAdded {
+        message_id: Uuid,
+        user_id: Uuid,
+        emoji: String,
+    },
+    ReactionRemoved {
+        message_id: Uuid,
+        user_id: Uuid,
+        emoji: String,
+    },
+    /// `user_id` ha letto il gruppo fino a `message_id` compreso.
+    ReadUpTo {
+        user_id: Uuid,
+        message_id: Uuid,
+    },
+    /// Effimero, non viene salvato: scade dopo `TYPING_EXPIRY_MS` se non arriva di nuovo.
+    Typing {
+        user_id: Uuid,
+        username: String,
+    },
+    /// Cambio di stato dell'utente, inviato a tutti i suoi gruppi. Con `Offline` indica anche quando si è disconnesso.
+    Presence {
+        user_id: Uuid,
+        status: PresenceStatus,
+        #[serde(default, with = "time::serde::rfc3339::option", skip_serializing_if = "Option::is_none")]
+        last_seen_at: Option<OffsetDateTime>,
+    },
+    /// Il profilo di un membro è cambiato (nome visualizzato, stato, biografia o avatar); inviato a tutti i suoi gruppi.
+    UserUpdated {
+        user: User,
+    },
+    Error {
+        code: WsErrorCode,
+        message: String,
+    },
+}
+
+// --- Connessione multiplexata `/ws` ---
+
+/// Comandi inviati sulla connessione unica `/ws`: ogni comando di chat indica il gruppo a cui si riferisce.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum WsMuxCommand {
+    /// Con `after` (ultimo messaggio visto dal client) il server invia anche i messaggi persi.
+    Subscribe {
+        group_id: Uuid,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        after: Option<Uuid>,
+    },
+    Unsubscribe { group_id: Uuid },
+    /// `reply_to` è l'id di un messaggio dello stesso gruppo.
+    SendMessage {
+        group_id: Uuid,
+        content: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        reply_to: Option<Uuid>,
+    },
+    Typing { group_id: Uuid },
+    /// L'utente è inattivo (`away: true`) o è tornato: vale per tutte le sue connessioni.
+    SetAway { away: bool },
+}
+
+/// Eventi inviati dal server sulla connessione unica `/ws`, distinti dal campo `type`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum WsMuxEvent {
+    /// Primo evento della connessione: elenca i gruppi a cui è già iscritta (tutti quelli dell'utente).
+    Welcome {
+        protocol_version: u32,
+        user_id: Uuid,
+        groups: Vec<Uuid>,
+    },
+    Subscribed {
+        group_id: Uuid,
+    },
+    /// La connessione non riceve più eventi del gruppo: su richiesta, o perché l'utente non ne fa più parte.
+    Unsubscribed {
+        group_id: Uuid,
+    },
+    /// Messaggi inviati dopo il cursore di `Subscribe`, dal più vecchio al più recente.
+    /// `has_more` indica che ne mancano altri (o che il cursore non è valido): il client deve ricaricare la cronologia.
+    Replay {
+        group_id: Uuid,
+        messages: Vec<WsServerMessage>,
+        has_more: bool,
+    },
+    /// Evento di un gruppo a cui la connessione è iscritta.
+    Group {
+        group_id: Uuid,
+        event: WsServerEvent,
+    },
+    InvitationReceived(Invitation),
+    /// L'invitato ha accettato o rifiutato un invito mandato dall'utente.
+    InvitationAnswered {
+        invitation_id: Uuid,
+        group_id: Uuid,
+        group_name: String,
+        invited_username: String,
+        accepted: bool,
+    },
+    /// Agli admin del gruppo: qualcuno chiede di entrare.
+    JoinRequestReceived(JoinRequest),
+    /// La richiesta di ingresso dell'utente è stata approvata: la connessione è già iscritta al gruppo.
+    JoinRequestApproved(Group),
+    JoinRequestRejected {
+        group_id: Uuid,
+        group_name: String,
+    },
+    /// Un messaggio cita l'utente: arriva anche se la connessione non sta guardando quel gruppo.
+    Mentioned(Mention),
+    /// Primo messaggio di una nuova conversazione diretta: la connessione è già iscritta al suo canale.
+    DirectConversationStarted {
+        conversation: DirectConversation,
+        message: WsServerMessage,
+    },
+    Error {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        group_id: Option<Uuid>,
+        code: WsErrorCode,
+        message: String,
+    },
+}
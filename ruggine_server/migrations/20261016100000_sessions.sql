@@ -0,0 +1,28 @@
+-- =========================================================
+-- Sessioni e refresh token
+-- Ogni login crea una sessione; l'access token JWT ne porta l'id (sid)
+-- così che revocare la sessione invalidi anche i token già emessi.
+-- =========================================================
+
+PRAGMA foreign_keys = ON;
+
+-- ---------------------------------------------------------
+-- Tabella: sessions
+-- ---------------------------------------------------------
+CREATE TABLE IF NOT EXISTS sessions (
+    id BLOB NOT NULL PRIMARY KEY
+    DEFAULT (randomblob(16)),
+
+    user_id             TEXT NOT NULL,
+    -- SHA-256 (hex) del refresh token corrente: il token in chiaro non viene mai salvato
+    refresh_token_hash  TEXT NOT NULL UNIQUE,
+    -- Hash del token sostituito dall'ultima rotazione, per rilevarne il riuso
+    previous_token_hash TEXT,
+    expires_at          TEXT NOT NULL,
+    revoked_at          TEXT,
+    created_at          TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%SZ','now')),
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_sessions_user     ON sessions(user_id);
+CREATE INDEX IF NOT EXISTS idx_sessions_previous ON sessions(previous_token_hash);
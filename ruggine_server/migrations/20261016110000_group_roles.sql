@@ -0,0 +1,14 @@
+-- =========================================================
+-- Ruoli nei gruppi: owner, admin, member
+-- =========================================================
+
+PRAGMA foreign_keys = ON;
+
+ALTER TABLE group_members ADD COLUMN role TEXT NOT NULL DEFAULT 'member'
+    CHECK (role IN ('owner','admin','member'));
+
+-- I gruppi esistenti non hanno un proprietario: lo diventa il primo membro inserito
+UPDATE group_members SET role = 'owner'
+WHERE rowid IN (SELECT MIN(rowid) FROM group_members GROUP BY group_id);
+
+CREATE INDEX IF NOT EXISTS idx_group_members_group_role ON group_members(group_id, role);
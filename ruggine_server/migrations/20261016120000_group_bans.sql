@@ -0,0 +1,23 @@
+-- =========================================================
+-- Ban dai gruppi
+-- Un utente bannato non può essere invitato né accettare inviti
+-- finché un admin non rimuove il ban.
+-- =========================================================
+
+PRAGMA foreign_keys = ON;
+
+-- ---------------------------------------------------------
+-- Tabella: group_bans
+-- ---------------------------------------------------------
+CREATE TABLE IF NOT EXISTS group_bans (
+    group_id   TEXT NOT NULL,
+    user_id    TEXT NOT NULL,
+    banned_by  TEXT,
+    created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%SZ','now')),
+    PRIMARY KEY (group_id, user_id),
+    FOREIGN KEY (group_id)  REFERENCES groups(id) ON DELETE CASCADE,
+    FOREIGN KEY (user_id)   REFERENCES users(id)  ON DELETE CASCADE,
+    FOREIGN KEY (banned_by) REFERENCES users(id)  ON DELETE SET NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_group_bans_user ON group_bans(user_id);
@@ -0,0 +1,9 @@
+-- =========================================================
+-- Modifica ed eliminazione dei messaggi
+-- L'eliminazione è logica: la riga resta per non spezzare la cronologia.
+-- =========================================================
+
+PRAGMA foreign_keys = ON;
+
+ALTER TABLE group_messages ADD COLUMN edited_at  TEXT;
+ALTER TABLE group_messages ADD COLUMN deleted_at TEXT;
@@ -0,0 +1,38 @@
+-- =========================================================
+-- Ricerca full-text sui messaggi (FTS5)
+-- Indice "external content": il testo resta in group_messages,
+-- collegato tramite rowid e aggiornato dai trigger qui sotto.
+-- I messaggi eliminati escono dall'indice.
+-- =========================================================
+
+PRAGMA foreign_keys = ON;
+
+CREATE VIRTUAL TABLE IF NOT EXISTS group_messages_fts USING fts5(
+    content,
+    content = 'group_messages',
+    content_rowid = 'rowid',
+    tokenize = 'unicode61 remove_diacritics 2'
+);
+
+-- Un 'delete' su una riga mai indicizzata corromperebbe l'indice:
+-- per questo ogni rimozione è condizionata a deleted_at IS NULL.
+CREATE TRIGGER IF NOT EXISTS group_messages_fts_insert AFTER INSERT ON group_messages BEGIN
+    INSERT INTO group_messages_fts (rowid, content)
+    SELECT new.rowid, new.content WHERE new.deleted_at IS NULL;
+END;
+
+CREATE TRIGGER IF NOT EXISTS group_messages_fts_delete AFTER DELETE ON group_messages BEGIN
+    INSERT INTO group_messages_fts (group_messages_fts, rowid, content)
+    SELECT 'delete', old.rowid, old.content WHERE old.deleted_at IS NULL;
+END;
+
+CREATE TRIGGER IF NOT EXISTS group_messages_fts_update AFTER UPDATE OF content, deleted_at ON group_messages BEGIN
+    INSERT INTO group_messages_fts (group_messages_fts, rowid, content)
+    SELECT 'delete', old.rowid, old.content WHERE old.deleted_at IS NULL;
+    INSERT INTO group_messages_fts (rowid, content)
+    SELECT new.rowid, new.content WHERE new.deleted_at IS NULL;
+END;
+
+-- Indicizza i messaggi già presenti
+INSERT INTO group_messages_fts (rowid, content)
+SELECT rowid, content FROM group_messages WHERE deleted_at IS NULL;
@@ -0,0 +1,24 @@
+-- =========================================================
+-- Conversazioni dirette tra due utenti
+-- Una conversazione è un gruppo senza nome con esattamente due membri:
+-- cronologia, ricerca e WebSocket restano quelli dei gruppi.
+-- La coppia è salvata ordinata (user_a < user_b), così è unica.
+-- =========================================================
+
+PRAGMA foreign_keys = ON;
+
+-- ---------------------------------------------------------
+-- Tabella: direct_conversations
+-- ---------------------------------------------------------
+CREATE TABLE IF NOT EXISTS direct_conversations (
+    group_id TEXT NOT NULL PRIMARY KEY,
+    user_a   TEXT NOT NULL,
+    user_b   TEXT NOT NULL,
+    UNIQUE (user_a, user_b),
+    CHECK (user_a < user_b),
+    FOREIGN KEY (group_id) REFERENCES groups(id) ON DELETE CASCADE,
+    FOREIGN KEY (user_a)   REFERENCES users(id)  ON DELETE CASCADE,
+    FOREIGN KEY (user_b)   REFERENCES users(id)  ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_direct_conversations_user_b ON direct_conversations(user_b);
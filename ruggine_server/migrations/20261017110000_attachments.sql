@@ -0,0 +1,26 @@
+-- =========================================================
+-- Allegati dei messaggi
+-- I file stanno su disco in ATTACHMENTS_DIR, indirizzati dal loro SHA-256:
+-- qui restano solo i metadati e il collegamento al messaggio.
+-- =========================================================
+
+PRAGMA foreign_keys = ON;
+
+-- ---------------------------------------------------------
+-- Tabella: attachments
+-- ---------------------------------------------------------
+CREATE TABLE IF NOT EXISTS attachments (
+    id BLOB NOT NULL PRIMARY KEY
+    DEFAULT (randomblob(16)),
+
+    message_id TEXT NOT NULL,
+    -- Nome del file su disco; più allegati possono condividere lo stesso contenuto
+    sha256     TEXT NOT NULL,
+    file_name  TEXT NOT NULL,
+    mime_type  TEXT NOT NULL,
+    size       INTEGER NOT NULL CHECK (size > 0),
+    created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%SZ','now')),
+    FOREIGN KEY (message_id) REFERENCES group_messages(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_attachments_message ON attachments(message_id);
@@ -0,0 +1,20 @@
+-- =========================================================
+-- Reazioni ai messaggi
+-- Ogni utente può reagire a un messaggio con più emoji diverse,
+-- ma con ciascuna una sola volta.
+-- =========================================================
+
+PRAGMA foreign_keys = ON;
+
+-- ---------------------------------------------------------
+-- Tabella: message_reactions
+-- ---------------------------------------------------------
+CREATE TABLE IF NOT EXISTS message_reactions (
+    message_id TEXT NOT NULL,
+    user_id    TEXT NOT NULL,
+    emoji      TEXT NOT NULL CHECK (length(emoji) > 0),
+    created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%SZ','now')),
+    PRIMARY KEY (message_id, user_id, emoji),
+    FOREIGN KEY (message_id) REFERENCES group_messages(id) ON DELETE CASCADE,
+    FOREIGN KEY (user_id)    REFERENCES users(id)          ON DELETE CASCADE
+);
@@ -0,0 +1,11 @@
+-- =========================================================
+-- Risposte ai messaggi
+-- reply_to punta a un messaggio dello stesso gruppo (verificato dal server).
+-- Le risposte a un messaggio formano la sua discussione.
+-- =========================================================
+
+PRAGMA foreign_keys = ON;
+
+ALTER TABLE group_messages ADD COLUMN reply_to TEXT REFERENCES group_messages(id) ON DELETE SET NULL;
+
+CREATE INDEX IF NOT EXISTS idx_group_messages_reply_to ON group_messages(reply_to);
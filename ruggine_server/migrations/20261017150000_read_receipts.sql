@@ -0,0 +1,9 @@
+-- =========================================================
+-- Conferme di lettura
+-- Ogni membro ha una posizione di lettura nel gruppo: i messaggi
+-- successivi (scritti da altri) sono i suoi non letti.
+-- =========================================================
+
+PRAGMA foreign_keys = ON;
+
+ALTER TABLE group_members ADD COLUMN last_read_message_id TEXT REFERENCES group_messages(id) ON DELETE SET NULL;
@@ -0,0 +1,9 @@
+-- =========================================================
+-- Presenza degli utenti
+-- Lo stato online/assente vive solo in memoria: su disco resta
+-- l'ultima disconnessione, mostrata come "visto l'ultima volta".
+-- =========================================================
+
+PRAGMA foreign_keys = ON;
+
+ALTER TABLE users ADD COLUMN last_seen_at TEXT;
@@ -0,0 +1,25 @@
+-- =========================================================
+-- Link di invito
+-- Un codice creato da un admin fa entrare nel gruppo chiunque lo usi,
+-- fino alla scadenza o al numero massimo di utilizzi (se indicati).
+-- La revoca elimina il codice.
+-- =========================================================
+
+PRAGMA foreign_keys = ON;
+
+-- ---------------------------------------------------------
+-- Tabella: group_invite_links
+-- ---------------------------------------------------------
+CREATE TABLE IF NOT EXISTS group_invite_links (
+    code       TEXT    NOT NULL PRIMARY KEY,
+    group_id   TEXT    NOT NULL,
+    created_by TEXT    NOT NULL,
+    expires_at TEXT,
+    max_uses   INTEGER CHECK (max_uses IS NULL OR max_uses > 0),
+    uses       INTEGER NOT NULL DEFAULT 0,
+    created_at TEXT    NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%SZ','now')),
+    FOREIGN KEY (group_id)   REFERENCES groups(id) ON DELETE CASCADE,
+    FOREIGN KEY (created_by) REFERENCES users(id)  ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_group_invite_links_group ON group_invite_links(group_id);
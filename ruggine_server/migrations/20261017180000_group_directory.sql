@@ -0,0 +1,31 @@
+-- =========================================================
+-- Elenco pubblico dei gruppi e richieste di ingresso
+-- I gruppi pubblici compaiono nell'elenco e chiunque può entrarci;
+-- in quelli privati si entra su invito, con un link di invito
+-- o chiedendo a un admin, che approva o rifiuta la richiesta.
+-- =========================================================
+
+PRAGMA foreign_keys = ON;
+
+ALTER TABLE groups ADD COLUMN visibility TEXT NOT NULL DEFAULT 'private'
+    CHECK (visibility IN ('public','private'));
+
+CREATE INDEX IF NOT EXISTS idx_groups_visibility ON groups(visibility, name);
+
+-- ---------------------------------------------------------
+-- Tabella: group_join_requests
+-- Solo le richieste in attesa: approvazione e rifiuto le eliminano.
+-- ---------------------------------------------------------
+CREATE TABLE IF NOT EXISTS group_join_requests (
+    id BLOB NOT NULL PRIMARY KEY
+    DEFAULT (randomblob(16)),
+
+    group_id   TEXT NOT NULL,
+    user_id    TEXT NOT NULL,
+    created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%SZ','now')),
+    UNIQUE (group_id, user_id),
+    FOREIGN KEY (group_id) REFERENCES groups(id) ON DELETE CASCADE,
+    FOREIGN KEY (user_id)  REFERENCES users(id)  ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_group_join_requests_user ON group_join_requests(user_id);
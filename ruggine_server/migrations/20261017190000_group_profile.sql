@@ -0,0 +1,33 @@
+-- =========================================================
+-- Profilo dei gruppi: descrizione, argomento e avatar
+-- L'avatar è salvato come gli allegati (per SHA-256 nella cartella
+-- degli allegati). Ogni modifica del profilo resta nella cronologia.
+-- =========================================================
+
+PRAGMA foreign_keys = ON;
+
+ALTER TABLE groups ADD COLUMN description      TEXT;
+ALTER TABLE groups ADD COLUMN topic            TEXT;
+ALTER TABLE groups ADD COLUMN avatar_sha256    TEXT;
+ALTER TABLE groups ADD COLUMN avatar_mime_type TEXT;
+
+-- ---------------------------------------------------------
+-- Tabella: group_profile_changes
+-- Per l'avatar i valori sono gli SHA-256 delle immagini.
+-- ---------------------------------------------------------
+CREATE TABLE IF NOT EXISTS group_profile_changes (
+    id BLOB NOT NULL PRIMARY KEY
+    DEFAULT (randomblob(16)),
+
+    group_id   TEXT NOT NULL,
+    changed_by TEXT,
+    field      TEXT NOT NULL
+        CHECK (field IN ('name','description','topic','avatar')),
+    old_value  TEXT,
+    new_value  TEXT,
+    created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%SZ','now')),
+    FOREIGN KEY (group_id)   REFERENCES groups(id) ON DELETE CASCADE,
+    FOREIGN KEY (changed_by) REFERENCES users(id)  ON DELETE SET NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_group_profile_changes_group ON group_profile_changes(group_id, created_at);
@@ -0,0 +1,13 @@
+-- =========================================================
+-- Profilo degli utenti: nome visualizzato, biografia, stato
+-- personalizzato e avatar. L'avatar è salvato come gli allegati
+-- (per SHA-256 nella cartella degli allegati).
+-- =========================================================
+
+PRAGMA foreign_keys = ON;
+
+ALTER TABLE users ADD COLUMN display_name     TEXT;
+ALTER TABLE users ADD COLUMN bio              TEXT;
+ALTER TABLE users ADD COLUMN status_message   TEXT;
+ALTER TABLE users ADD COLUMN avatar_sha256    TEXT;
+ALTER TABLE users ADD COLUMN avatar_mime_type TEXT;
@@ -0,0 +1,34 @@
+-- =========================================================
+-- Gestione dell'account: amministratori del server, token di
+-- reset della password ed eliminazione dell'account.
+-- Un account eliminato non viene cancellato da `users` (la FK di
+-- `group_messages` cancellerebbe i suoi messaggi): resta come
+-- utente anonimo, senza credenziali né profilo.
+-- =========================================================
+
+PRAGMA foreign_keys = ON;
+
+-- Gli amministratori possono emettere token di reset della password;
+-- si nominano direttamente nel database (UPDATE users SET is_admin = 1 ...)
+ALTER TABLE users ADD COLUMN is_admin   INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE users ADD COLUMN deleted_at TEXT;
+
+-- ---------------------------------------------------------
+-- Tabella: password_reset_tokens
+-- ---------------------------------------------------------
+CREATE TABLE IF NOT EXISTS password_reset_tokens (
+    id BLOB NOT NULL PRIMARY KEY
+    DEFAULT (randomblob(16)),
+
+    user_id    TEXT NOT NULL,
+    -- SHA-256 (hex) del token, come per i refresh token
+    token_hash TEXT NOT NULL UNIQUE,
+    created_by TEXT,
+    expires_at TEXT NOT NULL,
+    used_at    TEXT,
+    created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%SZ','now')),
+    FOREIGN KEY (user_id)    REFERENCES users(id) ON DELETE CASCADE,
+    FOREIGN KEY (created_by) REFERENCES users(id) ON DELETE SET NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_password_reset_tokens_user ON password_reset_tokens(user_id);
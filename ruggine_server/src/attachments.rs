@@ -0,0 +1,51 @@
+use ruggine_protocol::ALLOWED_ATTACHMENT_TYPES;
+use sha2::{Digest, Sha256};
+use std::io;
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+/// Cartella degli allegati usata se `ATTACHMENTS_DIR` non è impostata.
+pub const DEFAULT_ATTACHMENTS_DIR: &str = "attachments";
+
+pub fn is_allowed_mime_type(mime_type: &str) -> bool {
+    ALLOWED_ATTACHMENT_TYPES.contains(&mime_type)
+}
+
+/// Percorso del file con hash `sha256`: i primi due caratteri fanno da sottocartella,
+/// per non accumulare migliaia di file nella stessa directory.
+fn blob_path(dir: &Path, sha256: &str) -> PathBuf {
+    dir.join(&sha256[..2]).join(sha256)
+}
+
+/// Salva il contenuto indirizzandolo col suo SHA-256 (hex), che viene restituito.
+/// Lo stesso file caricato più volte occupa spazio una volta sola.
+pub async fn store(dir: &Path, bytes: &[u8]) -> io::Result<String> {
+    let sha256 = hex::encode(Sha256::digest(bytes));
+    let path = blob_path(dir, &sha256);
+    if tokio::fs::try_exists(&path).await? {
+        return Ok(sha256);
+    }
+
+    tokio::fs::create_dir_all(path.parent().unwrap_or(dir)).await?;
+    // Scrive su un file temporaneo e poi lo rinomina: un download concorrente non legge mai un file a metà
+    let tmp_path = path.with_extension(format!("tmp-{}", Uuid::new_v4()));
+    tokio::fs::write(&tmp_path, bytes).await?;
+    tokio::fs::rename(&tmp_path, &path).await?;
+    Ok(sha256)
+}
+
+pub async fn read(dir: &Path, sha256: &str) -> io::Result<Vec<u8>> {
+    tokio::fs::read(blob_path(dir, sha256)).await
+}
+
+/// Riduce il nome indicato dal client all'ultimo componente del percorso, senza caratteri di controllo.
+pub fn sanitize_file_name(name: &str) -> String {
+    let base = name.rsplit(['/', '\\']).next().unwrap_or_default();
+    let cleaned: String = base.chars().filter(|c| !c.is_control()).take(255).collect();
+    let cleaned = cleaned.trim();
+    if cleaned.is_empty() || cleaned == "." || cleaned == ".." {
+        "file".to_string()
+    } else {
+        cleaned.to_string()
+    }
+}
@@ -1,4 +1,8 @@
-use crate::{models::Claims, AppState};
+use crate::{
+    error::AppError,
+    models::{Claims, ErrorCode, ErrorResponse},
+    AppState,
+};
 use axum::{
     async_trait,
     extract::{FromRef, FromRequestParts},
@@ -10,14 +14,16 @@ use axum_extra::{
     headers::{authorization::Bearer, Authorization},
     TypedHeader,
 };
-use jsonwebtoken::{decode, DecodingKey, Validation};
-use serde::Serialize;
+use chrono::{Duration, Utc};
+use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
 
-/// Struct per una risposta di errore JSON standardizzata.
-#[derive(Serialize)]
-struct ErrorResponse {
-    message: String,
-}
+/// Durata dell'access token JWT: breve, perché il client lo rinnova col refresh token.
+pub const ACCESS_TOKEN_TTL_MINUTES: i64 = 15;
+/// Durata di una sessione (e del suo refresh token) dall'ultimo rinnovo.
+pub const SESSION_TTL_DAYS: i64 = 30;
 
 /// Implementazione dell'estrattore di Axum.
 /// Questo permette di usare `Claims` come parametro negli handler.
@@ -38,39 +44,99 @@ where
                 .await
                 .map_err(|_| AuthError::InvalidToken)?;
 
-        // 2. Ottiene la chiave segreta per il JWT dallo stato dell'applicazione
-        let secret = &AppState::from_ref(state).jwt_secret;
+        // 2. Valida firma, scadenza e sessione del token
+        validate_token(bearer.token(), &AppState::from_ref(state)).await
+    }
+}
 
-        // 3. Decodifica e valida il token
-        let token_data = decode::<Claims>(
-            bearer.token(),
-            &DecodingKey::from_secret(secret.as_ref()),
-            &Validation::default(),
-        )
-        .map_err(|_| AuthError::InvalidToken)?;
+/// Decodifica un access token e verifica che la sessione a cui appartiene sia ancora attiva.
+/// Usata sia dall'estrattore sia dalle rotte WebSocket, dove il token arriva in query string.
+pub async fn validate_token(token: &str, app_state: &AppState) -> Result<Claims, AuthError> {
+    let claims = decode::<Claims>(
+        token,
+        &DecodingKey::from_secret(app_state.jwt_secret.as_ref()),
+        &Validation::default(),
+    )
+    .map_err(|_| AuthError::InvalidToken)?
+    .claims;
 
-        // 4. Se la validazione ha successo, restituisce le "claims" (i dati dell'utente)
-        Ok(token_data.claims)
+    // Un token valido ma appartenente a una sessione revocata o scaduta viene rifiutato
+    let is_active: (bool,) = sqlx::query_as(
+        "SELECT EXISTS(SELECT 1 FROM sessions WHERE id = ? AND user_id = ? AND revoked_at IS NULL AND expires_at > strftime('%Y-%m-%dT%H:%M:%SZ','now'))",
+    )
+    .bind(claims.sid)
+    .bind(claims.sub)
+    .fetch_one(&app_state.db_pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to check session {}: {:?}", claims.sid, e);
+        AuthError::InvalidToken
+    })?;
+
+    if !is_active.0 {
+        return Err(AuthError::SessionRevoked);
     }
+
+    Ok(claims)
+}
+
+/// Firma un nuovo access token per l'utente, legato alla sessione `session_id`.
+pub fn create_access_token(
+    app_state: &AppState,
+    user_id: Uuid,
+    username: &str,
+    session_id: Uuid,
+) -> Result<String, AppError> {
+    let now = Utc::now();
+    let claims = Claims {
+        sub: user_id,
+        iat: now.timestamp(),
+        exp: (now + Duration::minutes(ACCESS_TOKEN_TTL_MINUTES)).timestamp(),
+        username: username.to_string(),
+        sid: session_id,
+    };
+
+    Ok(encode(
+        &Header::default(),
+        &claims,
+        &EncodingKey::from_secret(app_state.jwt_secret.as_ref()),
+    )?)
+}
+
+/// Genera un refresh token casuale e restituisce la coppia (token, hash da salvare nel DB).
+pub fn generate_refresh_token() -> (String, String) {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    let token = hex::encode(bytes);
+    let token_hash = hash_refresh_token(&token);
+    (token, token_hash)
+}
+
+/// SHA-256 del refresh token: basta a confrontarlo, perché il token è già ad alta entropia.
+pub fn hash_refresh_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
 }
 
 /// Tipo di errore per l'estrattore.
 pub enum AuthError {
     InvalidToken,
+    SessionRevoked,
 }
 
 /// Come convertire il nostro `AuthError` in una risposta HTTP.
 /// Questo permette di inviare un errore 401 standard in caso di token mancante o non valido.
 impl IntoResponse for AuthError {
     fn into_response(self) -> Response {
-        let (status, error_message) = match self {
-            AuthError::InvalidToken => (StatusCode::UNAUTHORIZED, "Token di autenticazione non valido o mancante."),
+        let (code, error_message) = match self {
+            AuthError::InvalidToken => (ErrorCode::Unauthorized, "Token di autenticazione non valido o mancante."),
+            AuthError::SessionRevoked => (ErrorCode::SessionRevoked, "Sessione scaduta o revocata, effettua di nuovo il login."),
         };
 
         let body = Json(ErrorResponse {
-            message: error_message.to_string(),
+            code,
+            error: error_message.to_string(),
         });
 
-        (status, body).into_response()
+        (StatusCode::UNAUTHORIZED, body).into_response()
     }
 }
\ No newline at end of file
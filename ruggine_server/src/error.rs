@@ -3,7 +3,7 @@ use axum::{
     response::{IntoResponse, Response},
     Json,
 };
-use serde_json::json;
+use ruggine_protocol::{ErrorCode, ErrorResponse, MAX_ATTACHMENT_SIZE};
 
 // Definisci il tuo tipo di errore custom con tutte le varianti necessarie
 #[derive(Debug)]
@@ -11,45 +11,87 @@ pub enum AppError {
     DatabaseError(sqlx::Error),
     JwtError(jsonwebtoken::errors::Error),
     PasswordHashError(bcrypt::BcryptError), // Errore specifico per bcrypt
+    StorageError(std::io::Error),           // Lettura/scrittura degli allegati su disco
 
     // Errori di Logica/Input
     InvalidInput(String),
     WrongCredentials,
+    InvalidRefreshToken,
     UsernameExists,
     UserNotFound,
     GroupNotFound,
     UserOrGroupNotFound, // Per violazioni di Foreign Key generiche
     InvitationNotFound,
+    MessageNotFound,
+    AttachmentNotFound,
+    AttachmentTooLarge,
+    UnsupportedMediaType,
     InvitationAlreadyExists,
     UserAlreadyInGroup,
+    UserNotInGroup,
+    UserBanned,
+    BanNotFound,
     MissingPermissions,
+    NotGroupMember,    // Il chiamante non fa parte del gruppo
+    InsufficientRole,  // Il chiamante è membro, ma il suo ruolo non basta
     CannotInviteSelf,
+    InviteLinkNotFound,
+    InviteLinkExpired, // Scaduto o esaurito
+    GroupNotPublic,    // Nei gruppi privati si entra solo su richiesta o invito
+    JoinRequestNotFound,
+    JoinRequestAlreadyExists,
+    WrongPassword,     // Password attuale errata nelle operazioni sull'account
+    InvalidResetToken, // Inesistente, scaduto o già usato
 }
 
 // Implementa `IntoResponse` per convertire l'errore in una risposta HTTP
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
-        let (status, error_message) = match self {
+        let (status, code, error_message) = match self {
             AppError::DatabaseError(e) => {
                 tracing::error!("Database error: {:?}", e);
-                (StatusCode::INTERNAL_SERVER_ERROR, "An internal server error occurred".to_string())
+                (StatusCode::INTERNAL_SERVER_ERROR, ErrorCode::Internal, "An internal server error occurred".to_string())
             }
-            AppError::JwtError(_) => (StatusCode::UNAUTHORIZED, "Invalid authentication token".to_string()),
-            AppError::PasswordHashError(_) => (StatusCode::INTERNAL_SERVER_ERROR, "Failed to process request".to_string()),
-            AppError::InvalidInput(msg) => (StatusCode::BAD_REQUEST, msg),
-            AppError::WrongCredentials => (StatusCode::UNAUTHORIZED, "Invalid username or password".to_string()),
-            AppError::UsernameExists => (StatusCode::CONFLICT, "Username already exists".to_string()),
-            AppError::UserNotFound => (StatusCode::NOT_FOUND, "User not found".to_string()),
-            AppError::GroupNotFound => (StatusCode::NOT_FOUND, "Group not found".to_string()),
-            AppError::UserOrGroupNotFound => (StatusCode::NOT_FOUND, "The specified user or group does not exist".to_string()),
-            AppError::InvitationNotFound => (StatusCode::NOT_FOUND, "Invitation not found or has already been handled".to_string()),
-            AppError::InvitationAlreadyExists => (StatusCode::CONFLICT, "An invitation for this user to this group already exists".to_string()),
-            AppError::UserAlreadyInGroup => (StatusCode::CONFLICT, "User is already a member of this group".to_string()),
-            AppError::MissingPermissions => (StatusCode::FORBIDDEN, "You do not have permission to perform this action".to_string()),
-            AppError::CannotInviteSelf => (StatusCode::BAD_REQUEST, "You cannot invite yourself to a group".to_string()),
+            AppError::JwtError(_) => (StatusCode::UNAUTHORIZED, ErrorCode::Unauthorized, "Invalid authentication token".to_string()),
+            AppError::PasswordHashError(_) => (StatusCode::INTERNAL_SERVER_ERROR, ErrorCode::Internal, "Failed to process request".to_string()),
+            AppError::StorageError(e) => {
+                tracing::error!("Storage error: {:?}", e);
+                (StatusCode::INTERNAL_SERVER_ERROR, ErrorCode::Internal, "An internal server error occurred".to_string())
+            }
+            AppError::InvalidInput(msg) => (StatusCode::BAD_REQUEST, ErrorCode::InvalidInput, msg),
+            AppError::WrongCredentials => (StatusCode::UNAUTHORIZED, ErrorCode::WrongCredentials, "Invalid username or password".to_string()),
+            AppError::InvalidRefreshToken => (StatusCode::UNAUTHORIZED, ErrorCode::InvalidRefreshToken, "Invalid or expired refresh token".to_string()),
+            AppError::UsernameExists => (StatusCode::CONFLICT, ErrorCode::UsernameExists, "Username already exists".to_string()),
+            AppError::UserNotFound => (StatusCode::NOT_FOUND, ErrorCode::UserNotFound, "User not found".to_string()),
+            AppError::GroupNotFound => (StatusCode::NOT_FOUND, ErrorCode::GroupNotFound, "Group not found".to_string()),
+            AppError::UserOrGroupNotFound => (StatusCode::NOT_FOUND, ErrorCode::UserOrGroupNotFound, "The specified user or group does not exist".to_string()),
+            AppError::InvitationNotFound => (StatusCode::NOT_FOUND, ErrorCode::InvitationNotFound, "Invitation not found or has already been handled".to_string()),
+            AppError::MessageNotFound => (StatusCode::NOT_FOUND, ErrorCode::MessageNotFound, "Message not found".to_string()),
+            AppError::AttachmentNotFound => (StatusCode::NOT_FOUND, ErrorCode::AttachmentNotFound, "Attachment not found".to_string()),
+            AppError::AttachmentTooLarge => (StatusCode::PAYLOAD_TOO_LARGE, ErrorCode::AttachmentTooLarge, format!("Attachments cannot exceed {} MB", MAX_ATTACHMENT_SIZE / (1024 * 1024))),
+            AppError::UnsupportedMediaType => (StatusCode::UNSUPPORTED_MEDIA_TYPE, ErrorCode::UnsupportedMediaType, "This file type is not allowed".to_string()),
+            AppError::InvitationAlreadyExists => (StatusCode::CONFLICT, ErrorCode::InvitationAlreadyExists, "An invitation for this user to this group already exists".to_string()),
+            AppError::UserAlreadyInGroup => (StatusCode::CONFLICT, ErrorCode::UserAlreadyInGroup, "User is already a member of this group".to_string()),
+            AppError::UserNotInGroup => (StatusCode::NOT_FOUND, ErrorCode::UserNotInGroup, "User is not a member of this group".to_string()),
+            AppError::UserBanned => (StatusCode::FORBIDDEN, ErrorCode::UserBanned, "This user is banned from the group".to_string()),
+            AppError::BanNotFound => (StatusCode::NOT_FOUND, ErrorCode::BanNotFound, "This user is not banned from the group".to_string()),
+            AppError::NotGroupMember => (StatusCode::FORBIDDEN, ErrorCode::NotGroupMember, "You are not a member of this group".to_string()),
+            AppError::InsufficientRole => (StatusCode::FORBIDDEN, ErrorCode::InsufficientRole, "Your role in this group does not allow this action".to_string()),
+            AppError::MissingPermissions => (StatusCode::FORBIDDEN, ErrorCode::MissingPermissions, "You do not have permission to perform this action".to_string()),
+            AppError::CannotInviteSelf => (StatusCode::BAD_REQUEST, ErrorCode::CannotInviteSelf, "You cannot invite yourself to a group".to_string()),
+            AppError::InviteLinkNotFound => (StatusCode::NOT_FOUND, ErrorCode::InviteLinkNotFound, "Invite link not found".to_string()),
+            AppError::InviteLinkExpired => (StatusCode::GONE, ErrorCode::InviteLinkExpired, "This invite link has expired or reached its maximum number of uses".to_string()),
+            AppError::GroupNotPublic => (StatusCode::FORBIDDEN, ErrorCode::GroupNotPublic, "This group is private: ask to join instead".to_string()),
+            AppError::JoinRequestNotFound => (StatusCode::NOT_FOUND, ErrorCode::JoinRequestNotFound, "Join request not found or has already been handled".to_string()),
+            AppError::JoinRequestAlreadyExists => (StatusCode::CONFLICT, ErrorCode::JoinRequestAlreadyExists, "You have already asked to join this group".to_string()),
+            AppError::WrongPassword => (StatusCode::FORBIDDEN, ErrorCode::WrongPassword, "The current password is incorrect".to_string()),
+            AppError::InvalidResetToken => (StatusCode::BAD_REQUEST, ErrorCode::InvalidResetToken, "Invalid, expired or already used password reset token".to_string()),
         };
 
-        let body = Json(json!({ "error": error_message }));
+        let body = Json(ErrorResponse {
+            code,
+            error: error_message,
+        });
         (status, body).into_response()
     }
 }
@@ -73,4 +115,10 @@ impl From<bcrypt::BcryptError> for AppError {
     fn from(e: bcrypt::BcryptError) -> Self {
         AppError::PasswordHashError(e)
     }
-}
\ No newline at end of file
+}
+
+impl From<std::io::Error> for AppError {
+    fn from(e: std::io::Error) -> Self {
+        AppError::StorageError(e)
+    }
+}
@@ -1,27 +1,226 @@
+use crate::attachments;
+use crate::auth::{self, create_access_token, generate_refresh_token, hash_refresh_token};
 use crate::error::AppError;
 use crate::models::{
-    Claims, CreateGroupPayload, Group, Invitation, InviteToGroupPayload, LoginPayload,
-    LoginResponse, RegisterUserPayload, User, WsClientMessage, WsServerMessage,
+    find_mentions, is_valid_reaction, Attachment, AttachmentRecord, ChangePasswordPayload, Claims,
+    CreateGroupPayload, CreateInviteLinkPayload, DeleteAccountPayload, DirectConversation,
+    DirectMessageSent, DirectoryGroup, DirectoryPage, EditMessagePayload, Group,
+    GroupDirectoryQuery, GroupMember, GroupMessageRecord, GroupProfileChange, GroupProfileField,
+    GroupRole, GroupVisibility, Invitation, InviteLink, InviteToGroupPayload, JoinRequest,
+    LoginPayload, LoginResponse, MarkReadPayload, MemberLeftReason, Mention, MentionPage,
+    MentionQuery, MessageHistoryQuery, MessagePage, MessageRecord, MessageSearchQuery,
+    MessageThread, PasswordResetToken, ReactionRecord, RefreshPayload, RefreshResponse,
+    RegisterUserPayload, ReplyPreview, ReplyPreviewRecord, ResetPasswordPayload, SearchHit,
+    SearchHitRecord, SearchPage, SendDirectMessagePayload, TransferOwnershipPayload, UnreadCount,
+    UpdateGroupPayload, UpdateGroupVisibilityPayload, UpdateMemberRolePayload,
+    UpdateProfilePayload, User, UserGroups, UserRecord, WsClientCommand, WsErrorCode, WsMuxCommand,
+    WsMuxEvent, WsServerEvent, WsServerMessage, ALLOWED_AVATAR_TYPES, CLOSE_GROUP_DELETED,
+    CLOSE_REMOVED_FROM_GROUP, CLOSE_SESSION_REVOKED, DELETED_USER_NAME, INVITE_CODE_LENGTH,
+    MAX_ATTACHMENT_SIZE, MAX_AVATAR_SIZE, MAX_BIO_CHARS, MAX_DISPLAY_NAME_CHARS,
+    MAX_GROUP_DESCRIPTION_CHARS, MAX_GROUP_TOPIC_CHARS, MAX_STATUS_MESSAGE_CHARS, MIN_PASSWORD_LEN,
+    REPLY_PREVIEW_CHARS, TYPING_MIN_INTERVAL_MS, WS_MIN_PROTOCOL_VERSION, WS_PROTOCOL_VERSION,
 };
-use crate::{AppState, ChatState};
+use crate::presence;
+use crate::{AppState, ChatEvent, ChatState, SessionSockets, UserChannels};
 use axum::{
     extract::{
-        ws::{Message, WebSocket, WebSocketUpgrade},
-        Path, Query, State,
+        multipart::{Field, MultipartError},
+        ws::{CloseFrame, Message, WebSocket, WebSocketUpgrade},
+        FromRequest, Multipart, Path, Query, Request, State,
     },
-    http::StatusCode,
-    response::IntoResponse,
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
     Json,
 };
 use bcrypt::{hash, verify, DEFAULT_COST};
-use chrono::{Duration, Utc};
 use futures_util::{stream::StreamExt, SinkExt};
-use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
-use sqlx::{Pool, Sqlite};
+use rand::Rng;
+use sqlx::{Executor, Pool, QueryBuilder, Sqlite};
 use std::collections::HashMap;
-use tokio::sync::broadcast;
+use std::ops::Range;
+use std::time::{Duration, Instant};
+use tokio::sync::{broadcast, mpsc};
+use tokio::task::JoinHandle;
 use uuid::Uuid;
 
+/// Numero massimo di messaggi persi reinviati a un client che si riconnette.
+const REPLAY_LIMIT: i64 = 200;
+/// Dimensione (e massimo) di una pagina della cronologia.
+const MESSAGE_PAGE_SIZE: i64 = 100;
+/// Dimensione (e massimo) di una pagina di risultati di ricerca.
+const SEARCH_PAGE_SIZE: i64 = 30;
+/// Dimensione (e massimo) di una pagina della casella delle menzioni.
+const MENTION_PAGE_SIZE: i64 = 50;
+/// Dimensione (e massimo) di una pagina dell'elenco dei gruppi pubblici.
+const DIRECTORY_PAGE_SIZE: i64 = 30;
+/// Numero di modifiche del profilo restituite da `GET /groups/:group_id/history`.
+const PROFILE_HISTORY_LIMIT: i64 = 50;
+
+// --- Permessi nei gruppi ---
+
+/// Restituisce il ruolo dell'utente nel gruppo, o `NotGroupMember` se non ne fa parte.
+async fn member_role<'e, E>(executor: E, user_id: Uuid, group_id: Uuid) -> Result<GroupRole, AppError>
+where
+    E: Executor<'e, Database = Sqlite>,
+{
+    sqlx::query_scalar::<_, GroupRole>("SELECT role FROM group_members WHERE user_id = ? AND group_id = ?")
+        .bind(user_id)
+        .bind(group_id)
+        .fetch_optional(executor)
+        .await?
+        .ok_or(AppError::NotGroupMember)
+}
+
+/// Verifica che l'utente sia membro del gruppo con almeno il ruolo `min_role`.
+async fn require_role<'e, E>(
+    executor: E,
+    user_id: Uuid,
+    group_id: Uuid,
+    min_role: GroupRole,
+) -> Result<GroupRole, AppError>
+where
+    E: Executor<'e, Database = Sqlite>,
+{
+    let role = member_role(executor, user_id, group_id).await?;
+    if role < min_role {
+        return Err(AppError::InsufficientRole);
+    }
+    Ok(role)
+}
+
+/// Verifica che l'utente non sia stato bannato dal gruppo.
+async fn ensure_not_banned<'e, E>(executor: E, user_id: Uuid, group_id: Uuid) -> Result<(), AppError>
+where
+    E: Executor<'e, Database = Sqlite>,
+{
+    let is_banned: (bool,) = sqlx::query_as("SELECT EXISTS(SELECT 1 FROM group_bans WHERE user_id = ? AND group_id = ?)")
+        .bind(user_id)
+        .bind(group_id)
+        .fetch_one(executor)
+        .await?;
+
+    if is_banned.0 {
+        return Err(AppError::UserBanned);
+    }
+    Ok(())
+}
+
+async fn fetch_group<'e, E>(executor: E, group_id: Uuid) -> Result<Group, AppError>
+where
+    E: Executor<'e, Database = Sqlite>,
+{
+    sqlx::query_as!(Group, "SELECT id as \"id!: uuid::Uuid\", name, created_at as \"created_at!: sqlx::types::time::OffsetDateTime\", visibility as \"visibility!: GroupVisibility\", description, topic, avatar_sha256 FROM groups WHERE id = ?", group_id)
+        .fetch_optional(executor)
+        .await?
+        .ok_or(AppError::GroupNotFound)
+}
+
+/// Nome visualizzato dell'utente, per i messaggi appena inviati.
+async fn fetch_display_name<'e, E>(executor: E, user_id: Uuid) -> Result<Option<String>, sqlx::Error>
+where
+    E: Executor<'e, Database = Sqlite>,
+{
+    let display_name = sqlx::query_scalar!("SELECT display_name FROM users WHERE id = ?", user_id)
+        .fetch_optional(executor)
+        .await?;
+    Ok(display_name.flatten())
+}
+
+/// Indica se il gruppo è in realtà una conversazione diretta tra due utenti.
+async fn is_direct_conversation<'e, E>(executor: E, group_id: Uuid) -> Result<bool, AppError>
+where
+    E: Executor<'e, Database = Sqlite>,
+{
+    let is_direct: (bool,) = sqlx::query_as("SELECT EXISTS(SELECT 1 FROM direct_conversations WHERE group_id = ?)")
+        .bind(group_id)
+        .fetch_one(executor)
+        .await?;
+    Ok(is_direct.0)
+}
+
+/// Invia un evento a tutti i client connessi alla chat del gruppo.
+fn broadcast_event(chat_state: &ChatState, group_id: Uuid, event: &WsServerEvent) {
+    if let Some(tx) = chat_state.get(&group_id) {
+        // Invia il messaggio, ignorando l'errore se non ci sono più iscritti
+        let _ = tx.send(ChatEvent::Event(event.clone()));
+    }
+}
+
+/// Chiude le connessioni di tutti i membri al gruppo e ne dimentica il canale.
+fn close_group_channel(chat_state: &ChatState, group_id: Uuid) {
+    if let Some((_, tx)) = chat_state.remove(&group_id) {
+        let _ = tx.send(ChatEvent::Close);
+    }
+}
+
+/// Invia un evento a tutte le connessioni `/ws` aperte dall'utente, se ce ne sono.
+fn notify_user(user_channels: &UserChannels, user_id: Uuid, event: WsMuxEvent) {
+    if let Some(tx) = user_channels.get(&user_id) {
+        let _ = tx.send(event);
+    }
+}
+
+/// Segnale ricevuto da una connessione quando la sessione con cui è stata aperta viene revocata.
+fn watch_session(session_sockets: &SessionSockets, session_id: Uuid) -> broadcast::Receiver<()> {
+    session_sockets.entry(session_id).or_insert_with(|| broadcast::channel(1).0).subscribe()
+}
+
+/// Chiude le connessioni WebSocket aperte con le sessioni indicate, appena revocate.
+fn close_session_sockets(session_sockets: &SessionSockets, session_ids: &[Uuid]) {
+    for session_id in session_ids {
+        if let Some((_, tx)) = session_sockets.remove(session_id) {
+            let _ = tx.send(());
+        }
+    }
+}
+
+/// Invia un evento che riguarda l'utente a tutti i suoi gruppi.
+async fn broadcast_to_user_groups(app_state: &AppState, user_id: Uuid, event: &WsServerEvent) {
+    let group_ids = sqlx::query_scalar!(
+        "SELECT group_id as \"group_id!: uuid::Uuid\" FROM group_members WHERE user_id = ?",
+        user_id
+    )
+    .fetch_all(&app_state.db_pool)
+    .await
+    .unwrap_or_default();
+
+    for group_id in group_ids {
+        broadcast_event(&app_state.chat_state, group_id, event);
+    }
+}
+
+/// Comunica il nuovo stato dell'utente a tutti i suoi gruppi.
+async fn broadcast_presence(app_state: &AppState, user_id: Uuid, last_seen_at: Option<sqlx::types::time::OffsetDateTime>) {
+    let event = WsServerEvent::Presence { user_id, status: presence::status(&app_state.presence, user_id), last_seen_at };
+    broadcast_to_user_groups(app_state, user_id, &event).await;
+}
+
+/// Da chiamare all'apertura di ogni connessione WebSocket dell'utente.
+async fn user_connected(app_state: &AppState, user_id: Uuid) {
+    if presence::connect(&app_state.presence, user_id) {
+        broadcast_presence(app_state, user_id, None).await;
+    }
+}
+
+/// Da chiamare alla chiusura di ogni connessione: con l'ultima l'utente va offline e se ne salva l'ora.
+async fn user_disconnected(app_state: &AppState, user_id: Uuid) {
+    if !presence::disconnect(&app_state.presence, user_id) {
+        return;
+    }
+    let last_seen_at = sqlx::query_scalar!(
+        "UPDATE users SET last_seen_at = strftime('%Y-%m-%dT%H:%M:%SZ','now') WHERE id = ? RETURNING last_seen_at as \"last_seen_at!: sqlx::types::time::OffsetDateTime\"",
+        user_id
+    )
+    .fetch_optional(&app_state.db_pool)
+    .await
+    .unwrap_or_else(|e| {
+        tracing::error!("Failed to save last seen time: {:?}", e);
+        None
+    });
+    broadcast_presence(app_state, user_id, last_seen_at).await;
+}
+
+
 // --- Gestione Utenti ---
 
 pub async fn leave_group(
@@ -29,56 +228,277 @@ pub async fn leave_group(
     State(app_state): State<AppState>,
     Path(group_id): Path<Uuid>,
 ) -> Result<StatusCode, AppError> {
-    let user_id = claims.sub;
-    let username = claims.username; // Prendiamo il nome utente dalle claims del token
-
     let mut tx = app_state.db_pool.begin().await?;
 
-    let result = sqlx::query!(
+    if is_direct_conversation(&mut *tx, group_id).await? {
+        return Err(AppError::InvalidInput("Direct conversations cannot be left.".to_string()));
+    }
+
+    let departure = remove_from_group(&mut tx, claims.sub, group_id).await?;
+
+    // Impegnamo la transazione prima di inviare il messaggio broadcast
+    tx.commit().await?;
+
+    if let Some(departure) = departure {
+        announce_departure(&app_state, claims.sub, &claims.username, departure).await?;
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Uscita di un utente da un gruppo, da notificare dopo il commit con `announce_departure`.
+struct GroupDeparture {
+    group_id: Uuid,
+    new_owner: Option<(Uuid, String)>,
+    empty: bool, // Nessun membro rimasto: il gruppo va eliminato
+}
+
+/// Toglie l'utente dal gruppo; `None` se non ne faceva parte.
+async fn remove_from_group(
+    conn: &mut sqlx::SqliteConnection,
+    user_id: Uuid,
+    group_id: Uuid,
+) -> Result<Option<GroupDeparture>, AppError> {
+    let role = sqlx::query_scalar!(
+        "SELECT role as \"role!: GroupRole\" FROM group_members WHERE user_id = ? AND group_id = ?",
+        user_id,
+        group_id
+    )
+    .fetch_optional(&mut *conn)
+    .await?;
+    let Some(role) = role else {
+        return Ok(None);
+    };
+
+    sqlx::query!(
         "DELETE FROM group_members WHERE user_id = ? AND group_id = ?",
         user_id,
         group_id
     )
-    .execute(&mut *tx)
+    .execute(&mut *conn)
     .await?;
 
-    if result.rows_affected() == 0 {
-        tx.commit().await?;
-        return Ok(StatusCode::NO_CONTENT);
-    }
+    // Il gruppo non resta senza proprietario: passa all'admin (o, se non ce ne sono, al membro) presente da più tempo
+    let new_owner = if role == GroupRole::Owner {
+        let successor = sqlx::query!(
+            r#"
+            SELECT gm.user_id as "user_id!: uuid::Uuid", u.username
+            FROM group_members gm
+            JOIN users u ON gm.user_id = u.id
+            WHERE gm.group_id = ?
+            ORDER BY gm.role = 'admin' DESC, gm.rowid ASC
+            LIMIT 1
+            "#,
+            group_id
+        )
+        .fetch_optional(&mut *conn)
+        .await?;
+        if let Some(successor) = &successor {
+            sqlx::query!(
+                "UPDATE group_members SET role = 'owner' WHERE user_id = ? AND group_id = ?",
+                successor.user_id, group_id
+            )
+            .execute(&mut *conn)
+            .await?;
+        }
+        successor.map(|successor| (successor.user_id, successor.username))
+    } else {
+        None
+    };
 
     let count: (i64,) = sqlx::query_as("SELECT COUNT(*) FROM group_members WHERE group_id = ?")
         .bind(group_id)
-        .fetch_one(&mut *tx)
+        .fetch_one(&mut *conn)
         .await?;
-    
-    // Impegnamo la transazione prima di inviare il messaggio broadcast
-    tx.commit().await?;
 
-    // --- INIZIO MODIFICA ---
-    // Invia un messaggio di notifica alla chat del gruppo
-    if let Some(tx) = app_state.chat_state.get(&group_id) {
-        let system_message = WsServerMessage {
-            sender_id: Uuid::nil(), // ID speciale per i messaggi di sistema
-            sender_username: "system".to_string(), // Non mostrato, ma utile per debug
-            content: format!("{} ha lasciato il gruppo.", username),
-        };
-        // Invia il messaggio, ignorando l'errore se non ci sono più iscritti
-        let _ = tx.send(serde_json::to_string(&system_message).unwrap());
+    Ok(Some(GroupDeparture { group_id, new_owner, empty: count.0 == 0 }))
+}
+
+/// Notifica l'uscita agli altri membri ed elimina il gruppo se è rimasto vuoto.
+async fn announce_departure(
+    app_state: &AppState,
+    user_id: Uuid,
+    username: &str,
+    departure: GroupDeparture,
+) -> Result<(), AppError> {
+    let group_id = departure.group_id;
+    broadcast_event(
+        &app_state.chat_state,
+        group_id,
+        &WsServerEvent::MemberLeft { user_id, username: username.to_string(), reason: MemberLeftReason::Left, by_username: None },
+    );
+    if let Some((new_owner_id, new_owner_username)) = departure.new_owner {
+        broadcast_event(
+            &app_state.chat_state,
+            group_id,
+            &WsServerEvent::OwnershipTransferred { previous_owner_id: user_id, new_owner_id, new_owner_username },
+        );
+    }
+    // Le connessioni dell'utente smettono di ricevere gli eventi del gruppo
+    if let Some(chat) = app_state.chat_state.get(&group_id) {
+        let _ = chat.send(ChatEvent::Disconnect(user_id));
     }
-    // --- FINE MODIFICA ---
 
     // Se non ci sono più membri, ora che la notifica è stata inviata, possiamo pulire il gruppo
-    if count.0 == 0 {
+    if departure.empty {
         sqlx::query!("DELETE FROM groups WHERE id = ?", group_id)
             .execute(&app_state.db_pool)
             .await?;
         tracing::info!("Gruppo {} eliminato perché non ha più membri.", group_id);
-        
+
         // Rimuovi anche lo stato della chat dalla memoria
-        app_state.chat_state.remove(&group_id);
+        close_group_channel(&app_state.chat_state, group_id);
+    }
+    Ok(())
+}
+
+/// Rimuove `member_id` dal gruppo per conto di un admin, restituendone lo username.
+/// Un admin non può rimuovere chi ha un ruolo pari o superiore al proprio.
+async fn remove_member_as_admin(
+    conn: &mut sqlx::SqliteConnection,
+    actor_id: Uuid,
+    member_id: Uuid,
+    group_id: Uuid,
+) -> Result<Option<String>, AppError> {
+    let actor_role = require_role(&mut *conn, actor_id, group_id, GroupRole::Admin).await?;
+
+    let target = sqlx::query!(
+        r#"
+        SELECT u.username, gm.role as "role!: GroupRole"
+        FROM group_members gm
+        JOIN users u ON gm.user_id = u.id
+        WHERE gm.user_id = ? AND gm.group_id = ?
+        "#,
+        member_id, group_id
+    )
+    .fetch_optional(&mut *conn)
+    .await?;
+
+    let Some(target) = target else {
+        return Ok(None);
+    };
+
+    if target.role >= actor_role {
+        return Err(AppError::InsufficientRole);
+    }
+
+    sqlx::query!(
+        "DELETE FROM group_members WHERE user_id = ? AND group_id = ?",
+        member_id, group_id
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(Some(target.username))
+}
+
+pub async fn kick_member(
+    claims: Claims,
+    State(app_state): State<AppState>,
+    Path((group_id, member_id)): Path<(Uuid, Uuid)>,
+) -> Result<StatusCode, AppError> {
+    if member_id == claims.sub {
+        return Err(AppError::InvalidInput("Use the leave endpoint to leave a group.".to_string()));
+    }
+
+    let mut tx = app_state.db_pool.begin().await?;
+    let username = remove_member_as_admin(&mut tx, claims.sub, member_id, group_id)
+        .await?
+        .ok_or(AppError::UserNotInGroup)?;
+    tx.commit().await?;
+
+    broadcast_event(
+        &app_state.chat_state,
+        group_id,
+        &WsServerEvent::MemberLeft {
+            user_id: member_id,
+            username,
+            reason: MemberLeftReason::Kicked,
+            by_username: Some(claims.username),
+        },
+    );
+    if let Some(chat) = app_state.chat_state.get(&group_id) {
+        let _ = chat.send(ChatEvent::Disconnect(member_id));
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn ban_member(
+    claims: Claims,
+    State(app_state): State<AppState>,
+    Path((group_id, member_id)): Path<(Uuid, Uuid)>,
+) -> Result<StatusCode, AppError> {
+    if member_id == claims.sub {
+        return Err(AppError::InvalidInput("You cannot ban yourself.".to_string()));
+    }
+
+    let mut tx = app_state.db_pool.begin().await?;
+    // Si può bannare anche chi non è (più) membro, per impedirgli di rientrare
+    let removed_username = remove_member_as_admin(&mut tx, claims.sub, member_id, group_id).await?;
+
+    let result = sqlx::query!(
+        "INSERT INTO group_bans (group_id, user_id, banned_by) VALUES (?, ?, ?) ON CONFLICT DO NOTHING",
+        group_id, member_id, claims.sub
+    )
+    .execute(&mut *tx)
+    .await;
+
+    if let Err(e) = result {
+        if let Some(db_err) = e.as_database_error() {
+            if db_err.is_foreign_key_violation() { return Err(AppError::UserNotFound); }
+        }
+        return Err(e.into());
+    }
+
+    // Gli inviti ancora pendenti non devono poter essere accettati
+    sqlx::query!(
+        "DELETE FROM group_invitations WHERE group_id = ? AND invited_user_id = ? AND status = 'pending'",
+        group_id, member_id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    if let Some(username) = removed_username {
+        broadcast_event(
+            &app_state.chat_state,
+            group_id,
+            &WsServerEvent::MemberLeft {
+                user_id: member_id,
+                username,
+                reason: MemberLeftReason::Banned,
+                by_username: Some(claims.username),
+            },
+        );
+        if let Some(chat) = app_state.chat_state.get(&group_id) {
+            let _ = chat.send(ChatEvent::Disconnect(member_id));
+        }
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn unban_member(
+    claims: Claims,
+    State(app_state): State<AppState>,
+    Path((group_id, member_id)): Path<(Uuid, Uuid)>,
+) -> Result<StatusCode, AppError> {
+    let mut tx = app_state.db_pool.begin().await?;
+    require_role(&mut *tx, claims.sub, group_id, GroupRole::Admin).await?;
+
+    let result = sqlx::query!(
+        "DELETE FROM group_bans WHERE group_id = ? AND user_id = ?",
+        group_id, member_id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(AppError::BanNotFound);
     }
 
+    tx.commit().await?;
     Ok(StatusCode::NO_CONTENT)
 }
 
@@ -86,17 +506,13 @@ pub async fn register_user(
     State(app_state): State<AppState>,
     Json(payload): Json<RegisterUserPayload>,
 ) -> Result<Json<User>, AppError> {
-    if payload.password.len() < 8 {
-        return Err(AppError::InvalidInput(
-            "Password must be at least 8 characters long.".to_string(),
-        ));
-    }
+    validate_new_password(&payload.password)?;
 
     let password_hash = hash(payload.password, DEFAULT_COST)?;
 
     sqlx::query_as!(
         User,
-        "INSERT INTO users (username, password_hash) VALUES (?, ?) RETURNING id as \"id!: uuid::Uuid\", username, password_hash, created_at as \"created_at!: sqlx::types::time::OffsetDateTime\"",
+        "INSERT INTO users (username, password_hash) VALUES (?, ?) RETURNING id as \"id!: uuid::Uuid\", username, created_at as \"created_at!: sqlx::types::time::OffsetDateTime\", display_name, bio, status_message, avatar_sha256",
         payload.username,
         password_hash
     )
@@ -118,8 +534,8 @@ pub async fn login_user(
     Json(payload): Json<LoginPayload>,
 ) -> Result<Json<LoginResponse>, AppError> {
     let user = sqlx::query_as!(
-        User,
-        "SELECT id \"id!: uuid::Uuid\", username, password_hash, created_at as \"created_at!: sqlx::types::time::OffsetDateTime\" FROM users WHERE username = ?",
+        UserRecord,
+        "SELECT id \"id!: uuid::Uuid\", username, password_hash, created_at as \"created_at!: sqlx::types::time::OffsetDateTime\", display_name, bio, status_message, avatar_sha256, is_admin as \"is_admin!: bool\" FROM users WHERE username = ? AND deleted_at IS NULL",
         payload.username
     )
     .fetch_optional(&app_state.db_pool)
@@ -130,48 +546,188 @@ pub async fn login_user(
         return Err(AppError::WrongCredentials);
     }
 
-    let user_groups = sqlx::query_as!(
+    let user_groups = fetch_user_groups(&app_state.db_pool, user.id).await?;
+    let direct_conversations = fetch_direct_conversations(&app_state.db_pool, user.id).await?;
+    let unread = fetch_unread_counts(&app_state.db_pool, user.id).await?;
+
+    // Ogni login apre una nuova sessione, revocabile indipendentemente dalle altre
+    let (refresh_token, refresh_token_hash) = generate_refresh_token();
+    let session_expiry = format!("+{} days", auth::SESSION_TTL_DAYS);
+    let session_id = sqlx::query_scalar!(
+        "INSERT INTO sessions (user_id, refresh_token_hash, expires_at) VALUES (?, ?, strftime('%Y-%m-%dT%H:%M:%SZ','now', ?)) RETURNING id as \"id!: uuid::Uuid\"",
+        user.id,
+        refresh_token_hash,
+        session_expiry
+    )
+    .fetch_one(&app_state.db_pool)
+    .await?;
+
+    let token = create_access_token(&app_state, user.id, &user.username, session_id)?;
+    let is_admin = user.is_admin;
+
+    Ok(Json(LoginResponse {
+        token,
+        refresh_token,
+        user: user.into(),
+        groups: user_groups,
+        direct_conversations,
+        unread,
+        is_admin,
+    }))
+}
+
+/// Gruppi dell'utente, escluse le conversazioni dirette.
+async fn fetch_user_groups<'e, E>(executor: E, user_id: Uuid) -> Result<Vec<Group>, AppError>
+where
+    E: Executor<'e, Database = Sqlite>,
+{
+    let groups = sqlx::query_as!(
         Group,
         r#"
-        SELECT g.id as "id!: uuid::Uuid", g.name, g.created_at as "created_at!: sqlx::types::time::OffsetDateTime"
+        SELECT g.id as "id!: uuid::Uuid", g.name, g.created_at as "created_at!: sqlx::types::time::OffsetDateTime", g.visibility as "visibility!: GroupVisibility",
+               g.description, g.topic, g.avatar_sha256
         FROM groups g
         JOIN group_members gm ON g.id = gm.group_id
-        WHERE gm.user_id = ?
+        WHERE gm.user_id = ? AND g.id NOT IN (SELECT group_id FROM direct_conversations)
         ORDER BY g.created_at ASC
         "#,
-        user.id
+        user_id
     )
-    .fetch_all(&app_state.db_pool)
+    .fetch_all(executor)
+    .await?;
+    Ok(groups)
+}
+
+/// Non letti di ogni gruppo dell'utente: i messaggi altrui, non eliminati, successivi alla sua posizione di lettura.
+async fn fetch_unread_counts<'e, E>(executor: E, user_id: Uuid) -> Result<Vec<UnreadCount>, AppError>
+where
+    E: Executor<'e, Database = Sqlite>,
+{
+    let rows = sqlx::query!(
+        r#"
+        SELECT
+            gm.group_id as "group_id!: uuid::Uuid",
+            gm.last_read_message_id as "last_read_message_id: uuid::Uuid",
+            (
+                SELECT COUNT(*)
+                FROM group_messages m
+                LEFT JOIN group_messages r ON r.id = gm.last_read_message_id
+                WHERE m.group_id = gm.group_id
+                  AND m.user_id != gm.user_id
+                  AND m.deleted_at IS NULL
+                  AND (r.id IS NULL OR m.created_at > r.created_at OR (m.created_at = r.created_at AND m.rowid > r.rowid))
+            ) as "unread_count!: i64"
+        FROM group_members gm
+        WHERE gm.user_id = ?
+        "#,
+        user_id
+    )
+    .fetch_all(executor)
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| UnreadCount {
+            group_id: row.group_id,
+            unread_count: row.unread_count as u32,
+            last_read_message_id: row.last_read_message_id,
+        })
+        .collect())
+}
+
+/// Gruppi, conversazioni dirette e non letti dell'utente: gli stessi dati del login, da aggiornare in qualsiasi momento.
+pub async fn get_my_groups(
+    claims: Claims,
+    State(app_state): State<AppState>,
+) -> Result<Json<UserGroups>, AppError> {
+    let groups = fetch_user_groups(&app_state.db_pool, claims.sub).await?;
+    let direct_conversations = fetch_direct_conversations(&app_state.db_pool, claims.sub).await?;
+    let unread = fetch_unread_counts(&app_state.db_pool, claims.sub).await?;
+    Ok(Json(UserGroups { groups, direct_conversations, unread }))
+}
+
+pub async fn refresh_session(
+    State(app_state): State<AppState>,
+    Json(payload): Json<RefreshPayload>,
+) -> Result<Json<RefreshResponse>, AppError> {
+    let presented_hash = hash_refresh_token(&payload.refresh_token);
+    let mut tx = app_state.db_pool.begin().await?;
+
+    let session = sqlx::query!(
+        r#"
+        SELECT s.id as "id!: uuid::Uuid", s.user_id as "user_id!: uuid::Uuid", u.username
+        FROM sessions s
+        JOIN users u ON s.user_id = u.id
+        WHERE s.refresh_token_hash = ?
+          AND s.revoked_at IS NULL
+          AND s.expires_at > strftime('%Y-%m-%dT%H:%M:%SZ','now')
+        "#,
+        presented_hash
+    )
+    .fetch_optional(&mut *tx)
     .await?;
 
-    let now = Utc::now();
-    let claims = Claims {
-        sub: user.id,
-        iat: now.timestamp(),
-        exp: (now + Duration::days(1)).timestamp(),
-        username: user.username.clone(),
+    let Some(session) = session else {
+        // Un token già ruotato che viene ripresentato indica un furto: si chiude la sessione
+        let revoked = sqlx::query_scalar!(
+            "UPDATE sessions SET revoked_at = strftime('%Y-%m-%dT%H:%M:%SZ','now') WHERE previous_token_hash = ? AND revoked_at IS NULL RETURNING id as \"id!: uuid::Uuid\"",
+            presented_hash
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        if !revoked.is_empty() {
+            tracing::warn!("Refresh token riutilizzato: sessione revocata.");
+            close_session_sockets(&app_state.session_sockets, &revoked);
+        }
+        return Err(AppError::InvalidRefreshToken);
     };
 
-    let token = encode(
-        &Header::default(),
-        &claims,
-        &EncodingKey::from_secret(app_state.jwt_secret.as_ref()),
-    )?;
+    // Rotazione: il vecchio token non è più valido e la sessione viene prolungata
+    let (refresh_token, refresh_token_hash) = generate_refresh_token();
+    let session_expiry = format!("+{} days", auth::SESSION_TTL_DAYS);
+    sqlx::query!(
+        "UPDATE sessions SET previous_token_hash = refresh_token_hash, refresh_token_hash = ?, expires_at = strftime('%Y-%m-%dT%H:%M:%SZ','now', ?) WHERE id = ?",
+        refresh_token_hash,
+        session_expiry,
+        session.id
+    )
+    .execute(&mut *tx)
+    .await?;
 
-    Ok(Json(LoginResponse {
+    tx.commit().await?;
+
+    let token = create_access_token(&app_state, session.user_id, &session.username, session.id)?;
+
+    Ok(Json(RefreshResponse {
         token,
-        user,
-        groups: user_groups,
+        refresh_token,
     }))
 }
 
+pub async fn logout_user(
+    claims: Claims,
+    State(app_state): State<AppState>,
+) -> Result<StatusCode, AppError> {
+    sqlx::query!(
+        "UPDATE sessions SET revoked_at = strftime('%Y-%m-%dT%H:%M:%SZ','now') WHERE id = ? AND revoked_at IS NULL",
+        claims.sid
+    )
+    .execute(&app_state.db_pool)
+    .await?;
+    close_session_sockets(&app_state.session_sockets, &[claims.sid]);
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
 pub async fn get_user_by_username(
     State(app_state): State<AppState>,
     Path(username): Path<String>,
 ) -> Result<Json<User>, AppError> {
     sqlx::query_as!(
         User,
-        "SELECT id \"id!: uuid::Uuid\", username, password_hash, created_at as \"created_at!: sqlx::types::time::OffsetDateTime\" FROM users WHERE username = ?",
+        "SELECT id \"id!: uuid::Uuid\", username, created_at as \"created_at!: sqlx::types::time::OffsetDateTime\", display_name, bio, status_message, avatar_sha256 FROM users WHERE username = ? AND deleted_at IS NULL",
         username
     )
     .fetch_optional(&app_state.db_pool)
@@ -180,27 +736,371 @@ pub async fn get_user_by_username(
     .ok_or(AppError::UserNotFound)
 }
 
-// --- Handler Protetti con Auth ---
+// --- Profilo utente ---
 
-pub async fn create_group(
+async fn fetch_user<'e, E>(executor: E, user_id: Uuid) -> Result<User, AppError>
+where
+    E: Executor<'e, Database = Sqlite>,
+{
+    sqlx::query_as!(
+        User,
+        "SELECT id \"id!: uuid::Uuid\", username, created_at as \"created_at!: sqlx::types::time::OffsetDateTime\", display_name, bio, status_message, avatar_sha256 FROM users WHERE id = ?",
+        user_id
+    )
+    .fetch_optional(executor)
+    .await?
+    .ok_or(AppError::UserNotFound)
+}
+
+pub async fn get_me(
     claims: Claims,
     State(app_state): State<AppState>,
-    Json(payload): Json<CreateGroupPayload>,
-) -> Result<Json<Group>, AppError> {
+) -> Result<Json<User>, AppError> {
+    fetch_user(&app_state.db_pool, claims.sub).await.map(Json)
+}
+
+/// Profilo di un altro utente, per la scheda mostrata dal client.
+pub async fn get_user_profile(
+    _claims: Claims,
+    State(app_state): State<AppState>,
+    Path(user_id): Path<Uuid>,
+) -> Result<Json<User>, AppError> {
+    fetch_user(&app_state.db_pool, user_id).await.map(Json)
+}
+
+/// Legge il corpo di `PATCH /users/me`, in JSON o, se c'è un nuovo avatar, in multipart.
+async fn read_profile_update(request: Request) -> Result<(UpdateProfilePayload, Option<AvatarUpload>), AppError> {
+    if !is_multipart(&request) {
+        return Ok((json_body(request).await?, None));
+    }
+
+    let mut multipart = multipart_body(request).await?;
+    let mut payload = UpdateProfilePayload::default();
+    let mut avatar = None;
+    while let Some(field) = multipart.next_field().await.map_err(multipart_error)? {
+        match field.name() {
+            Some("display_name") => payload.display_name = Some(text_field(field).await?),
+            Some("bio") => payload.bio = Some(text_field(field).await?),
+            Some("status_message") => payload.status_message = Some(text_field(field).await?),
+            Some("remove_avatar") => payload.remove_avatar = matches!(text_field(field).await?.trim(), "true" | "1"),
+            Some("avatar") => avatar = Some(read_avatar(field).await?),
+            _ => continue,
+        }
+    }
+    Ok((payload, avatar))
+}
+
+/// Aggiorna il profilo dell'utente e lo comunica a tutti i suoi gruppi.
+pub async fn update_me(
+    claims: Claims,
+    State(app_state): State<AppState>,
+    request: Request,
+) -> Result<Json<User>, AppError> {
+    let (payload, avatar) = read_profile_update(request).await?;
+    let display_name = payload
+        .display_name
+        .as_deref()
+        .map(|text| profile_text(text, MAX_DISPLAY_NAME_CHARS, "display name"))
+        .transpose()?;
+    let bio = payload.bio.as_deref().map(|text| profile_text(text, MAX_BIO_CHARS, "bio")).transpose()?;
+    let status_message = payload
+        .status_message
+        .as_deref()
+        .map(|text| profile_text(text, MAX_STATUS_MESSAGE_CHARS, "status message"))
+        .transpose()?;
+    let avatar = store_avatar(&app_state, avatar, payload.remove_avatar).await?;
+
+    let mut tx = app_state.db_pool.begin().await?;
+    let mut user = fetch_user(&mut *tx, claims.sub).await?;
+    if let Some(display_name) = display_name {
+        user.display_name = display_name;
+    }
+    if let Some(bio) = bio {
+        user.bio = bio;
+    }
+    if let Some(status_message) = status_message {
+        user.status_message = status_message;
+    }
+    sqlx::query!(
+        "UPDATE users SET display_name = ?, bio = ?, status_message = ? WHERE id = ?",
+        user.display_name, user.bio, user.status_message, claims.sub
+    )
+    .execute(&mut *tx)
+    .await?;
+    if let Some(avatar) = avatar {
+        let (sha256, mime_type) = avatar.unzip();
+        sqlx::query!("UPDATE users SET avatar_sha256 = ?, avatar_mime_type = ? WHERE id = ?", sha256, mime_type, claims.sub)
+            .execute(&mut *tx)
+            .await?;
+        user.avatar_sha256 = sha256;
+    }
+    tx.commit().await?;
+
+    broadcast_to_user_groups(&app_state, claims.sub, &WsServerEvent::UserUpdated { user: user.clone() }).await;
+    Ok(Json(user))
+}
+
+pub async fn get_user_avatar(
+    _claims: Claims,
+    State(app_state): State<AppState>,
+    Path(user_id): Path<Uuid>,
+) -> Result<Response, AppError> {
+    let user = sqlx::query!("SELECT avatar_sha256, avatar_mime_type FROM users WHERE id = ?", user_id)
+        .fetch_optional(&app_state.db_pool)
+        .await?
+        .ok_or(AppError::UserNotFound)?;
+    avatar_response(&app_state, user.avatar_sha256, user.avatar_mime_type).await
+}
+
+// --- Gestione dell'account ---
+
+/// Validità di un token di reset della password dall'emissione.
+const PASSWORD_RESET_TTL_HOURS: i64 = 24;
+
+fn validate_new_password(password: &str) -> Result<(), AppError> {
+    if password.len() < MIN_PASSWORD_LEN {
+        return Err(AppError::InvalidInput(format!(
+            "Password must be at least {} characters long.",
+            MIN_PASSWORD_LEN
+        )));
+    }
+    Ok(())
+}
+
+/// Conferma l'identità dell'utente prima di un'operazione sull'account.
+async fn verify_current_password<'e, E>(executor: E, user_id: Uuid, password: &str) -> Result<(), AppError>
+where
+    E: Executor<'e, Database = Sqlite>,
+{
+    let password_hash = sqlx::query_scalar!("SELECT password_hash FROM users WHERE id = ?", user_id)
+        .fetch_optional(executor)
+        .await?
+        .ok_or(AppError::UserNotFound)?;
+    if !verify(password, &password_hash).unwrap_or(false) {
+        return Err(AppError::WrongPassword);
+    }
+    Ok(())
+}
+
+/// Cambia la password e chiude tutte le altre sessioni dell'utente.
+pub async fn change_password(
+    claims: Claims,
+    State(app_state): State<AppState>,
+    Json(payload): Json<ChangePasswordPayload>,
+) -> Result<StatusCode, AppError> {
+    validate_new_password(&payload.new_password)?;
+    verify_current_password(&app_state.db_pool, claims.sub, &payload.old_password).await?;
+
+    let password_hash = hash(payload.new_password, DEFAULT_COST)?;
+    let mut tx = app_state.db_pool.begin().await?;
+    sqlx::query!("UPDATE users SET password_hash = ? WHERE id = ?", password_hash, claims.sub)
+        .execute(&mut *tx)
+        .await?;
+    let revoked = sqlx::query_scalar!(
+        "UPDATE sessions SET revoked_at = strftime('%Y-%m-%dT%H:%M:%SZ','now') WHERE user_id = ? AND id != ? AND revoked_at IS NULL RETURNING id as \"id!: uuid::Uuid\"",
+        claims.sub, claims.sid
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+    close_session_sockets(&app_state.session_sockets, &revoked);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Emette un token di reset monouso per `user_id`; riservato agli amministratori del server.
+/// Un nuovo token annulla quelli non ancora usati.
+pub async fn issue_password_reset(
+    claims: Claims,
+    State(app_state): State<AppState>,
+    Path(user_id): Path<Uuid>,
+) -> Result<Json<PasswordResetToken>, AppError> {
+    let mut tx = app_state.db_pool.begin().await?;
+
+    let is_admin = sqlx::query_scalar!("SELECT is_admin as \"is_admin!: bool\" FROM users WHERE id = ?", claims.sub)
+        .fetch_optional(&mut *tx)
+        .await?
+        .unwrap_or(false);
+    if !is_admin {
+        return Err(AppError::MissingPermissions);
+    }
+
+    let exists = sqlx::query_scalar!(
+        "SELECT EXISTS(SELECT 1 FROM users WHERE id = ? AND deleted_at IS NULL) as \"exists!: bool\"",
+        user_id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    if !exists {
+        return Err(AppError::UserNotFound);
+    }
+
+    sqlx::query!("DELETE FROM password_reset_tokens WHERE user_id = ? AND used_at IS NULL", user_id)
+        .execute(&mut *tx)
+        .await?;
+
+    // Stesso formato dei refresh token: casuale, salvato solo come hash
+    let (token, token_hash) = generate_refresh_token();
+    let ttl = format!("+{} hours", PASSWORD_RESET_TTL_HOURS);
+    let expires_at = sqlx::query_scalar!(
+        "INSERT INTO password_reset_tokens (user_id, token_hash, created_by, expires_at) VALUES (?, ?, ?, strftime('%Y-%m-%dT%H:%M:%SZ','now', ?)) RETURNING expires_at as \"expires_at!: sqlx::types::time::OffsetDateTime\"",
+        user_id, token_hash, claims.sub, ttl
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+    tracing::info!("Token di reset della password per {} emesso da {}.", user_id, claims.sub);
+    Ok(Json(PasswordResetToken { token, expires_at }))
+}
+
+/// Imposta una nuova password con un token di reset, senza login; revoca tutte le sessioni.
+pub async fn reset_password(
+    State(app_state): State<AppState>,
+    Json(payload): Json<ResetPasswordPayload>,
+) -> Result<StatusCode, AppError> {
+    validate_new_password(&payload.new_password)?;
+    let token_hash = hash_refresh_token(payload.token.trim());
+
+    let mut tx = app_state.db_pool.begin().await?;
+    let user_id = sqlx::query_scalar!(
+        r#"
+        UPDATE password_reset_tokens SET used_at = strftime('%Y-%m-%dT%H:%M:%SZ','now')
+        WHERE token_hash = ? AND used_at IS NULL AND expires_at > strftime('%Y-%m-%dT%H:%M:%SZ','now')
+        RETURNING user_id as "user_id!: uuid::Uuid"
+        "#,
+        token_hash
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or(AppError::InvalidResetToken)?;
+
+    let password_hash = hash(payload.new_password, DEFAULT_COST)?;
+    sqlx::query!("UPDATE users SET password_hash = ? WHERE id = ?", password_hash, user_id)
+        .execute(&mut *tx)
+        .await?;
+    let revoked = sqlx::query_scalar!(
+        "UPDATE sessions SET revoked_at = strftime('%Y-%m-%dT%H:%M:%SZ','now') WHERE user_id = ? AND revoked_at IS NULL RETURNING id as \"id!: uuid::Uuid\"",
+        user_id
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+    close_session_sockets(&app_state.session_sockets, &revoked);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Elimina l'account: l'utente esce da tutti i gruppi e perde credenziali e profilo,
+/// ma la riga in `users` resta anonima perché i suoi messaggi non vengano cancellati.
+pub async fn delete_account(
+    claims: Claims,
+    State(app_state): State<AppState>,
+    Json(payload): Json<DeleteAccountPayload>,
+) -> Result<StatusCode, AppError> {
+    let user_id = claims.sub;
+    verify_current_password(&app_state.db_pool, user_id, &payload.password).await?;
+
+    // La transazione comincia con una scrittura: in SQLite una transazione che ha già letto
+    // non può più scrivere se nel frattempo un'altra connessione ha modificato il database
+    let mut tx = app_state.db_pool.begin().await?;
+    let session_ids = sqlx::query_scalar!("DELETE FROM sessions WHERE user_id = ? RETURNING id as \"id!: uuid::Uuid\"", user_id)
+        .fetch_all(&mut *tx)
+        .await?;
+
+    // Le conversazioni dirette restano, così l'altro partecipante conserva la cronologia
+    let group_ids = sqlx::query_scalar!(
+        r#"
+        SELECT group_id as "group_id!: uuid::Uuid" FROM group_members
+        WHERE user_id = ? AND group_id NOT IN (SELECT group_id FROM direct_conversations)
+        "#,
+        user_id
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+    let mut departures = Vec::new();
+    for group_id in group_ids {
+        departures.extend(remove_from_group(&mut tx, user_id, group_id).await?);
+    }
+    let conversation_ids = sqlx::query_scalar!(
+        "SELECT group_id as \"group_id!: uuid::Uuid\" FROM direct_conversations WHERE user_a = ? OR user_b = ?",
+        user_id, user_id
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    sqlx::query!("DELETE FROM password_reset_tokens WHERE user_id = ?", user_id).execute(&mut *tx).await?;
+    sqlx::query!(
+        "DELETE FROM group_invitations WHERE status = 'pending' AND (invited_user_id = ? OR inviter_id = ?)",
+        user_id, user_id
+    )
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query!("DELETE FROM group_join_requests WHERE user_id = ?", user_id).execute(&mut *tx).await?;
+    sqlx::query!("DELETE FROM group_invite_links WHERE created_by = ?", user_id).execute(&mut *tx).await?;
+    sqlx::query!("DELETE FROM message_mentions WHERE user_id = ?", user_id).execute(&mut *tx).await?;
+
+    // Lo username torna libero; la password vuota non corrisponde a nessun hash bcrypt
+    sqlx::query!(
+        r#"
+        UPDATE users
+        SET username = 'deleted-' || lower(hex(id)), password_hash = '', display_name = ?,
+            bio = NULL, status_message = NULL, avatar_sha256 = NULL, avatar_mime_type = NULL,
+            is_admin = 0, deleted_at = strftime('%Y-%m-%dT%H:%M:%SZ','now')
+        WHERE id = ?
+        "#,
+        DELETED_USER_NAME, user_id
+    )
+    .execute(&mut *tx)
+    .await?;
+    let user = fetch_user(&mut *tx, user_id).await?;
+
+    tx.commit().await?;
+    tracing::info!("Account {} ({}) eliminato.", user_id, claims.username);
+
+    for departure in departures {
+        announce_departure(&app_state, user_id, &claims.username, departure).await?;
+    }
+    // Nelle conversazioni dirette rimaste i messaggi passano al nome anonimo
+    broadcast_to_user_groups(&app_state, user_id, &WsServerEvent::UserUpdated { user }).await;
+
+    // Nessuna connessione dell'utente resta aperta, nemmeno sulle conversazioni dirette
+    for conversation_id in conversation_ids {
+        if let Some(chat) = app_state.chat_state.get(&conversation_id) {
+            let _ = chat.send(ChatEvent::Disconnect(user_id));
+        }
+    }
+    app_state.user_channels.remove(&user_id);
+    close_session_sockets(&app_state.session_sockets, &session_ids);
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// --- Handler Protetti con Auth ---
+
+pub async fn create_group(
+    claims: Claims,
+    State(app_state): State<AppState>,
+    Json(payload): Json<CreateGroupPayload>,
+) -> Result<Json<Group>, AppError> {
     let creator_id = claims.sub;
     let mut tx = app_state.db_pool.begin().await?;
 
-    let new_group = sqlx::query_as!(Group, "INSERT INTO groups (name) VALUES (?) RETURNING
+    let new_group = sqlx::query_as!(Group, "INSERT INTO groups (name, visibility) VALUES (?, ?) RETURNING
             id          AS \"id!: uuid::Uuid\",
             name,
-            created_at  AS \"created_at!: sqlx::types::time::OffsetDateTime\"
+            created_at  AS \"created_at!: sqlx::types::time::OffsetDateTime\",
+            visibility  AS \"visibility!: GroupVisibility\",
+            description,
+            topic,
+            avatar_sha256
         ",
-        payload.name)
+        payload.name, payload.visibility)
         .fetch_one(&mut *tx)
         .await?;
 
     sqlx::query!(
-        "INSERT INTO group_members (user_id, group_id) VALUES (?, ?)",
+        "INSERT INTO group_members (user_id, group_id, role) VALUES (?, ?, 'owner')",
         creator_id,
         new_group.id
     )
@@ -211,207 +1111,2014 @@ pub async fn create_group(
     Ok(Json(new_group))
 }
 
-pub async fn invite_to_group(
+/// Immagine ricevuta come nuovo avatar di un gruppo o di un utente.
+struct AvatarUpload {
+    mime_type: String,
+    bytes: Vec<u8>,
+}
+
+fn is_multipart(request: &Request) -> bool {
+    request
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("multipart/form-data"))
+}
+
+async fn multipart_body(request: Request) -> Result<Multipart, AppError> {
+    Multipart::from_request(request, &())
+        .await
+        .map_err(|e| AppError::InvalidInput(e.body_text()))
+}
+
+async fn json_body<T: serde::de::DeserializeOwned>(request: Request) -> Result<T, AppError> {
+    let Json(payload) = Json::<T>::from_request(request, &())
+        .await
+        .map_err(|e| AppError::InvalidInput(e.body_text()))?;
+    Ok(payload)
+}
+
+async fn text_field(field: Field<'_>) -> Result<String, AppError> {
+    field.text().await.map_err(multipart_error)
+}
+
+/// Legge l'immagine del campo `avatar`, controllandone tipo e dimensione.
+async fn read_avatar(mut field: Field<'_>) -> Result<AvatarUpload, AppError> {
+    let mime_type = field.content_type().unwrap_or("application/octet-stream").to_string();
+    if !ALLOWED_AVATAR_TYPES.contains(&mime_type.as_str()) {
+        return Err(AppError::UnsupportedMediaType);
+    }
+
+    let mut bytes = Vec::new();
+    while let Some(chunk) = field.chunk().await.map_err(multipart_error)? {
+        if (bytes.len() + chunk.len()) as u64 > MAX_AVATAR_SIZE {
+            return Err(AppError::InvalidInput(format!("The avatar cannot exceed {} KB.", MAX_AVATAR_SIZE / 1024)));
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    if bytes.is_empty() {
+        return Err(AppError::InvalidInput("The avatar is empty.".to_string()));
+    }
+    Ok(AvatarUpload { mime_type, bytes })
+}
+
+/// Salva il nuovo avatar su disco. `Some(Some((sha256, mime_type)))` sostituisce l'avatar,
+/// `Some(None)` lo toglie e `None` lo lascia com'è.
+async fn store_avatar(app_state: &AppState, avatar: Option<AvatarUpload>, remove: bool) -> Result<Option<Option<(String, String)>>, AppError> {
+    Ok(match avatar {
+        Some(upload) => Some(Some((attachments::store(&app_state.attachments_dir, &upload.bytes).await?, upload.mime_type))),
+        None if remove => Some(None),
+        None => None,
+    })
+}
+
+/// Legge il corpo di `PATCH /groups/:group_id`, in JSON o, se c'è un nuovo avatar, in multipart.
+async fn read_group_update(request: Request) -> Result<(UpdateGroupPayload, Option<AvatarUpload>), AppError> {
+    if !is_multipart(&request) {
+        return Ok((json_body(request).await?, None));
+    }
+
+    let mut multipart = multipart_body(request).await?;
+    let mut payload = UpdateGroupPayload::default();
+    let mut avatar = None;
+    while let Some(field) = multipart.next_field().await.map_err(multipart_error)? {
+        match field.name() {
+            Some("name") => payload.name = Some(text_field(field).await?),
+            Some("description") => payload.description = Some(text_field(field).await?),
+            Some("topic") => payload.topic = Some(text_field(field).await?),
+            Some("remove_avatar") => payload.remove_avatar = matches!(text_field(field).await?.trim(), "true" | "1"),
+            Some("avatar") => avatar = Some(read_avatar(field).await?),
+            _ => continue,
+        }
+    }
+    Ok((payload, avatar))
+}
+
+/// Ripulisce un campo di testo di un profilo: un testo vuoto toglie il campo.
+fn profile_text(text: &str, max_chars: usize, field: &str) -> Result<Option<String>, AppError> {
+    let text = text.trim();
+    if text.chars().count() > max_chars {
+        return Err(AppError::InvalidInput(format!("The {} cannot exceed {} characters.", field, max_chars)));
+    }
+    Ok((!text.is_empty()).then(|| text.to_string()))
+}
+
+/// Aggiorna nome, descrizione, argomento o avatar del gruppo, registrando ogni campo cambiato nella cronologia.
+pub async fn update_group(
     claims: Claims,
     State(app_state): State<AppState>,
     Path(group_id): Path<Uuid>,
-    Json(payload): Json<InviteToGroupPayload>,
-) -> Result<StatusCode, AppError> {
-    let inviter_id = claims.sub;
+    request: Request,
+) -> Result<Json<Group>, AppError> {
+    let (payload, avatar) = read_group_update(request).await?;
+    require_role(&app_state.db_pool, claims.sub, group_id, GroupRole::Admin).await?;
 
-    if inviter_id == payload.user_to_invite_id {
-        return Err(AppError::CannotInviteSelf);
-    }
-    
-    let mut tx = app_state.db_pool.begin().await?;
+    let name = match payload.name.as_deref().map(str::trim) {
+        Some("") => return Err(AppError::InvalidInput("Group name cannot be empty.".to_string())),
+        name => name.map(str::to_string),
+    };
+    let description = payload
+        .description
+        .as_deref()
+        .map(|text| profile_text(text, MAX_GROUP_DESCRIPTION_CHARS, "group description"))
+        .transpose()?;
+    let topic = payload
+        .topic
+        .as_deref()
+        .map(|text| profile_text(text, MAX_GROUP_TOPIC_CHARS, "group topic"))
+        .transpose()?;
+    let avatar = store_avatar(&app_state, avatar, payload.remove_avatar).await?;
 
-    let is_inviter_a_member: (bool,) = sqlx::query_as("SELECT EXISTS(SELECT 1 FROM group_members WHERE user_id = ? AND group_id = ?)")
-        .bind(inviter_id)
-        .bind(group_id)
-        .fetch_one(&mut *tx).await?;
+    let mut tx = app_state.db_pool.begin().await?;
+    let current = fetch_group(&mut *tx, group_id).await?;
+    let mut group = current.clone();
+    let mut changes = Vec::new();
 
-    if !is_inviter_a_member.0 {
-        return Err(AppError::MissingPermissions);
+    if let Some(name) = name.filter(|name| *name != current.name) {
+        changes.push((GroupProfileField::Name, Some(current.name.clone()), Some(name.clone())));
+        group.name = name;
+    }
+    if let Some(description) = description.filter(|description| *description != current.description) {
+        changes.push((GroupProfileField::Description, current.description.clone(), description.clone()));
+        group.description = description;
+    }
+    if let Some(topic) = topic.filter(|topic| *topic != current.topic) {
+        changes.push((GroupProfileField::Topic, current.topic.clone(), topic.clone()));
+        group.topic = topic;
+    }
+    let avatar = avatar.filter(|avatar| avatar.as_ref().map(|(sha256, _)| sha256) != current.avatar_sha256.as_ref());
+    if let Some(avatar) = &avatar {
+        let sha256 = avatar.as_ref().map(|(sha256, _)| sha256.clone());
+        changes.push((GroupProfileField::Avatar, current.avatar_sha256.clone(), sha256.clone()));
+        group.avatar_sha256 = sha256;
     }
 
-    let is_already_member: (bool,) = sqlx::query_as("SELECT EXISTS(SELECT 1 FROM group_members WHERE user_id = ? AND group_id = ?)")
-        .bind(payload.user_to_invite_id).bind(group_id)
-        .fetch_one(&mut *tx).await?;
-
-    if is_already_member.0 {
-        return Err(AppError::UserAlreadyInGroup);
+    if changes.is_empty() {
+        return Ok(Json(current));
     }
 
-    let result = sqlx::query!(
-        "INSERT INTO group_invitations (group_id, inviter_id, invited_user_id, status) VALUES (?, ?, ?, 'pending')",
-        group_id, inviter_id, payload.user_to_invite_id
+    sqlx::query!(
+        "UPDATE groups SET name = ?, description = ?, topic = ? WHERE id = ?",
+        group.name, group.description, group.topic, group_id
     )
-    .execute(&mut *tx).await;
-    
-    match result {
-        Ok(_) => {
-            tx.commit().await?;
-            Ok(StatusCode::CREATED)
-        }
-        Err(e) => {
-            if let Some(db_err) = e.as_database_error() {
-                if db_err.is_unique_violation() { return Err(AppError::InvitationAlreadyExists); }
-                if db_err.is_foreign_key_violation() { return Err(AppError::UserOrGroupNotFound); }
-            }
-            Err(e.into())
-        }
+    .execute(&mut *tx)
+    .await?;
+    if let Some(avatar) = avatar {
+        let (sha256, mime_type) = avatar.unzip();
+        sqlx::query!("UPDATE groups SET avatar_sha256 = ?, avatar_mime_type = ? WHERE id = ?", sha256, mime_type, group_id)
+            .execute(&mut *tx)
+            .await?;
+    }
+    for (field, old_value, new_value) in changes {
+        sqlx::query!(
+            "INSERT INTO group_profile_changes (group_id, changed_by, field, old_value, new_value) VALUES (?, ?, ?, ?, ?)",
+            group_id, claims.sub, field, old_value, new_value
+        )
+        .execute(&mut *tx)
+        .await?;
     }
+    tx.commit().await?;
+
+    broadcast_event(
+        &app_state.chat_state,
+        group_id,
+        &WsServerEvent::GroupUpdated { group: group.clone(), by_username: claims.username },
+    );
+    Ok(Json(group))
 }
 
-pub async fn get_pending_invitations(
+/// Modifiche del profilo del gruppo, dalla più recente.
+pub async fn get_group_history(
     claims: Claims,
     State(app_state): State<AppState>,
-) -> Result<Json<Vec<Invitation>>, AppError> {
-    sqlx::query_as!(
-        Invitation,
+    Path(group_id): Path<Uuid>,
+) -> Result<Json<Vec<GroupProfileChange>>, AppError> {
+    member_role(&app_state.db_pool, claims.sub, group_id).await?;
+
+    let changes = sqlx::query_as!(
+        GroupProfileChange,
         r#"
-        SELECT gi.id as "id!: uuid::Uuid", g.id as "group_id!: uuid::Uuid", g.name as "group_name", u.username as "inviter_username"
-        FROM group_invitations gi
-        JOIN groups g ON gi.group_id = g.id
-        JOIN users u ON gi.inviter_id = u.id
-        WHERE gi.invited_user_id = ? AND gi.status = 'pending'
+        SELECT c.field as "field!: GroupProfileField", c.old_value, c.new_value,
+               u.username as "changed_by_username?",
+               c.created_at as "created_at!: sqlx::types::time::OffsetDateTime"
+        FROM group_profile_changes c
+        LEFT JOIN users u ON c.changed_by = u.id
+        WHERE c.group_id = ?
+        ORDER BY c.created_at DESC, c.rowid DESC
+        LIMIT ?
         "#,
-        claims.sub
+        group_id, PROFILE_HISTORY_LIMIT
+    )
+    .fetch_all(&app_state.db_pool)
+    .await?;
+    Ok(Json(changes))
+}
+
+/// Avatar del gruppo, visibile ai membri e, per i gruppi pubblici, a chiunque nell'elenco.
+pub async fn get_group_avatar(
+    claims: Claims,
+    State(app_state): State<AppState>,
+    Path(group_id): Path<Uuid>,
+) -> Result<Response, AppError> {
+    let group = sqlx::query!(
+        "SELECT visibility as \"visibility!: GroupVisibility\", avatar_sha256, avatar_mime_type FROM groups WHERE id = ?",
+        group_id
+    )
+    .fetch_optional(&app_state.db_pool)
+    .await?
+    .ok_or(AppError::GroupNotFound)?;
+    if group.visibility != GroupVisibility::Public {
+        member_role(&app_state.db_pool, claims.sub, group_id).await?;
+    }
+
+    avatar_response(&app_state, group.avatar_sha256, group.avatar_mime_type).await
+}
+
+/// Risposta con l'immagine di un avatar, o `AttachmentNotFound` se non è impostato.
+async fn avatar_response(app_state: &AppState, sha256: Option<String>, mime_type: Option<String>) -> Result<Response, AppError> {
+    let (Some(sha256), Some(mime_type)) = (sha256, mime_type) else {
+        return Err(AppError::AttachmentNotFound);
+    };
+    let bytes = attachments::read(&app_state.attachments_dir, &sha256).await?;
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, mime_type),
+            (header::CONTENT_DISPOSITION, "inline".to_string()),
+            (header::X_CONTENT_TYPE_OPTIONS, "nosniff".to_string()),
+            (header::ETAG, format!("\"{}\"", sha256)),
+        ],
+        bytes,
+    )
+        .into_response())
+}
+
+pub async fn delete_group(
+    claims: Claims,
+    State(app_state): State<AppState>,
+    Path(group_id): Path<Uuid>,
+) -> Result<StatusCode, AppError> {
+    let mut tx = app_state.db_pool.begin().await?;
+    require_role(&mut *tx, claims.sub, group_id, GroupRole::Owner).await?;
+
+    // Membri, inviti e messaggi vengono rimossi dalle FK con ON DELETE CASCADE
+    sqlx::query!("DELETE FROM groups WHERE id = ?", group_id)
+        .execute(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+    // Ultimo evento del gruppo, poi tutte le connessioni ancora aperte vengono chiuse
+    broadcast_event(&app_state.chat_state, group_id, &WsServerEvent::GroupDeleted { by_username: claims.username });
+    close_group_channel(&app_state.chat_state, group_id);
+    tracing::info!("Gruppo {} eliminato dal proprietario {}.", group_id, claims.sub);
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Cede la proprietà del gruppo a un altro membro; il vecchio proprietario resta come admin.
+pub async fn transfer_ownership(
+    claims: Claims,
+    State(app_state): State<AppState>,
+    Path(group_id): Path<Uuid>,
+    Json(payload): Json<TransferOwnershipPayload>,
+) -> Result<StatusCode, AppError> {
+    let new_owner_id = payload.new_owner_id;
+    if new_owner_id == claims.sub {
+        return Err(AppError::InvalidInput("You already own this group.".to_string()));
+    }
+
+    let mut tx = app_state.db_pool.begin().await?;
+    require_role(&mut *tx, claims.sub, group_id, GroupRole::Owner).await?;
+
+    let result = sqlx::query!(
+        "UPDATE group_members SET role = 'owner' WHERE user_id = ? AND group_id = ?",
+        new_owner_id, group_id
+    )
+    .execute(&mut *tx)
+    .await?;
+    if result.rows_affected() == 0 {
+        return Err(AppError::UserNotInGroup);
+    }
+    sqlx::query!(
+        "UPDATE group_members SET role = 'admin' WHERE user_id = ? AND group_id = ?",
+        claims.sub, group_id
+    )
+    .execute(&mut *tx)
+    .await?;
+    let new_owner_username = sqlx::query_scalar!("SELECT username FROM users WHERE id = ?", new_owner_id)
+        .fetch_one(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+    broadcast_event(
+        &app_state.chat_state,
+        group_id,
+        &WsServerEvent::OwnershipTransferred { previous_owner_id: claims.sub, new_owner_id, new_owner_username },
+    );
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn update_member_role(
+    claims: Claims,
+    State(app_state): State<AppState>,
+    Path((group_id, member_id)): Path<(Uuid, Uuid)>,
+    Json(payload): Json<UpdateMemberRolePayload>,
+) -> Result<StatusCode, AppError> {
+    // Esiste un solo proprietario: questa rotta gestisce soltanto admin e membri
+    if payload.role == GroupRole::Owner {
+        return Err(AppError::InvalidInput("Ownership cannot be assigned by changing a role.".to_string()));
+    }
+    if member_id == claims.sub {
+        return Err(AppError::InvalidInput("You cannot change your own role.".to_string()));
+    }
+
+    let mut tx = app_state.db_pool.begin().await?;
+    require_role(&mut *tx, claims.sub, group_id, GroupRole::Owner).await?;
+
+    let result = sqlx::query!(
+        "UPDATE group_members SET role = ? WHERE user_id = ? AND group_id = ?",
+        payload.role, member_id, group_id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(AppError::UserNotInGroup);
+    }
+
+    tx.commit().await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn invite_to_group(
+    claims: Claims,
+    State(app_state): State<AppState>,
+    Path(group_id): Path<Uuid>,
+    Json(payload): Json<InviteToGroupPayload>,
+) -> Result<StatusCode, AppError> {
+    let inviter_id = claims.sub;
+
+    if inviter_id == payload.user_to_invite_id {
+        return Err(AppError::CannotInviteSelf);
+    }
+    
+    let mut tx = app_state.db_pool.begin().await?;
+
+    require_role(&mut *tx, inviter_id, group_id, GroupRole::Admin).await?;
+
+    let is_already_member: (bool,) = sqlx::query_as("SELECT EXISTS(SELECT 1 FROM group_members WHERE user_id = ? AND group_id = ?)")
+        .bind(payload.user_to_invite_id).bind(group_id)
+        .fetch_one(&mut *tx).await?;
+
+    if is_already_member.0 {
+        return Err(AppError::UserAlreadyInGroup);
+    }
+
+    ensure_not_banned(&mut *tx, payload.user_to_invite_id, group_id).await?;
+
+    // Gli inviti già gestiti (es. di chi è stato rimosso o è uscito) non devono bloccarne uno nuovo
+    sqlx::query!(
+        "DELETE FROM group_invitations WHERE group_id = ? AND invited_user_id = ? AND status != 'pending'",
+        group_id, payload.user_to_invite_id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    let result = sqlx::query_scalar!(
+        "INSERT INTO group_invitations (group_id, inviter_id, invited_user_id, status) VALUES (?, ?, ?, 'pending') RETURNING id as \"id!: uuid::Uuid\"",
+        group_id, inviter_id, payload.user_to_invite_id
+    )
+    .fetch_one(&mut *tx).await;
+    
+    match result {
+        Ok(invitation_id) => {
+            let group_name = sqlx::query_scalar!("SELECT name FROM groups WHERE id = ?", group_id)
+                .fetch_one(&mut *tx)
+                .await?;
+            tx.commit().await?;
+
+            // L'invitato lo riceve subito se è connesso a `/ws`
+            notify_user(
+                &app_state.user_channels,
+                payload.user_to_invite_id,
+                WsMuxEvent::InvitationReceived(Invitation {
+                    id: invitation_id,
+                    group_id,
+                    group_name,
+                    inviter_username: claims.username,
+                }),
+            );
+            Ok(StatusCode::CREATED)
+        }
+        Err(e) => {
+            if let Some(db_err) = e.as_database_error() {
+                if db_err.is_unique_violation() { return Err(AppError::InvitationAlreadyExists); }
+                if db_err.is_foreign_key_violation() { return Err(AppError::UserOrGroupNotFound); }
+            }
+            Err(e.into())
+        }
+    }
+}
+
+pub async fn get_pending_invitations(
+    claims: Claims,
+    State(app_state): State<AppState>,
+) -> Result<Json<Vec<Invitation>>, AppError> {
+    sqlx::query_as!(
+        Invitation,
+        r#"
+        SELECT gi.id as "id!: uuid::Uuid", g.id as "group_id!: uuid::Uuid", g.name as "group_name", u.username as "inviter_username"
+        FROM group_invitations gi
+        JOIN groups g ON gi.group_id = g.id
+        JOIN users u ON gi.inviter_id = u.id
+        WHERE gi.invited_user_id = ? AND gi.status = 'pending'
+        "#,
+        claims.sub
+    )
+    .fetch_all(&app_state.db_pool)
+    .await
+    .map(Json)
+    .map_err(Into::into)
+}
+
+pub async fn accept_invitation(
+    claims: Claims,
+    State(app_state): State<AppState>,
+    Path(invitation_id): Path<Uuid>,
+) -> Result<Json<Group>, AppError> {
+    let user_id = claims.sub;
+    let mut tx = app_state.db_pool.begin().await?;
+
+    let invitation = sqlx::query!(
+        "SELECT group_id as \"group_id!: uuid::Uuid\", inviter_id as \"inviter_id!: uuid::Uuid\" FROM group_invitations WHERE id = ? AND invited_user_id = ? AND status = 'pending'",
+        invitation_id, user_id
+    )
+    .fetch_optional(&mut *tx).await?
+    .ok_or(AppError::InvitationNotFound)?;
+
+    ensure_not_banned(&mut *tx, user_id, invitation.group_id).await?;
+
+    sqlx::query!("UPDATE group_invitations SET status = 'accepted' WHERE id = ?", invitation_id)
+        .execute(&mut *tx)
+        .await?;
+
+    sqlx::query!("INSERT INTO group_members (user_id, group_id) VALUES (?, ?) ON CONFLICT DO NOTHING", user_id, invitation.group_id)
+        .execute(&mut *tx)
+        .await?;
+
+    let group = fetch_group(&mut *tx, invitation.group_id).await?;
+
+    tx.commit().await?;
+
+    broadcast_event(
+        &app_state.chat_state,
+        group.id,
+        &WsServerEvent::MemberJoined { user_id, username: claims.username.clone() },
+    );
+    notify_user(
+        &app_state.user_channels,
+        invitation.inviter_id,
+        WsMuxEvent::InvitationAnswered {
+            invitation_id,
+            group_id: group.id,
+            group_name: group.name.clone(),
+            invited_username: claims.username,
+            accepted: true,
+        },
+    );
+
+    Ok(Json(group))
+}
+
+pub async fn decline_invitation(
+    claims: Claims,
+    State(app_state): State<AppState>,
+    Path(invitation_id): Path<Uuid>,
+) -> Result<StatusCode, AppError> {
+    let invitation = sqlx::query!(
+        r#"
+        UPDATE group_invitations SET status = 'declined'
+        WHERE id = ? AND invited_user_id = ? AND status = 'pending'
+        RETURNING group_id as "group_id!: uuid::Uuid", inviter_id as "inviter_id!: uuid::Uuid"
+        "#,
+        invitation_id, claims.sub
+    )
+    .fetch_optional(&app_state.db_pool)
+    .await?
+    .ok_or(AppError::InvitationNotFound)?;
+
+    let group_name = sqlx::query_scalar!("SELECT name FROM groups WHERE id = ?", invitation.group_id)
+        .fetch_one(&app_state.db_pool)
+        .await?;
+    notify_user(
+        &app_state.user_channels,
+        invitation.inviter_id,
+        WsMuxEvent::InvitationAnswered {
+            invitation_id,
+            group_id: invitation.group_id,
+            group_name,
+            invited_username: claims.username,
+            accepted: false,
+        },
+    );
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// --- Link di invito ---
+
+/// Caratteri dei codici di invito: mancano 0/O e 1/I, facili da confondere quando il codice viene ricopiato.
+const INVITE_CODE_ALPHABET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+
+fn generate_invite_code() -> String {
+    let mut rng = rand::thread_rng();
+    (0..INVITE_CODE_LENGTH)
+        .map(|_| INVITE_CODE_ALPHABET[rng.gen_range(0..INVITE_CODE_ALPHABET.len())] as char)
+        .collect()
+}
+
+pub async fn create_invite_link(
+    claims: Claims,
+    State(app_state): State<AppState>,
+    Path(group_id): Path<Uuid>,
+    Json(payload): Json<CreateInviteLinkPayload>,
+) -> Result<(StatusCode, Json<InviteLink>), AppError> {
+    if payload.expires_in_hours == Some(0) || payload.max_uses == Some(0) {
+        return Err(AppError::InvalidInput("Expiry and maximum uses must be greater than zero".to_string()));
+    }
+
+    require_role(&app_state.db_pool, claims.sub, group_id, GroupRole::Admin).await?;
+
+    let code = generate_invite_code();
+    // Senza durata il modificatore è NULL e strftime restituisce NULL: il codice non scade
+    let row = sqlx::query!(
+        r#"
+        INSERT INTO group_invite_links (code, group_id, created_by, expires_at, max_uses)
+        VALUES (?, ?, ?, strftime('%Y-%m-%dT%H:%M:%SZ', 'now', '+' || ? || ' hours'), ?)
+        RETURNING
+            created_at as "created_at!: sqlx::types::time::OffsetDateTime",
+            expires_at as "expires_at: sqlx::types::time::OffsetDateTime"
+        "#,
+        code, group_id, claims.sub, payload.expires_in_hours, payload.max_uses
+    )
+    .fetch_one(&app_state.db_pool)
+    .await?;
+
+    let link = InviteLink {
+        code,
+        group_id,
+        created_by_username: claims.username,
+        created_at: row.created_at,
+        expires_at: row.expires_at,
+        max_uses: payload.max_uses,
+        uses: 0,
+    };
+    Ok((StatusCode::CREATED, Json(link)))
+}
+
+/// Link di invito del gruppo, dal più recente; comprende quelli scaduti o esauriti.
+pub async fn get_invite_links(
+    claims: Claims,
+    State(app_state): State<AppState>,
+    Path(group_id): Path<Uuid>,
+) -> Result<Json<Vec<InviteLink>>, AppError> {
+    require_role(&app_state.db_pool, claims.sub, group_id, GroupRole::Admin).await?;
+
+    sqlx::query_as!(
+        InviteLink,
+        r#"
+        SELECT
+            l.code,
+            l.group_id as "group_id!: uuid::Uuid",
+            u.username as "created_by_username",
+            l.created_at as "created_at!: sqlx::types::time::OffsetDateTime",
+            l.expires_at as "expires_at: sqlx::types::time::OffsetDateTime",
+            l.max_uses as "max_uses: u32",
+            l.uses as "uses!: u32"
+        FROM group_invite_links l
+        JOIN users u ON u.id = l.created_by
+        WHERE l.group_id = ?
+        ORDER BY l.created_at DESC
+        "#,
+        group_id
+    )
+    .fetch_all(&app_state.db_pool)
+    .await
+    .map(Json)
+    .map_err(Into::into)
+}
+
+pub async fn revoke_invite_link(
+    claims: Claims,
+    State(app_state): State<AppState>,
+    Path((group_id, code)): Path<(Uuid, String)>,
+) -> Result<StatusCode, AppError> {
+    require_role(&app_state.db_pool, claims.sub, group_id, GroupRole::Admin).await?;
+
+    let result = sqlx::query!("DELETE FROM group_invite_links WHERE code = ? AND group_id = ?", code, group_id)
+        .execute(&app_state.db_pool)
+        .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(AppError::InviteLinkNotFound);
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Fa entrare il chiamante nel gruppo del codice, che conta un utilizzo in più.
+pub async fn join_with_invite_link(
+    claims: Claims,
+    State(app_state): State<AppState>,
+    Path(code): Path<String>,
+) -> Result<Json<Group>, AppError> {
+    let user_id = claims.sub;
+    // I codici sono generati in maiuscolo, ma chi li ricopia può sbagliare
+    let code = code.trim().to_uppercase();
+    let mut tx = app_state.db_pool.begin().await?;
+
+    // Il controllo di validità sta nella stessa scrittura che consuma un uso, così due ingressi
+    // concorrenti non possono superare `max_uses`; un errore successivo annulla il consumo
+    let group_id = sqlx::query_scalar!(
+        r#"
+        UPDATE group_invite_links SET uses = uses + 1
+        WHERE code = ?
+          AND (max_uses IS NULL OR uses < max_uses)
+          AND (expires_at IS NULL OR expires_at > strftime('%Y-%m-%dT%H:%M:%SZ','now'))
+        RETURNING group_id as "group_id!: uuid::Uuid"
+        "#,
+        code
+    )
+    .fetch_optional(&mut *tx)
+    .await?;
+
+    let group_id = match group_id {
+        Some(group_id) => group_id,
+        None => {
+            let exists = sqlx::query_scalar!("SELECT 1 as \"one!: i64\" FROM group_invite_links WHERE code = ?", code)
+                .fetch_optional(&mut *tx)
+                .await?
+                .is_some();
+            return Err(if exists { AppError::InviteLinkExpired } else { AppError::InviteLinkNotFound });
+        }
+    };
+
+    if member_role(&mut *tx, user_id, group_id).await.is_ok() {
+        return Err(AppError::UserAlreadyInGroup);
+    }
+    ensure_not_banned(&mut *tx, user_id, group_id).await?;
+
+    sqlx::query!("INSERT INTO group_members (user_id, group_id) VALUES (?, ?)", user_id, group_id)
+        .execute(&mut *tx)
+        .await?;
+
+    // Un invito ancora in sospeso per lo stesso gruppo non ha più senso
+    sqlx::query!(
+        "UPDATE group_invitations SET status = 'accepted' WHERE group_id = ? AND invited_user_id = ? AND status = 'pending'",
+        group_id, user_id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    let group = fetch_group(&mut *tx, group_id).await?;
+
+    tx.commit().await?;
+
+    broadcast_event(
+        &app_state.chat_state,
+        group.id,
+        &WsServerEvent::MemberJoined { user_id, username: claims.username },
+    );
+
+    Ok(Json(group))
+}
+
+// --- Elenco dei gruppi pubblici e richieste di ingresso ---
+
+pub async fn set_group_visibility(
+    claims: Claims,
+    State(app_state): State<AppState>,
+    Path(group_id): Path<Uuid>,
+    Json(payload): Json<UpdateGroupVisibilityPayload>,
+) -> Result<Json<Group>, AppError> {
+    let mut tx = app_state.db_pool.begin().await?;
+    require_role(&mut *tx, claims.sub, group_id, GroupRole::Admin).await?;
+
+    sqlx::query!("UPDATE groups SET visibility = ? WHERE id = ?", payload.visibility, group_id)
+        .execute(&mut *tx)
+        .await?;
+    // Chi aveva chiesto di entrare in un gruppo diventato pubblico può ora entrare da solo
+    if payload.visibility == GroupVisibility::Public {
+        sqlx::query!("DELETE FROM group_join_requests WHERE group_id = ?", group_id)
+            .execute(&mut *tx)
+            .await?;
+    }
+    let group = fetch_group(&mut *tx, group_id).await?;
+
+    tx.commit().await?;
+    Ok(Json(group))
+}
+
+/// Gruppi pubblici il cui nome contiene `q`, dal più numeroso.
+pub async fn get_group_directory(
+    claims: Claims,
+    State(app_state): State<AppState>,
+    Query(query): Query<GroupDirectoryQuery>,
+) -> Result<Json<DirectoryPage>, AppError> {
+    let limit = query.limit.map_or(DIRECTORY_PAGE_SIZE, |limit| i64::from(limit).clamp(1, DIRECTORY_PAGE_SIZE));
+    let offset = i64::from(query.offset.unwrap_or(0));
+    let fetch_limit = limit + 1;
+    // `%` e `_` digitati dall'utente vanno cercati alla lettera
+    let pattern = query.q.as_deref().map(str::trim).filter(|q| !q.is_empty()).map(|q| {
+        format!("%{}%", q.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_"))
+    });
+
+    let mut rows = sqlx::query!(
+        r#"
+        SELECT
+            id as "id!: uuid::Uuid",
+            name as "name!: String",
+            created_at as "created_at!: sqlx::types::time::OffsetDateTime",
+            member_count as "member_count!: u32",
+            is_member as "is_member!: bool"
+        FROM (
+            SELECT
+                g.id,
+                g.name,
+                g.created_at,
+                (SELECT COUNT(*) FROM group_members gm WHERE gm.group_id = g.id) AS member_count,
+                EXISTS(SELECT 1 FROM group_members gm WHERE gm.group_id = g.id AND gm.user_id = ?) AS is_member
+            FROM groups g
+            WHERE g.visibility = 'public'
+              AND (? IS NULL OR g.name LIKE ? ESCAPE '\')
+        )
+        ORDER BY member_count DESC, name
+        LIMIT ? OFFSET ?
+        "#,
+        claims.sub, pattern, pattern, fetch_limit, offset
+    )
+    .fetch_all(&app_state.db_pool)
+    .await?;
+
+    let has_more = rows.len() as i64 > limit;
+    rows.truncate(limit as usize);
+
+    let groups = rows
+        .into_iter()
+        .map(|row| DirectoryGroup {
+            id: row.id,
+            name: row.name,
+            member_count: row.member_count,
+            is_member: row.is_member,
+            created_at: row.created_at,
+        })
+        .collect();
+
+    Ok(Json(DirectoryPage { groups, has_more }))
+}
+
+/// Ingresso diretto in un gruppo pubblico.
+pub async fn join_public_group(
+    claims: Claims,
+    State(app_state): State<AppState>,
+    Path(group_id): Path<Uuid>,
+) -> Result<Json<Group>, AppError> {
+    let user_id = claims.sub;
+    let mut tx = app_state.db_pool.begin().await?;
+
+    let group = fetch_group(&mut *tx, group_id).await?;
+    if group.visibility != GroupVisibility::Public {
+        return Err(AppError::GroupNotPublic);
+    }
+    if member_role(&mut *tx, user_id, group_id).await.is_ok() {
+        return Err(AppError::UserAlreadyInGroup);
+    }
+    ensure_not_banned(&mut *tx, user_id, group_id).await?;
+
+    sqlx::query!("INSERT INTO group_members (user_id, group_id) VALUES (?, ?)", user_id, group_id)
+        .execute(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+
+    broadcast_event(
+        &app_state.chat_state,
+        group_id,
+        &WsServerEvent::MemberJoined { user_id, username: claims.username },
+    );
+
+    Ok(Json(group))
+}
+
+/// Chiede di entrare in un gruppo privato: gli admin connessi ricevono subito la richiesta.
+pub async fn create_join_request(
+    claims: Claims,
+    State(app_state): State<AppState>,
+    Path(group_id): Path<Uuid>,
+) -> Result<(StatusCode, Json<JoinRequest>), AppError> {
+    let user_id = claims.sub;
+    let mut tx = app_state.db_pool.begin().await?;
+
+    let group = fetch_group(&mut *tx, group_id).await?;
+    if is_direct_conversation(&mut *tx, group_id).await? {
+        return Err(AppError::GroupNotFound);
+    }
+    if group.visibility == GroupVisibility::Public {
+        return Err(AppError::InvalidInput("Public groups can be joined directly".to_string()));
+    }
+    if member_role(&mut *tx, user_id, group_id).await.is_ok() {
+        return Err(AppError::UserAlreadyInGroup);
+    }
+    ensure_not_banned(&mut *tx, user_id, group_id).await?;
+
+    let row = sqlx::query!(
+        r#"
+        INSERT INTO group_join_requests (group_id, user_id) VALUES (?, ?)
+        RETURNING id as "id!: uuid::Uuid", created_at as "created_at!: sqlx::types::time::OffsetDateTime"
+        "#,
+        group_id, user_id
+    )
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(|e| match e.as_database_error() {
+        Some(db_err) if db_err.is_unique_violation() => AppError::JoinRequestAlreadyExists,
+        _ => e.into(),
+    })?;
+
+    let admin_ids = sqlx::query_scalar!(
+        "SELECT user_id as \"user_id!: uuid::Uuid\" FROM group_members WHERE group_id = ? AND role IN ('admin', 'owner')",
+        group_id
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    let request = JoinRequest {
+        id: row.id,
+        group_id,
+        group_name: group.name,
+        user_id,
+        username: claims.username,
+        created_at: row.created_at,
+    };
+    for admin_id in admin_ids {
+        notify_user(&app_state.user_channels, admin_id, WsMuxEvent::JoinRequestReceived(request.clone()));
+    }
+    Ok((StatusCode::CREATED, Json(request)))
+}
+
+/// Richieste in attesa per il gruppo, dalla più vecchia.
+pub async fn get_join_requests(
+    claims: Claims,
+    State(app_state): State<AppState>,
+    Path(group_id): Path<Uuid>,
+) -> Result<Json<Vec<JoinRequest>>, AppError> {
+    require_role(&app_state.db_pool, claims.sub, group_id, GroupRole::Admin).await?;
+
+    sqlx::query_as!(
+        JoinRequest,
+        r#"
+        SELECT
+            r.id as "id!: uuid::Uuid",
+            g.id as "group_id!: uuid::Uuid",
+            g.name as "group_name",
+            u.id as "user_id!: uuid::Uuid",
+            u.username,
+            r.created_at as "created_at!: sqlx::types::time::OffsetDateTime"
+        FROM group_join_requests r
+        JOIN groups g ON g.id = r.group_id
+        JOIN users u ON u.id = r.user_id
+        WHERE r.group_id = ?
+        ORDER BY r.created_at ASC
+        "#,
+        group_id
+    )
+    .fetch_all(&app_state.db_pool)
+    .await
+    .map(Json)
+    .map_err(Into::into)
+}
+
+/// Approva (`approve = true`) o rifiuta una richiesta di ingresso e avvisa chi l'ha fatta.
+async fn answer_join_request(
+    app_state: &AppState,
+    claims: &Claims,
+    group_id: Uuid,
+    request_id: Uuid,
+    approve: bool,
+) -> Result<StatusCode, AppError> {
+    let mut tx = app_state.db_pool.begin().await?;
+    require_role(&mut *tx, claims.sub, group_id, GroupRole::Admin).await?;
+
+    let requester = sqlx::query!(
+        r#"
+        DELETE FROM group_join_requests WHERE id = ? AND group_id = ?
+        RETURNING user_id as "user_id!: uuid::Uuid"
+        "#,
+        request_id, group_id
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or(AppError::JoinRequestNotFound)?;
+    let user_id = requester.user_id;
+
+    let group = fetch_group(&mut *tx, group_id).await?;
+    if !approve {
+        tx.commit().await?;
+        notify_user(
+            &app_state.user_channels,
+            user_id,
+            WsMuxEvent::JoinRequestRejected { group_id, group_name: group.name },
+        );
+        return Ok(StatusCode::NO_CONTENT);
+    }
+
+    // Nel frattempo potrebbe essere stato bannato o essere entrato in un altro modo
+    ensure_not_banned(&mut *tx, user_id, group_id).await?;
+    sqlx::query!("INSERT INTO group_members (user_id, group_id) VALUES (?, ?) ON CONFLICT DO NOTHING", user_id, group_id)
+        .execute(&mut *tx)
+        .await?;
+    let username = sqlx::query_scalar!("SELECT username FROM users WHERE id = ?", user_id)
+        .fetch_one(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+
+    broadcast_event(&app_state.chat_state, group_id, &WsServerEvent::MemberJoined { user_id, username });
+    notify_user(&app_state.user_channels, user_id, WsMuxEvent::JoinRequestApproved(group));
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn approve_join_request(
+    claims: Claims,
+    State(app_state): State<AppState>,
+    Path((group_id, request_id)): Path<(Uuid, Uuid)>,
+) -> Result<StatusCode, AppError> {
+    answer_join_request(&app_state, &claims, group_id, request_id, true).await
+}
+
+pub async fn reject_join_request(
+    claims: Claims,
+    State(app_state): State<AppState>,
+    Path((group_id, request_id)): Path<(Uuid, Uuid)>,
+) -> Result<StatusCode, AppError> {
+    answer_join_request(&app_state, &claims, group_id, request_id, false).await
+}
+
+// --- Conversazioni dirette ---
+
+/// Conversazioni dirette dell'utente, viste dalla sua parte (`peer_*` è l'altro partecipante).
+async fn fetch_direct_conversations<'e, E>(executor: E, user_id: Uuid) -> Result<Vec<DirectConversation>, AppError>
+where
+    E: Executor<'e, Database = Sqlite>,
+{
+    let conversations = sqlx::query_as!(
+        DirectConversation,
+        r#"
+        SELECT
+            dc.group_id as "id!: uuid::Uuid",
+            peer.id as "peer_id!: uuid::Uuid",
+            peer.username as "peer_username!: String",
+            g.created_at as "created_at!: sqlx::types::time::OffsetDateTime"
+        FROM direct_conversations dc
+        JOIN groups g ON dc.group_id = g.id
+        JOIN users peer ON peer.id = CASE WHEN dc.user_a = ? THEN dc.user_b ELSE dc.user_a END
+        WHERE dc.user_a = ? OR dc.user_b = ?
+        ORDER BY g.created_at ASC
+        "#,
+        user_id, user_id, user_id
+    )
+    .fetch_all(executor)
+    .await?;
+    Ok(conversations)
+}
+
+pub async fn get_direct_conversations(
+    claims: Claims,
+    State(app_state): State<AppState>,
+) -> Result<Json<Vec<DirectConversation>>, AppError> {
+    fetch_direct_conversations(&app_state.db_pool, claims.sub).await.map(Json)
+}
+
+/// Invia un messaggio diretto a `peer_id`, creando la conversazione al primo messaggio.
+/// I messaggi successivi possono viaggiare anche sul WebSocket, usando l'id della conversazione.
+pub async fn send_direct_message(
+    claims: Claims,
+    State(app_state): State<AppState>,
+    Path(peer_id): Path<Uuid>,
+    Json(payload): Json<SendDirectMessagePayload>,
+) -> Result<Json<DirectMessageSent>, AppError> {
+    if peer_id == claims.sub {
+        return Err(AppError::InvalidInput("You cannot start a conversation with yourself.".to_string()));
+    }
+    if payload.content.trim().is_empty() {
+        return Err(AppError::InvalidInput("Message content cannot be empty.".to_string()));
+    }
+
+    // La coppia è salvata in ordine, così (a, b) e (b, a) sono la stessa conversazione
+    let (user_a, user_b) = if claims.sub < peer_id { (claims.sub, peer_id) } else { (peer_id, claims.sub) };
+
+    let peer_username = sqlx::query_scalar!("SELECT username FROM users WHERE id = ? AND deleted_at IS NULL", peer_id)
+        .fetch_optional(&app_state.db_pool)
+        .await?
+        .ok_or(AppError::UserNotFound)?;
+
+    let (conversation_id, created_at, is_new) = loop {
+        let existing = sqlx::query!(
+            r#"
+            SELECT dc.group_id as "id!: uuid::Uuid", g.created_at as "created_at!: sqlx::types::time::OffsetDateTime"
+            FROM direct_conversations dc
+            JOIN groups g ON dc.group_id = g.id
+            WHERE dc.user_a = ? AND dc.user_b = ?
+            "#,
+            user_a, user_b
+        )
+        .fetch_optional(&app_state.db_pool)
+        .await?;
+        if let Some(row) = existing {
+            break (row.id, row.created_at, false);
+        }
+
+        // La transazione inizia con una scrittura: due primi messaggi concorrenti si serializzano
+        // invece di fallire quando la lettura dovrebbe diventare scrittura
+        let mut tx = app_state.db_pool.begin().await?;
+        let channel = sqlx::query!(
+            "INSERT INTO groups (name) VALUES ('') RETURNING id as \"id!: uuid::Uuid\", created_at as \"created_at!: sqlx::types::time::OffsetDateTime\""
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        // Nessun invito: entrambi diventano subito membri, senza ruoli di amministrazione
+        sqlx::query!(
+            "INSERT INTO group_members (user_id, group_id, role) VALUES (?, ?, 'member'), (?, ?, 'member')",
+            user_a, channel.id, user_b, channel.id
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        let created = sqlx::query!(
+            "INSERT INTO direct_conversations (group_id, user_a, user_b) VALUES (?, ?, ?)",
+            channel.id, user_a, user_b
+        )
+        .execute(&mut *tx)
+        .await;
+        match created {
+            // L'altro utente ha aperto la conversazione nel frattempo: si annulla il canale e si usa il suo
+            Err(e) if e.as_database_error().is_some_and(|db_err| db_err.is_unique_violation()) => continue,
+            created => created?,
+        };
+
+        tx.commit().await?;
+        break (channel.id, channel.created_at, true);
+    };
+
+    let saved = sqlx::query!(
+        "INSERT INTO group_messages (group_id, user_id, content) VALUES (?, ?, ?) RETURNING id as \"id!: uuid::Uuid\", created_at as \"created_at!: sqlx::types::time::OffsetDateTime\"",
+        conversation_id, claims.sub, payload.content
+    )
+    .fetch_one(&app_state.db_pool)
+    .await?;
+
+    let sender_display_name = fetch_display_name(&app_state.db_pool, claims.sub).await?;
+
+    let message = WsServerMessage {
+        id: saved.id,
+        sender_id: claims.sub,
+        sender_username: claims.username.clone(),
+        sender_display_name,
+        content: payload.content,
+        created_at: saved.created_at,
+        edited_at: None,
+        deleted: false,
+        attachments: Vec::new(),
+        reactions: Vec::new(),
+        reply_to: None,
+    };
+    let conversation = DirectConversation {
+        id: conversation_id,
+        peer_id,
+        peer_username,
+        created_at,
+    };
+
+    if is_new {
+        // Le connessioni `/ws` di entrambi si iscrivono al nuovo canale ricevendo l'evento
+        let peer_view = DirectConversation {
+            id: conversation_id,
+            peer_id: claims.sub,
+            peer_username: claims.username,
+            created_at,
+        };
+        notify_user(
+            &app_state.user_channels,
+            peer_id,
+            WsMuxEvent::DirectConversationStarted { conversation: peer_view, message: message.clone() },
+        );
+        notify_user(
+            &app_state.user_channels,
+            claims.sub,
+            WsMuxEvent::DirectConversationStarted { conversation: conversation.clone(), message: message.clone() },
+        );
+    } else {
+        broadcast_event(&app_state.chat_state, conversation_id, &WsServerEvent::Message(message.clone()));
+    }
+    deliver_mentions(&app_state.db_pool, &app_state.user_channels, conversation_id, &message).await;
+
+    Ok(Json(DirectMessageSent { conversation, message }))
+}
+
+// --- Handler non protetti e WebSocket ---
+
+pub async fn get_group_by_name(
+    State(app_state): State<AppState>,
+    Path(name): Path<String>,
+) -> Result<Json<Group>, AppError> {
+    sqlx::query_as!(Group, "SELECT id as \"id!: uuid::Uuid\", name, created_at as \"created_at!: sqlx::types::time::OffsetDateTime\", visibility as \"visibility!: GroupVisibility\", description, topic, avatar_sha256 FROM groups WHERE name = ? AND id NOT IN (SELECT group_id FROM direct_conversations)", name)
+        .fetch_optional(&app_state.db_pool)
+        .await?
+        .map(Json)
+        .ok_or(AppError::GroupNotFound)
+}
+
+pub async fn get_group_members(
+    claims: Claims,
+    State(app_state):State<AppState>,
+    Path(group_id): Path<Uuid>)
+ -> Result<Json<Vec<GroupMember>>,AppError>{
+    member_role(&app_state.db_pool, claims.sub, group_id).await?;
+
+    let rows = sqlx::query!(
+            r#"
+            SELECT 
+                u.id as "id!: uuid::Uuid",
+                 u.username,
+                  u.display_name,
+                  u.status_message,
+                  u.avatar_sha256,
+                  gm.role as "role!: GroupRole",
+                  gm.last_read_message_id as "last_read_message_id: uuid::Uuid",
+                  u.last_seen_at as "last_seen_at: sqlx::types::time::OffsetDateTime"
+            FROM users u
+            JOIN group_members gm ON u.id = gm.user_id
+            WHERE gm.group_id = ?
+            ORDER BY CASE gm.role WHEN 'owner' THEN 0 WHEN 'admin' THEN 1 ELSE 2 END, u.username
+            "#,
+            group_id
+        )
+        .fetch_all(&app_state.db_pool)
+        .await?;
+
+    let members = rows
+        .into_iter()
+        .map(|row| GroupMember {
+            id: row.id,
+            username: row.username,
+            display_name: row.display_name,
+            status_message: row.status_message,
+            avatar_sha256: row.avatar_sha256,
+            role: row.role,
+            last_read_message_id: row.last_read_message_id,
+            presence: presence::status(&app_state.presence, row.id),
+            last_seen_at: row.last_seen_at,
+        })
+        .collect();
+        Ok(Json(members))
+}
+
+pub async fn get_group_messages(
+    claims: Claims,
+    State(app_state): State<AppState>,
+    Path(group_id): Path<Uuid>,
+    Query(query): Query<MessageHistoryQuery>,
+) -> Result<Json<MessagePage>, AppError> {
+    member_role(&app_state.db_pool, claims.sub, group_id).await?;
+
+    let cursor = match (query.before, query.after) {
+        (None, None) => PageCursor::Latest,
+        (Some(before), None) => PageCursor::Before(before),
+        (None, Some(after)) => PageCursor::After(after),
+        (Some(_), Some(_)) => {
+            return Err(AppError::InvalidInput("Use either 'before' or 'after', not both".to_string()));
+        }
+    };
+    let limit = query.limit.map_or(MESSAGE_PAGE_SIZE, |limit| i64::from(limit).clamp(1, MESSAGE_PAGE_SIZE));
+
+    fetch_message_page(&app_state.db_pool, group_id, cursor, limit).await.map(Json)
+}
+
+/// Punto da cui leggere una pagina della cronologia.
+enum PageCursor {
+    /// I messaggi più recenti.
+    Latest,
+    /// I messaggi immediatamente precedenti a quello indicato.
+    Before(Uuid),
+    /// I messaggi immediatamente successivi a quello indicato.
+    After(Uuid),
+}
+
+/// Legge fino a `limit` messaggi del gruppo a partire dal cursore.
+/// L'ordine è dato da `created_at` e, a parità di secondo, dall'ordine di inserimento (`rowid`).
+async fn fetch_message_page(
+    db_pool: &Pool<Sqlite>,
+    group_id: Uuid,
+    cursor: PageCursor,
+    limit: i64,
+) -> Result<MessagePage, AppError> {
+    let cursor_id = match cursor {
+        PageCursor::Latest => None,
+        PageCursor::Before(id) | PageCursor::After(id) => Some(id),
+    };
+    let position = match cursor_id {
+        Some(id) => {
+            let row = sqlx::query!(
+                "SELECT created_at as \"created_at!: String\", rowid as \"rowid!: i64\" FROM group_messages WHERE id = ? AND group_id = ?",
+                id, group_id
+            )
+            .fetch_optional(db_pool)
+            .await?
+            .ok_or(AppError::MessageNotFound)?;
+            Some((row.created_at, row.rowid))
+        }
+        None => None,
+    };
+
+    // Un messaggio in più del necessario dice se la pagina ha un seguito
+    let fetch_limit = limit + 1;
+    let newest_first = !matches!(cursor, PageCursor::After(_));
+    let (comparison, order) = if newest_first { ("<", "DESC") } else { (">", "ASC") };
+    let mut query = message_query();
+    query.push("WHERE m.group_id = ").push_bind(group_id);
+    if let Some((created_at, rowid)) = position {
+        query
+            .push(format!(" AND (m.created_at {} ", comparison))
+            .push_bind(created_at.clone())
+            .push(" OR (m.created_at = ")
+            .push_bind(created_at)
+            .push(format!(" AND m.rowid {} ", comparison))
+            .push_bind(rowid)
+            .push("))");
+    }
+    query.push(format!(" ORDER BY m.created_at {0}, m.rowid {0} LIMIT ", order)).push_bind(fetch_limit);
+    let mut messages: Vec<MessageRecord> = query.build_query_as().fetch_all(db_pool).await?;
+
+    let has_more = messages.len() as i64 > limit;
+    messages.truncate(limit as usize);
+    // Le query verso il passato leggono all'indietro: riportiamo la pagina in ordine cronologico
+    if newest_first {
+        messages.reverse();
+    }
+    let messages = complete_messages(db_pool, messages).await?;
+    Ok(MessagePage { messages, has_more })
+}
+
+/// Colonne di `MessageRecord` su `group_messages m JOIN users u`: tutte le query che leggono messaggi partono da qui.
+const MESSAGE_COLUMNS: &str = "m.id, m.user_id as sender_id, u.username as sender_username, u.display_name as sender_display_name, \
+    CASE WHEN m.deleted_at IS NULL THEN m.content ELSE '' END as content, m.created_at, m.edited_at, \
+    m.deleted_at IS NOT NULL as deleted, m.reply_to";
+
+/// Inizio di una query che restituisce `MessageRecord`, da completare con `WHERE` e ordinamento.
+fn message_query<'a>() -> QueryBuilder<'a, Sqlite> {
+    QueryBuilder::new(format!("SELECT {} FROM group_messages m JOIN users u ON m.user_id = u.id ", MESSAGE_COLUMNS))
+}
+
+/// Trasforma le righe lette da `group_messages` nei messaggi per i client,
+/// con allegati, reazioni e anteprima del messaggio citato.
+async fn complete_messages(db_pool: &Pool<Sqlite>, records: Vec<MessageRecord>) -> Result<Vec<WsServerMessage>, AppError> {
+    let reply_ids: Vec<Option<Uuid>> = records.iter().map(|r| r.reply_to).collect();
+    let mut messages: Vec<WsServerMessage> = records.into_iter().map(Into::into).collect();
+    load_attachments(db_pool, &mut messages).await?;
+    load_reactions(db_pool, &mut messages).await?;
+    load_reply_previews(db_pool, &mut messages, &reply_ids).await?;
+    Ok(messages)
+}
+
+/// Completa le risposte con l'anteprima del messaggio citato; `reply_ids` è allineato a `messages`.
+async fn load_reply_previews(
+    db_pool: &Pool<Sqlite>,
+    messages: &mut [WsServerMessage],
+    reply_ids: &[Option<Uuid>],
+) -> Result<(), AppError> {
+    let mut quoted: Vec<Uuid> = reply_ids.iter().flatten().copied().collect();
+    quoted.sort();
+    quoted.dedup();
+    if quoted.is_empty() {
+        return Ok(());
+    }
+
+    let mut query = QueryBuilder::<Sqlite>::new("SELECT m.id as message_id, u.username as sender_username, CASE WHEN m.deleted_at IS NULL THEN substr(m.content, 1, ");
+    query.push_bind(REPLY_PREVIEW_CHARS as i64);
+    query.push(") ELSE '' END as content, m.deleted_at IS NOT NULL as deleted FROM group_messages m JOIN users u ON m.user_id = u.id WHERE m.id IN (");
+    let mut ids = query.separated(", ");
+    for id in quoted {
+        ids.push_bind(id);
+    }
+    ids.push_unseparated(")");
+
+    let previews: HashMap<Uuid, ReplyPreview> = query
+        .build_query_as::<ReplyPreviewRecord>()
+        .fetch_all(db_pool)
+        .await?
+        .into_iter()
+        .map(|record| (record.message_id, record.into()))
+        .collect();
+    for (message, reply_id) in messages.iter_mut().zip(reply_ids) {
+        message.reply_to = reply_id.and_then(|id| previews.get(&id).cloned()).map(Box::new);
+    }
+    Ok(())
+}
+
+/// Anteprima del messaggio a cui si vuole rispondere; `None` se non esiste in questo gruppo o è stato eliminato.
+async fn fetch_reply_preview<'e, E>(executor: E, group_id: Uuid, message_id: Uuid) -> Result<Option<ReplyPreview>, sqlx::Error>
+where
+    E: Executor<'e, Database = Sqlite>,
+{
+    let preview_chars = REPLY_PREVIEW_CHARS as i64;
+    let record = sqlx::query_as!(
+        ReplyPreviewRecord,
+        r#"
+        SELECT
+            m.id as "message_id!: uuid::Uuid",
+            u.username as "sender_username",
+            substr(m.content, 1, ?) as "content!: String",
+            false as "deleted!: bool"
+        FROM group_messages m
+        JOIN users u ON m.user_id = u.id
+        WHERE m.id = ? AND m.group_id = ? AND m.deleted_at IS NULL
+        "#,
+        preview_chars, message_id, group_id
+    )
+    .fetch_optional(executor)
+    .await?;
+    Ok(record.map(Into::into))
+}
+
+/// Il messaggio e le sue risposte dirette, per la vista della discussione.
+pub async fn get_message_thread(
+    claims: Claims,
+    State(app_state): State<AppState>,
+    Path((group_id, message_id)): Path<(Uuid, Uuid)>,
+) -> Result<Json<MessageThread>, AppError> {
+    member_role(&app_state.db_pool, claims.sub, group_id).await?;
+
+    let mut query = message_query();
+    query.push("WHERE m.id = ").push_bind(message_id).push(" AND m.group_id = ").push_bind(group_id);
+    let root: MessageRecord = query
+        .build_query_as()
+        .fetch_optional(&app_state.db_pool)
+        .await?
+        .ok_or(AppError::MessageNotFound)?;
+
+    let mut query = message_query();
+    query
+        .push("WHERE m.reply_to = ")
+        .push_bind(message_id)
+        .push(" AND m.group_id = ")
+        .push_bind(group_id)
+        .push(" ORDER BY m.created_at ASC, m.rowid ASC");
+    let replies: Vec<MessageRecord> = query.build_query_as().fetch_all(&app_state.db_pool).await?;
+
+    let mut messages = complete_messages(&app_state.db_pool, std::iter::once(root).chain(replies).collect()).await?;
+    let replies = messages.split_off(1);
+    let root = messages.pop().ok_or(AppError::MessageNotFound)?;
+    Ok(Json(MessageThread { root, replies }))
+}
+
+/// Completa i messaggi con i loro allegati, con una sola query. Quelli eliminati non ne mostrano.
+async fn load_attachments(db_pool: &Pool<Sqlite>, messages: &mut [WsServerMessage]) -> Result<(), AppError> {
+    let message_ids: Vec<Uuid> = messages.iter().filter(|m| !m.deleted).map(|m| m.id).collect();
+    if message_ids.is_empty() {
+        return Ok(());
+    }
+
+    let mut query = QueryBuilder::<Sqlite>::new(
+        "SELECT id, message_id, file_name, mime_type, size FROM attachments WHERE message_id IN (",
+    );
+    let mut ids = query.separated(", ");
+    for id in message_ids {
+        ids.push_bind(id);
+    }
+    ids.push_unseparated(") ORDER BY rowid");
+
+    let records: Vec<AttachmentRecord> = query.build_query_as().fetch_all(db_pool).await?;
+    let mut by_message: HashMap<Uuid, Vec<Attachment>> = HashMap::new();
+    for record in records {
+        by_message.entry(record.message_id).or_default().push(record.into());
+    }
+    for message in messages {
+        if let Some(attachments) = by_message.remove(&message.id) {
+            message.attachments = attachments;
+        }
+    }
+    Ok(())
+}
+
+/// Completa i messaggi con le reazioni, raggruppate per emoji. Quelli eliminati non ne mostrano.
+async fn load_reactions(db_pool: &Pool<Sqlite>, messages: &mut [WsServerMessage]) -> Result<(), AppError> {
+    let message_ids: Vec<Uuid> = messages.iter().filter(|m| !m.deleted).map(|m| m.id).collect();
+    if message_ids.is_empty() {
+        return Ok(());
+    }
+
+    let mut query = QueryBuilder::<Sqlite>::new(
+        "SELECT message_id, user_id, emoji FROM message_reactions WHERE message_id IN (",
+    );
+    let mut ids = query.separated(", ");
+    for id in message_ids {
+        ids.push_bind(id);
+    }
+    ids.push_unseparated(") ORDER BY created_at, rowid");
+
+    let records: Vec<ReactionRecord> = query.build_query_as().fetch_all(db_pool).await?;
+    let mut by_message: HashMap<Uuid, Vec<ReactionRecord>> = HashMap::new();
+    for record in records {
+        by_message.entry(record.message_id).or_default().push(record);
+    }
+    for message in messages {
+        for record in by_message.remove(&message.id).unwrap_or_default() {
+            message.add_reaction(record.user_id, &record.emoji);
+        }
+    }
+    Ok(())
+}
+
+pub async fn search_group_messages(
+    claims: Claims,
+    State(app_state): State<AppState>,
+    Path(group_id): Path<Uuid>,
+    Query(query): Query<MessageSearchQuery>,
+) -> Result<Json<SearchPage>, AppError> {
+    member_role(&app_state.db_pool, claims.sub, group_id).await?;
+    search_messages_in(&app_state.db_pool, claims.sub, Some(group_id), query).await.map(Json)
+}
+
+/// Ricerca su tutti i gruppi del chiamante.
+pub async fn search_messages(
+    claims: Claims,
+    State(app_state): State<AppState>,
+    Query(query): Query<MessageSearchQuery>,
+) -> Result<Json<SearchPage>, AppError> {
+    search_messages_in(&app_state.db_pool, claims.sub, None, query).await.map(Json)
+}
+
+/// Trasforma il testo dell'utente in una query FTS5: ogni parola va tra virgolette,
+/// così operatori e caratteri speciali non vengono interpretati, e cerca anche i prefissi.
+fn fts_query(text: &str) -> Option<String> {
+    let terms: Vec<String> = text
+        .split_whitespace()
+        .map(|term| format!("\"{}\"*", term.replace('"', "\"\"")))
+        .collect();
+    (!terms.is_empty()).then(|| terms.join(" "))
+}
+
+async fn search_messages_in(
+    db_pool: &Pool<Sqlite>,
+    user_id: Uuid,
+    group_id: Option<Uuid>,
+    query: MessageSearchQuery,
+) -> Result<SearchPage, AppError> {
+    let fts = fts_query(&query.q).ok_or_else(|| AppError::InvalidInput("Search query cannot be empty".to_string()))?;
+    let limit = query.limit.map_or(SEARCH_PAGE_SIZE, |limit| i64::from(limit).clamp(1, SEARCH_PAGE_SIZE));
+    let offset = i64::from(query.offset.unwrap_or(0));
+    let fetch_limit = limit + 1;
+
+    let mut query = QueryBuilder::<Sqlite>::new(format!(
+        "SELECT {}, g.id as group_id, COALESCE('@' || peer.username, g.name) as group_name, ",
+        MESSAGE_COLUMNS
+    ));
+    query
+        .push(
+            r#"snippet(group_messages_fts, 0, char(57344), char(57345), '…', 16) as snippet
+            FROM group_messages_fts
+            JOIN group_messages m ON m.rowid = group_messages_fts.rowid
+            JOIN users u ON m.user_id = u.id
+            JOIN groups g ON m.group_id = g.id
+            -- Le conversazioni dirette prendono il nome dell'altro partecipante
+            LEFT JOIN direct_conversations dc ON dc.group_id = g.id
+            LEFT JOIN users peer ON peer.id = CASE WHEN dc.user_a = "#,
+        )
+        .push_bind(user_id)
+        .push(" THEN dc.user_b ELSE dc.user_a END WHERE group_messages_fts MATCH ")
+        .push_bind(fts)
+        .push(" AND m.deleted_at IS NULL AND m.group_id IN (SELECT group_id FROM group_members WHERE user_id = ")
+        .push_bind(user_id)
+        .push(")");
+    if let Some(group_id) = group_id {
+        query.push(" AND m.group_id = ").push_bind(group_id);
+    }
+    query
+        .push(" ORDER BY m.created_at DESC, m.rowid DESC LIMIT ")
+        .push_bind(fetch_limit)
+        .push(" OFFSET ")
+        .push_bind(offset);
+    let mut rows: Vec<SearchHitRecord> = query.build_query_as().fetch_all(db_pool).await?;
+
+    let has_more = rows.len() as i64 > limit;
+    rows.truncate(limit as usize);
+
+    let hits = rows
+        .into_iter()
+        .map(|row| {
+            let (snippet, highlights) = split_snippet(&row.snippet, &row.hit.message.content);
+            SearchHit {
+                group_id: row.hit.group_id,
+                group_name: row.hit.group_name,
+                snippet,
+                highlights,
+                message: row.hit.message.into(),
+            }
+        })
+        .collect();
+
+    Ok(SearchPage { hits, has_more })
+}
+
+/// Marcatori dei termini trovati che FTS5 inserisce nell'estratto (`char(57344)` e `char(57345)`
+/// nella query): caratteri Unicode a uso privato, che non arrivano mai ai client.
+const SNIPPET_MARK_START: char = '\u{E000}';
+const SNIPPET_MARK_END: char = '\u{E001}';
+
+/// Separa l'estratto di FTS5 in testo semplice e posizioni dei termini trovati.
+/// Se il messaggio stesso contiene i marcatori non ci si può fidare di loro, e l'estratto resta senza evidenziazioni.
+fn split_snippet(raw: &str, content: &str) -> (String, Vec<Range<usize>>) {
+    let is_mark = |c: char| c == SNIPPET_MARK_START || c == SNIPPET_MARK_END;
+    if content.contains(is_mark) {
+        return (raw.replace(is_mark, ""), Vec::new());
+    }
+
+    let mut snippet = String::with_capacity(raw.len());
+    let mut highlights = Vec::new();
+    let mut start = None;
+    for c in raw.chars() {
+        match c {
+            SNIPPET_MARK_START => start = Some(snippet.len()),
+            SNIPPET_MARK_END => highlights.extend(start.take().map(|start| start..snippet.len())),
+            c => snippet.push(c),
+        }
+    }
+    (snippet, highlights)
+}
+
+pub async fn edit_message(
+    claims: Claims,
+    State(app_state): State<AppState>,
+    Path((group_id, message_id)): Path<(Uuid, Uuid)>,
+    Json(payload): Json<EditMessagePayload>,
+) -> Result<StatusCode, AppError> {
+    if payload.content.trim().is_empty() {
+        return Err(AppError::InvalidInput("Message content cannot be empty.".to_string()));
+    }
+
+    let mut tx = app_state.db_pool.begin().await?;
+    let role = member_role(&mut *tx, claims.sub, group_id).await?;
+
+    let author_id = sqlx::query_scalar!(
+        "SELECT user_id as \"user_id!: uuid::Uuid\" FROM group_messages WHERE id = ? AND group_id = ? AND deleted_at IS NULL",
+        message_id, group_id
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or(AppError::MessageNotFound)?;
+
+    // Come per l'eliminazione: l'autore modifica i propri messaggi, admin e proprietario anche quelli altrui
+    if author_id != claims.sub && role < GroupRole::Admin {
+        return Err(AppError::InsufficientRole);
+    }
+
+    let edited_at = sqlx::query_scalar!(
+        "UPDATE group_messages SET content = ?, edited_at = strftime('%Y-%m-%dT%H:%M:%SZ','now') WHERE id = ? RETURNING edited_at as \"edited_at!: sqlx::types::time::OffsetDateTime\"",
+        payload.content, message_id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    broadcast_event(
+        &app_state.chat_state,
+        group_id,
+        &WsServerEvent::MessageEdited { message_id, content: payload.content.clone(), edited_at },
+    );
+
+    // Le menzioni aggiunte con la modifica vengono notificate come per un messaggio nuovo
+    if !find_mentions(&payload.content).is_empty() {
+        let mut query = message_query();
+        query.push("WHERE m.id = ").push_bind(message_id);
+        let record: MessageRecord = query.build_query_as().fetch_one(&app_state.db_pool).await?;
+        for message in complete_messages(&app_state.db_pool, vec![record]).await? {
+            deliver_mentions(&app_state.db_pool, &app_state.user_channels, group_id, &message).await;
+        }
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn delete_message(
+    claims: Claims,
+    State(app_state): State<AppState>,
+    Path((group_id, message_id)): Path<(Uuid, Uuid)>,
+) -> Result<StatusCode, AppError> {
+    let mut tx = app_state.db_pool.begin().await?;
+    let role = member_role(&mut *tx, claims.sub, group_id).await?;
+
+    let author_id = sqlx::query_scalar!(
+        "SELECT user_id as \"user_id!: uuid::Uuid\" FROM group_messages WHERE id = ? AND group_id = ? AND deleted_at IS NULL",
+        message_id, group_id
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or(AppError::MessageNotFound)?;
+
+    // L'autore può sempre eliminare i propri messaggi, admin e proprietario anche quelli altrui
+    if author_id != claims.sub && role < GroupRole::Admin {
+        return Err(AppError::InsufficientRole);
+    }
+
+    sqlx::query!(
+        "UPDATE group_messages SET deleted_at = strftime('%Y-%m-%dT%H:%M:%SZ','now') WHERE id = ?",
+        message_id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    broadcast_event(&app_state.chat_state, group_id, &WsServerEvent::MessageDeleted { message_id });
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Verifica che l'utente possa reagire al messaggio: deve essere membro del gruppo e il messaggio non eliminato.
+async fn ensure_reactable(db_pool: &Pool<Sqlite>, user_id: Uuid, group_id: Uuid, message_id: Uuid, emoji: &str) -> Result<(), AppError> {
+    if !is_valid_reaction(emoji) {
+        return Err(AppError::InvalidInput("A reaction must be a single emoji.".to_string()));
+    }
+    member_role(db_pool, user_id, group_id).await?;
+
+    sqlx::query_scalar!(
+        "SELECT 1 as \"found!: i64\" FROM group_messages WHERE id = ? AND group_id = ? AND deleted_at IS NULL",
+        message_id, group_id
     )
-    .fetch_all(&app_state.db_pool)
-    .await
-    .map(Json)
-    .map_err(Into::into)
+    .fetch_optional(db_pool)
+    .await?
+    .ok_or(AppError::MessageNotFound)?;
+    Ok(())
 }
 
-pub async fn accept_invitation(
+pub async fn add_reaction(
     claims: Claims,
     State(app_state): State<AppState>,
-    Path(invitation_id): Path<Uuid>,
-) -> Result<Json<Group>, AppError> {
-    let user_id = claims.sub;
-    let mut tx = app_state.db_pool.begin().await?;
+    Path((group_id, message_id, emoji)): Path<(Uuid, Uuid, String)>,
+) -> Result<StatusCode, AppError> {
+    ensure_reactable(&app_state.db_pool, claims.sub, group_id, message_id, &emoji).await?;
 
-    let invitation = sqlx::query!(
-        "SELECT group_id as \"group_id!: uuid::Uuid\" FROM group_invitations WHERE id = ? AND invited_user_id = ? AND status = 'pending'",
-        invitation_id, user_id
+    // Idempotente: ripetere la stessa reazione non genera un nuovo evento
+    let inserted = sqlx::query!(
+        "INSERT OR IGNORE INTO message_reactions (message_id, user_id, emoji) VALUES (?, ?, ?)",
+        message_id, claims.sub, emoji
     )
-    .fetch_optional(&mut *tx).await?
-    .ok_or(AppError::InvitationNotFound)?;
+    .execute(&app_state.db_pool)
+    .await?
+    .rows_affected();
 
-    sqlx::query!("UPDATE group_invitations SET status = 'accepted' WHERE id = ?", invitation_id)
-        .execute(&mut *tx)
-        .await?;
+    if inserted > 0 {
+        broadcast_event(
+            &app_state.chat_state,
+            group_id,
+            &WsServerEvent::ReactionAdded { message_id, user_id: claims.sub, emoji },
+        );
+    }
 
-    sqlx::query!("INSERT INTO group_members (user_id, group_id) VALUES (?, ?) ON CONFLICT DO NOTHING", user_id, invitation.group_id)
-        .execute(&mut *tx)
-        .await?;
+    Ok(StatusCode::NO_CONTENT)
+}
 
-    let group = sqlx::query_as!(Group, "SELECT id as \"id!: uuid::Uuid\", name, created_at as \"created_at!: sqlx::types::time::OffsetDateTime\" FROM groups WHERE id = ?", invitation.group_id)
-        .fetch_one(&mut *tx)
-        .await?;
+pub async fn remove_reaction(
+    claims: Claims,
+    State(app_state): State<AppState>,
+    Path((group_id, message_id, emoji)): Path<(Uuid, Uuid, String)>,
+) -> Result<StatusCode, AppError> {
+    ensure_reactable(&app_state.db_pool, claims.sub, group_id, message_id, &emoji).await?;
 
-    tx.commit().await?;
-    Ok(Json(group))
+    let removed = sqlx::query!(
+        "DELETE FROM message_reactions WHERE message_id = ? AND user_id = ? AND emoji = ?",
+        message_id, claims.sub, emoji
+    )
+    .execute(&app_state.db_pool)
+    .await?
+    .rows_affected();
+
+    if removed > 0 {
+        broadcast_event(
+            &app_state.chat_state,
+            group_id,
+            &WsServerEvent::ReactionRemoved { message_id, user_id: claims.sub, emoji },
+        );
+    }
+
+    Ok(StatusCode::NO_CONTENT)
 }
 
-pub async fn decline_invitation(
+/// Avanza la posizione di lettura dell'utente fino al messaggio indicato; non torna mai indietro.
+pub async fn mark_group_read(
     claims: Claims,
     State(app_state): State<AppState>,
-    Path(invitation_id): Path<Uuid>,
+    Path(group_id): Path<Uuid>,
+    Json(payload): Json<MarkReadPayload>,
 ) -> Result<StatusCode, AppError> {
-    let result = sqlx::query!(
-        "UPDATE group_invitations SET status = 'declined' WHERE id = ? AND invited_user_id = ? AND status = 'pending'",
-        invitation_id, claims.sub
+    member_role(&app_state.db_pool, claims.sub, group_id).await?;
+
+    let message_id = payload.message_id;
+    let position = sqlx::query!(
+        "SELECT created_at as \"created_at!: String\", rowid as \"rowid!: i64\" FROM group_messages WHERE id = ? AND group_id = ?",
+        message_id, group_id
+    )
+    .fetch_optional(&app_state.db_pool)
+    .await?
+    .ok_or(AppError::MessageNotFound)?;
+
+    let updated = sqlx::query!(
+        r#"
+        UPDATE group_members SET last_read_message_id = ?
+        WHERE group_id = ? AND user_id = ?
+          AND (
+            last_read_message_id IS NULL
+            OR (?, ?) > (SELECT created_at, rowid FROM group_messages WHERE id = group_members.last_read_message_id)
+          )
+        "#,
+        message_id, group_id, claims.sub, position.created_at, position.rowid
     )
-    .execute(&app_state.db_pool).await?;
+    .execute(&app_state.db_pool)
+    .await?
+    .rows_affected();
 
-    if result.rows_affected() == 0 {
-        return Err(AppError::InvitationNotFound);
+    if updated > 0 {
+        broadcast_event(&app_state.chat_state, group_id, &WsServerEvent::ReadUpTo { user_id: claims.sub, message_id });
     }
 
     Ok(StatusCode::NO_CONTENT)
 }
 
-// --- Handler non protetti e WebSocket ---
+/// Registra le menzioni `@username` rivolte ad altri membri del gruppo e le notifica ai destinatari
+/// sulle loro connessioni `/ws`, anche se non stanno guardando il gruppo.
+async fn deliver_mentions(db_pool: &Pool<Sqlite>, user_channels: &UserChannels, group_id: Uuid, message: &WsServerMessage) {
+    if let Err(e) = record_mentions(db_pool, user_channels, group_id, message).await {
+        tracing::error!("Failed to record mentions of message {}: {:?}", message.id, e);
+    }
+}
 
-pub async fn get_group_by_name(
-    State(app_state): State<AppState>,
-    Path(name): Path<String>,
-) -> Result<Json<Group>, AppError> {
-    sqlx::query_as!(Group, "SELECT id as \"id!: uuid::Uuid\", name, created_at as \"created_at!: sqlx::types::time::OffsetDateTime\" FROM groups WHERE name = ?", name)
-        .fetch_optional(&app_state.db_pool)
+async fn record_mentions(
+    db_pool: &Pool<Sqlite>,
+    user_channels: &UserChannels,
+    group_id: Uuid,
+    message: &WsServerMessage,
+) -> Result<(), AppError> {
+    let mut usernames: Vec<&str> = find_mentions(&message.content).into_iter().map(|(_, name)| name).collect();
+    usernames.sort_unstable();
+    usernames.dedup();
+    if usernames.is_empty() {
+        return Ok(());
+    }
+
+    let mut query = QueryBuilder::<Sqlite>::new("SELECT u.id FROM group_members gm JOIN users u ON u.id = gm.user_id WHERE gm.group_id = ");
+    query.push_bind(group_id);
+    query.push(" AND u.id != ");
+    query.push_bind(message.sender_id);
+    query.push(" AND u.username IN (");
+    let mut names = query.separated(", ");
+    for name in usernames {
+        names.push_bind(name);
+    }
+    names.push_unseparated(")");
+    let mentioned: Vec<Uuid> = query.build_query_scalar().fetch_all(db_pool).await?;
+    if mentioned.is_empty() {
+        return Ok(());
+    }
+
+    // Dopo una modifica si notifica solo chi non era già menzionato nel testo precedente
+    let mut tx = db_pool.begin().await?;
+    let mut newly_mentioned = Vec::with_capacity(mentioned.len());
+    for user_id in mentioned {
+        let inserted = sqlx::query!(
+            "INSERT OR IGNORE INTO message_mentions (message_id, user_id) VALUES (?, ?)",
+            message.id, user_id
+        )
+        .execute(&mut *tx)
         .await?
-        .map(Json)
-        .ok_or(AppError::GroupNotFound)
+        .rows_affected();
+        if inserted > 0 {
+            newly_mentioned.push(user_id);
+        }
+    }
+    tx.commit().await?;
+    if newly_mentioned.is_empty() {
+        return Ok(());
+    }
+
+    // Nelle conversazioni dirette il destinatario vede il nome di chi scrive
+    let group_name = if is_direct_conversation(db_pool, group_id).await? {
+        format!("@{}", message.sender_username)
+    } else {
+        sqlx::query_scalar!("SELECT name FROM groups WHERE id = ?", group_id).fetch_one(db_pool).await?
+    };
+    for user_id in newly_mentioned {
+        let mention = Mention { group_id, group_name: group_name.clone(), message: message.clone() };
+        notify_user(user_channels, user_id, WsMuxEvent::Mentioned(mention));
+    }
+    Ok(())
 }
 
-pub async fn get_group_members(
-    State(app_state):State<AppState>,
-    Path(group_id): Path<Uuid>)
- -> Result<Json<Vec<User>>,AppError>{
-    let users = sqlx::query_as!(
-            User,
+/// Casella delle menzioni dell'utente, limitata ai gruppi di cui fa ancora parte.
+pub async fn get_mentions(
+    claims: Claims,
+    State(app_state): State<AppState>,
+    Query(query): Query<MentionQuery>,
+) -> Result<Json<MentionPage>, AppError> {
+    let limit = query.limit.map_or(MENTION_PAGE_SIZE, |limit| i64::from(limit).clamp(1, MENTION_PAGE_SIZE));
+    let offset = i64::from(query.offset.unwrap_or(0));
+    let fetch_limit = limit + 1;
+
+    let mut query = QueryBuilder::<Sqlite>::new(format!(
+        "SELECT {}, g.id as group_id, COALESCE('@' || peer.username, g.name) as group_name",
+        MESSAGE_COLUMNS
+    ));
+    query
+        .push(
             r#"
-            SELECT 
-                u.id as "id!: uuid::Uuid",
-                 u.username, u.password_hash,
-                  u.created_at as "created_at!: sqlx::types::time::OffsetDateTime"
-            FROM users u
-            JOIN group_members gm ON u.id = gm.user_id
-            WHERE gm.group_id = ?
-            "#,
-            group_id
+            FROM message_mentions mm
+            JOIN group_messages m ON m.id = mm.message_id
+            JOIN users u ON m.user_id = u.id
+            JOIN groups g ON m.group_id = g.id
+            LEFT JOIN direct_conversations dc ON dc.group_id = g.id
+            LEFT JOIN users peer ON peer.id = CASE WHEN dc.user_a = "#,
         )
-        .fetch_all(&app_state.db_pool)
-        .await?;
-        Ok(Json(users))
+        .push_bind(claims.sub)
+        .push(" THEN dc.user_b ELSE dc.user_a END WHERE mm.user_id = ")
+        .push_bind(claims.sub)
+        .push(" AND m.deleted_at IS NULL AND m.group_id IN (SELECT group_id FROM group_members WHERE user_id = ")
+        .push_bind(claims.sub)
+        .push(") ORDER BY m.created_at DESC, m.rowid DESC LIMIT ")
+        .push_bind(fetch_limit)
+        .push(" OFFSET ")
+        .push_bind(offset);
+    let mut rows: Vec<GroupMessageRecord> = query.build_query_as().fetch_all(&app_state.db_pool).await?;
+
+    let has_more = rows.len() as i64 > limit;
+    rows.truncate(limit as usize);
+
+    let groups: Vec<(Uuid, String)> = rows.iter().map(|row| (row.group_id, row.group_name.clone())).collect();
+    let records = rows.into_iter().map(|row| row.message).collect();
+    let messages = complete_messages(&app_state.db_pool, records).await?;
+    let mentions = groups
+        .into_iter()
+        .zip(messages)
+        .map(|((group_id, group_name), message)| Mention { group_id, group_name, message })
+        .collect();
+
+    Ok(Json(MentionPage { mentions, has_more }))
 }
 
-pub async fn get_group_messages(
+/// Traduce gli errori di lettura del corpo multipart, compreso il superamento del limite di dimensione.
+fn multipart_error(e: MultipartError) -> AppError {
+    if e.status() == StatusCode::PAYLOAD_TOO_LARGE {
+        AppError::AttachmentTooLarge
+    } else {
+        AppError::InvalidInput(e.body_text())
+    }
+}
+
+/// Invia un file nel gruppo: crea un messaggio con l'allegato e, se presenti, il testo del campo `content`
+/// e la risposta al messaggio indicato in `reply_to`.
+pub async fn upload_attachment(
     claims: Claims,
     State(app_state): State<AppState>,
     Path(group_id): Path<Uuid>,
-) -> Result<Json<Vec<WsServerMessage>>, AppError> {
-    let is_member: (bool,) = sqlx::query_as(
-        "SELECT EXISTS(SELECT 1 FROM group_members WHERE user_id = ? AND group_id = ?)"
+    mut multipart: Multipart,
+) -> Result<(StatusCode, Json<WsServerMessage>), AppError> {
+    member_role(&app_state.db_pool, claims.sub, group_id).await?;
+
+    let mut content = String::new();
+    let mut reply_to = None;
+    let mut file = None;
+    while let Some(mut field) = multipart.next_field().await.map_err(multipart_error)? {
+        match field.name() {
+            Some("content") => content = field.text().await.map_err(multipart_error)?,
+            Some("reply_to") => {
+                let text = field.text().await.map_err(multipart_error)?;
+                let id = Uuid::parse_str(text.trim()).map_err(|_| AppError::InvalidInput("Invalid 'reply_to' field.".to_string()))?;
+                reply_to = Some(id);
+            }
+            Some("file") => {
+                let file_name = attachments::sanitize_file_name(field.file_name().unwrap_or_default());
+                let mime_type = field.content_type().unwrap_or("application/octet-stream").to_string();
+                if !attachments::is_allowed_mime_type(&mime_type) {
+                    return Err(AppError::UnsupportedMediaType);
+                }
+
+                let mut bytes = Vec::new();
+                while let Some(chunk) = field.chunk().await.map_err(multipart_error)? {
+                    if (bytes.len() + chunk.len()) as u64 > MAX_ATTACHMENT_SIZE {
+                        return Err(AppError::AttachmentTooLarge);
+                    }
+                    bytes.extend_from_slice(&chunk);
+                }
+                file = Some((file_name, mime_type, bytes));
+            }
+            _ => continue,
+        }
+    }
+
+    let (file_name, mime_type, bytes) = file.ok_or_else(|| AppError::InvalidInput("Missing 'file' field.".to_string()))?;
+    if bytes.is_empty() {
+        return Err(AppError::InvalidInput("The file is empty.".to_string()));
+    }
+
+    let reply_preview = match reply_to {
+        Some(reply_id) => Some(
+            fetch_reply_preview(&app_state.db_pool, group_id, reply_id)
+                .await?
+                .ok_or_else(|| AppError::InvalidInput("The message you are replying to does not exist in this group.".to_string()))?,
+        ),
+        None => None,
+    };
+
+    let sha256 = attachments::store(&app_state.attachments_dir, &bytes).await?;
+    let size = bytes.len() as i64;
+
+    let mut tx = app_state.db_pool.begin().await?;
+    let saved = sqlx::query!(
+        "INSERT INTO group_messages (group_id, user_id, content, reply_to) VALUES (?, ?, ?, ?) RETURNING id as \"id!: uuid::Uuid\", created_at as \"created_at!: sqlx::types::time::OffsetDateTime\"",
+        group_id, claims.sub, content, reply_to
     )
-    .bind(claims.sub)
-    .bind(group_id)
-    .fetch_one(&app_state.db_pool)
+    .fetch_one(&mut *tx)
     .await?;
 
-    if !is_member.0 {
-        return Err(AppError::MissingPermissions);
-    }
+    let attachment_id = sqlx::query_scalar!(
+        "INSERT INTO attachments (message_id, sha256, file_name, mime_type, size) VALUES (?, ?, ?, ?, ?) RETURNING id as \"id!: uuid::Uuid\"",
+        saved.id, sha256, file_name, mime_type, size
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    let sender_display_name = fetch_display_name(&app_state.db_pool, claims.sub).await?;
+
+    let message = WsServerMessage {
+        id: saved.id,
+        sender_id: claims.sub,
+        sender_username: claims.username,
+        sender_display_name,
+        content,
+        created_at: saved.created_at,
+        edited_at: None,
+        deleted: false,
+        attachments: vec![Attachment { id: attachment_id, file_name, mime_type, size: size as u64 }],
+        reactions: Vec::new(),
+        reply_to: reply_preview.map(Box::new),
+    };
+    broadcast_event(&app_state.chat_state, group_id, &WsServerEvent::Message(message.clone()));
+    deliver_mentions(&app_state.db_pool, &app_state.user_channels, group_id, &message).await;
+
+    Ok((StatusCode::CREATED, Json(message)))
+}
 
-    // --- INIZIO MODIFICA ---
+pub async fn download_attachment(
+    claims: Claims,
+    State(app_state): State<AppState>,
+    Path((group_id, attachment_id)): Path<(Uuid, Uuid)>,
+) -> Result<Response, AppError> {
+    member_role(&app_state.db_pool, claims.sub, group_id).await?;
 
-    // 1. Recupera gli ultimi 100 messaggi in ordine cronologico inverso
-    let mut messages = sqlx::query_as!(
-        WsServerMessage,
+    let attachment = sqlx::query!(
         r#"
-        SELECT
-            m.user_id as "sender_id!: uuid::Uuid",
-            u.username as "sender_username",
-            m.content
-        FROM group_messages m
-        JOIN users u ON m.user_id = u.id
-        WHERE m.group_id = ?
-        ORDER BY m.created_at DESC
-        LIMIT 100
+        SELECT a.sha256, a.file_name, a.mime_type
+        FROM attachments a
+        JOIN group_messages m ON a.message_id = m.id
+        WHERE a.id = ? AND m.group_id = ? AND m.deleted_at IS NULL
         "#,
-        group_id
+        attachment_id, group_id
     )
-    .fetch_all(&app_state.db_pool)
-    .await?;
+    .fetch_optional(&app_state.db_pool)
+    .await?
+    .ok_or(AppError::AttachmentNotFound)?;
 
-    // 2. Inverti la lista per ripristinare l'ordine cronologico corretto (dal più vecchio al più nuovo)
-    messages.reverse();
+    let bytes = attachments::read(&app_state.attachments_dir, &attachment.sha256).await?;
 
-    // --- FINE MODIFICA ---
+    // Solo le immagini si aprono nel browser; il resto viene sempre scaricato
+    let disposition = if attachment.mime_type.starts_with("image/") { "inline" } else { "attachment" };
+    let header_file_name: String = attachment
+        .file_name
+        .chars()
+        .map(|c| if (c.is_ascii_graphic() && c != '"') || c == ' ' { c } else { '_' })
+        .collect();
 
-    Ok(Json(messages))
+    Ok((
+        [
+            (header::CONTENT_TYPE, attachment.mime_type),
+            (header::CONTENT_DISPOSITION, format!("{}; filename=\"{}\"", disposition, header_file_name)),
+            (header::X_CONTENT_TYPE_OPTIONS, "nosniff".to_string()),
+        ],
+        bytes,
+    )
+        .into_response())
 }
 
 pub async fn chat_handler(
@@ -425,62 +3132,164 @@ pub async fn chat_handler(
         None => return (StatusCode::UNAUTHORIZED, "Missing token").into_response(),
     };
 
-    let claims = match decode::<Claims>(
-        token,
-        &DecodingKey::from_secret(app_state.jwt_secret.as_ref()),
-        &Validation::default(),
-    ) {
-        Ok(token_data) => token_data.claims,
-        Err(_) => return (StatusCode::UNAUTHORIZED, "Invalid token").into_response(),
+    let claims = match auth::validate_token(token, &app_state).await {
+        Ok(claims) => claims,
+        Err(e) => return e.into_response(),
     };
-    
-    ws.on_upgrade(move |socket| {
-        handle_socket(socket, app_state.db_pool, app_state.chat_state, group_id, claims.sub)
+
+    // Solo i membri (non rimossi né bannati) possono aprire la chat del gruppo
+    if let Err(e) = member_role(&app_state.db_pool, claims.sub, group_id).await {
+        return e.into_response();
+    }
+
+    let (requested_version, protocol_version) = negotiate_protocol(&params);
+
+    ws.on_upgrade(move |socket| async move {
+        if protocol_version < WS_MIN_PROTOCOL_VERSION {
+            reject_socket(socket, requested_version).await;
+            return;
+        }
+        handle_socket(socket, app_state, group_id, claims.sub, claims.sid, protocol_version).await
     })
 }
 
-async fn handle_socket(socket: WebSocket, db_pool: Pool<Sqlite>, chat_state: ChatState, group_id: Uuid, user_id: Uuid) {
-    let tx = chat_state.entry(group_id).or_insert_with(|| broadcast::channel(100).0).clone();
+/// Restituisce la versione richiesta dal client e quella che verrà usata sulla connessione.
+fn negotiate_protocol(params: &HashMap<String, String>) -> (u32, u32) {
+    // Il client indica la versione più alta che conosce; i client che non la indicano parlano la minima
+    let requested_version = params
+        .get("protocol")
+        .and_then(|v| v.parse::<u32>().ok())
+        .unwrap_or(WS_MIN_PROTOCOL_VERSION);
+    (requested_version, requested_version.min(WS_PROTOCOL_VERSION))
+}
+
+/// Comunica al client che la sua versione del protocollo non è supportata e chiude la connessione.
+async fn reject_socket(mut socket: WebSocket, requested_version: u32) {
+    let event = WsServerEvent::Error {
+        code: WsErrorCode::UnsupportedProtocol,
+        message: format!(
+            "Protocol version {} is not supported (supported: {}-{})",
+            requested_version, WS_MIN_PROTOCOL_VERSION, WS_PROTOCOL_VERSION
+        ),
+    };
+    let _ = socket.send(Message::Text(serde_json::to_string(&event).unwrap())).await;
+    let _ = socket.send(Message::Close(None)).await;
+}
+
+async fn handle_socket(
+    socket: WebSocket,
+    app_state: AppState,
+    group_id: Uuid,
+    user_id: Uuid,
+    session_id: Uuid,
+    protocol_version: u32,
+) {
+    let tx = app_state.chat_state.entry(group_id).or_insert_with(|| broadcast::channel(100).0).clone();
     let mut rx = tx.subscribe();
+    let mut session_rx = watch_session(&app_state.session_sockets, session_id);
 
     let username = sqlx::query_scalar!("SELECT username FROM users WHERE id = ?", user_id)
-        .fetch_one(&db_pool).await.unwrap_or_else(|_| "Sconosciuto".to_string());
+        .fetch_one(&app_state.db_pool).await.unwrap_or_else(|_| "Sconosciuto".to_string());
 
     let (mut sender, mut receiver) = socket.split();
 
+    // Canale riservato a questa connessione, per le risposte che non vanno diffuse al gruppo
+    let (direct_tx, mut direct_rx) = mpsc::channel::<WsServerEvent>(16);
+    let _ = direct_tx
+        .send(WsServerEvent::Welcome { protocol_version, group_id, user_id })
+        .await;
+
+    user_connected(&app_state, user_id).await;
+
     let recv_username = username.clone();
-    let recv_db_pool = db_pool.clone();
+    let recv_db_pool = app_state.db_pool.clone();
+    let user_channels = app_state.user_channels.clone();
+    let recv_tx = tx.clone();
 
     let mut recv_task = tokio::spawn(async move {
-        while let Some(Ok(Message::Text(text))) = receiver.next().await {
-            let msg: WsClientMessage = match serde_json::from_str(&text) {
-                Ok(m) => m,
-                Err(_) => continue,
+        let mut typing_limiter = TypingLimiter::default();
+        while let Some(Ok(frame)) = receiver.next().await {
+            let text = match frame {
+                Message::Text(text) => text,
+                Message::Close(_) => break,
+                _ => continue, // Ping/pong sono gestiti da axum
             };
-            
-            if let Err(e) = sqlx::query!(
-                "INSERT INTO group_messages (group_id, user_id, content) VALUES (?, ?, ?)",
-                group_id, user_id, msg.content
-            )
-            .execute(&recv_db_pool)
-            .await {
-                tracing::error!("Failed to save message to DB: {}", e);
-                continue;
-            }
 
-            let server_msg = WsServerMessage {
-                sender_id: user_id,
-                sender_username: recv_username.clone(),
-                content: msg.content.clone(),
+            let command: WsClientCommand = match serde_json::from_str(&text) {
+                Ok(command) => command,
+                Err(e) => {
+                    let error = WsServerEvent::Error { code: WsErrorCode::InvalidCommand, message: e.to_string() };
+                    if direct_tx.send(error).await.is_err() { break; }
+                    continue;
+                }
             };
-            
-            if tx.send(serde_json::to_string(&server_msg).unwrap()).is_err() { break; }
+
+            let event = match command {
+                WsClientCommand::SendMessage { content, reply_to } => {
+                    match save_message(&recv_db_pool, group_id, user_id, &recv_username, content, reply_to).await {
+                        Ok(event) => {
+                            if let WsServerEvent::Message(message) = &event {
+                                deliver_mentions(&recv_db_pool, &user_channels, group_id, message).await;
+                            }
+                            event
+                        }
+                        Err(error) => {
+                            if direct_tx.send(error).await.is_err() { break; }
+                            continue;
+                        }
+                    }
+                }
+                WsClientCommand::Typing if !typing_limiter.allow(group_id) => continue,
+                WsClientCommand::Typing => WsServerEvent::Typing { user_id, username: recv_username.clone() },
+            };
+
+            if recv_tx.send(ChatEvent::Event(event)).is_err() { break; }
         }
     });
 
     let mut send_task = tokio::spawn(async move {
-        while let Ok(msg) = rx.recv().await {
-            if sender.send(Message::Text(msg)).await.is_err() { break; }
+        loop {
+            let outgoing = tokio::select! {
+                event = rx.recv() => match event {
+                    Ok(ChatEvent::Event(event)) => Message::Text(serde_json::to_string(&event).unwrap()),
+                    Ok(ChatEvent::Disconnect(target_id)) if target_id == user_id => {
+                        let _ = sender
+                            .send(Message::Close(Some(CloseFrame {
+                                code: CLOSE_REMOVED_FROM_GROUP,
+                                reason: "removed from group".into(),
+                            })))
+                            .await;
+                        break;
+                    }
+                    Ok(ChatEvent::Disconnect(_)) => continue,
+                    Ok(ChatEvent::Close) => {
+                        let _ = sender
+                            .send(Message::Close(Some(CloseFrame {
+                                code: CLOSE_GROUP_DELETED,
+                                reason: "group deleted".into(),
+                            })))
+                            .await;
+                        break;
+                    }
+                    // Un client troppo lento perde gli eventi più vecchi ma resta connesso
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        tracing::warn!("Client {} in ritardo: {} eventi persi.", user_id, skipped);
+                        continue;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                },
+                Some(event) = direct_rx.recv() => Message::Text(serde_json::to_string(&event).unwrap()),
+                _ = session_rx.recv() => {
+                    let _ = sender
+                        .send(Message::Close(Some(CloseFrame {
+                            code: CLOSE_SESSION_REVOKED,
+                            reason: "session revoked".into(),
+                        })))
+                        .await;
+                    break;
+                }
+            };
+            if sender.send(outgoing).await.is_err() { break; }
         }
     });
 
@@ -488,6 +3297,380 @@ async fn handle_socket(socket: WebSocket, db_pool: Pool<Sqlite>, chat_state: Cha
         _ = (&mut recv_task) => send_task.abort(),
         _ = (&mut send_task) => recv_task.abort(),
     };
-    
-    chat_state.remove_if(&group_id, |_, channel| channel.receiver_count() == 0);
-}
\ No newline at end of file
+    // Attende che i receiver vengano rilasciati prima di contare gli iscritti
+    if !send_task.is_finished() {
+        let _ = send_task.await;
+    }
+
+    user_disconnected(&app_state, user_id).await;
+    drop(tx);
+
+    app_state.chat_state.remove_if(&group_id, |_, channel| channel.receiver_count() == 0);
+    app_state.session_sockets.remove_if(&session_id, |_, channel| channel.receiver_count() == 0);
+}
+
+/// Connessione unica per utente: riceve gli eventi di tutti i suoi gruppi e gli inviti.
+pub async fn mux_handler(
+    ws: WebSocketUpgrade,
+    State(app_state): State<AppState>,
+    Query(params): Query<HashMap<String, String>>,
+) -> impl IntoResponse {
+    let token = match params.get("token") {
+        Some(t) => t,
+        None => return (StatusCode::UNAUTHORIZED, "Missing token").into_response(),
+    };
+
+    let claims = match auth::validate_token(token, &app_state).await {
+        Ok(claims) => claims,
+        Err(e) => return e.into_response(),
+    };
+
+    let (requested_version, protocol_version) = negotiate_protocol(&params);
+
+    ws.on_upgrade(move |socket| async move {
+        if protocol_version < WS_MIN_PROTOCOL_VERSION {
+            reject_socket(socket, requested_version).await;
+            return;
+        }
+        handle_mux_socket(socket, app_state, claims.sub, claims.sid, claims.username, protocol_version).await
+    })
+}
+
+/// Iscrizioni ai gruppi di una connessione `/ws`: per ogni gruppo un task inoltra
+/// gli eventi del canale broadcast sulla coda di uscita della connessione.
+struct MuxSubscriptions {
+    chat_state: ChatState,
+    user_id: Uuid,
+    out_tx: mpsc::Sender<WsMuxEvent>,
+    forwarders: HashMap<Uuid, JoinHandle<()>>,
+}
+
+impl MuxSubscriptions {
+    /// Un'iscrizione termina da sola quando l'utente viene rimosso dal gruppo.
+    fn is_active(&self, group_id: Uuid) -> bool {
+        self.forwarders.get(&group_id).is_some_and(|task| !task.is_finished())
+    }
+
+    fn subscribe(&mut self, group_id: Uuid) {
+        if self.is_active(group_id) {
+            return;
+        }
+
+        let tx = self.chat_state.entry(group_id).or_insert_with(|| broadcast::channel(100).0).clone();
+        let mut rx = tx.subscribe();
+
+        let user_id = self.user_id;
+        let out_tx = self.out_tx.clone();
+        let task = tokio::spawn(async move {
+            loop {
+                match rx.recv().await {
+                    Ok(ChatEvent::Event(event)) => {
+                        if out_tx.send(WsMuxEvent::Group { group_id, event }).await.is_err() { return; }
+                    }
+                    Ok(ChatEvent::Disconnect(target_id)) if target_id == user_id => break,
+                    Ok(ChatEvent::Disconnect(_)) => continue,
+                    Ok(ChatEvent::Close) => break,
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        tracing::warn!("Client {} in ritardo sul gruppo {}: {} eventi persi.", user_id, group_id, skipped);
+                        continue;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+            // L'utente non fa più parte del gruppo (o il gruppo non esiste più)
+            let _ = out_tx.send(WsMuxEvent::Unsubscribed { group_id }).await;
+        });
+        self.forwarders.insert(group_id, task);
+    }
+
+    async fn unsubscribe(&mut self, group_id: Uuid) {
+        let Some(task) = self.forwarders.remove(&group_id) else { return };
+        task.abort();
+        let _ = task.await; // Attende che il receiver venga rilasciato prima di contare gli iscritti
+        self.chat_state.remove_if(&group_id, |_, channel| channel.receiver_count() == 0);
+    }
+
+    async fn clear(&mut self) {
+        let group_ids: Vec<Uuid> = self.forwarders.keys().copied().collect();
+        for group_id in group_ids {
+            self.unsubscribe(group_id).await;
+        }
+    }
+}
+
+async fn handle_mux_socket(
+    socket: WebSocket,
+    app_state: AppState,
+    user_id: Uuid,
+    session_id: Uuid,
+    username: String,
+    protocol_version: u32,
+) {
+    let (mut sender, mut receiver) = socket.split();
+
+    // Coda di uscita della connessione, condivisa dai task di inoltro dei gruppi
+    let (out_tx, mut out_rx) = mpsc::channel::<WsMuxEvent>(64);
+    let mut session_rx = watch_session(&app_state.session_sockets, session_id);
+    let mut send_task = tokio::spawn(async move {
+        loop {
+            let event = tokio::select! {
+                event = out_rx.recv() => match event {
+                    Some(event) => event,
+                    None => break,
+                },
+                _ = session_rx.recv() => {
+                    let _ = sender
+                        .send(Message::Close(Some(CloseFrame {
+                            code: CLOSE_SESSION_REVOKED,
+                            reason: "session revoked".into(),
+                        })))
+                        .await;
+                    break;
+                }
+            };
+            if sender.send(Message::Text(serde_json::to_string(&event).unwrap())).await.is_err() { break; }
+        }
+    });
+
+    let mut user_rx = app_state
+        .user_channels
+        .entry(user_id)
+        .or_insert_with(|| broadcast::channel(16).0)
+        .subscribe();
+
+    let groups = sqlx::query_scalar!(
+        "SELECT group_id as \"group_id!: uuid::Uuid\" FROM group_members WHERE user_id = ?",
+        user_id
+    )
+    .fetch_all(&app_state.db_pool)
+    .await
+    .unwrap_or_default();
+
+    let _ = out_tx
+        .send(WsMuxEvent::Welcome { protocol_version, user_id, groups: groups.clone() })
+        .await;
+
+    let mut subscriptions = MuxSubscriptions {
+        chat_state: app_state.chat_state.clone(),
+        user_id,
+        out_tx: out_tx.clone(),
+        forwarders: HashMap::new(),
+    };
+    for group_id in groups {
+        subscriptions.subscribe(group_id);
+    }
+    user_connected(&app_state, user_id).await;
+    let mut typing_limiter = TypingLimiter::default();
+
+    loop {
+        let frame = tokio::select! {
+            frame = receiver.next() => frame,
+            event = user_rx.recv() => {
+                match event {
+                    Ok(event) => {
+                        match &event {
+                            WsMuxEvent::DirectConversationStarted { conversation, .. } => subscriptions.subscribe(conversation.id),
+                            WsMuxEvent::JoinRequestApproved(group) => subscriptions.subscribe(group.id),
+                            _ => {}
+                        }
+                        if out_tx.send(event).await.is_err() { break }
+                    }
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        tracing::warn!("Client {} in ritardo: {} notifiche perse.", user_id, skipped);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+                continue;
+            }
+            _ = &mut send_task => break,
+        };
+
+        let text = match frame {
+            Some(Ok(Message::Text(text))) => text,
+            Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+            Some(Ok(_)) => continue, // Ping/pong sono gestiti da axum
+        };
+
+        let command: WsMuxCommand = match serde_json::from_str(&text) {
+            Ok(command) => command,
+            Err(e) => {
+                let error = WsMuxEvent::Error { group_id: None, code: WsErrorCode::InvalidCommand, message: e.to_string() };
+                if out_tx.send(error).await.is_err() { break; }
+                continue;
+            }
+        };
+
+        let reply = match command {
+            WsMuxCommand::Subscribe { group_id, after } => {
+                match member_role(&app_state.db_pool, user_id, group_id).await {
+                    Ok(_) => {
+                        // L'iscrizione precede la lettura dal DB: nessun messaggio cade nel mezzo,
+                        // al più qualcuno arriva due volte e il client lo riconosce dall'id
+                        subscriptions.subscribe(group_id);
+                        if out_tx.send(WsMuxEvent::Subscribed { group_id }).await.is_err() { break; }
+                        match after {
+                            Some(after) => match replay_messages(&app_state.db_pool, group_id, after).await {
+                                Ok(replay) => Some(replay),
+                                Err(e) => {
+                                    tracing::error!("Failed to replay messages: {:?}", e);
+                                    Some(WsMuxEvent::Error {
+                                        group_id: Some(group_id),
+                                        code: WsErrorCode::Internal,
+                                        message: "Failed to replay missed messages".to_string(),
+                                    })
+                                }
+                            },
+                            None => None,
+                        }
+                    }
+                    Err(_) => Some(WsMuxEvent::Error {
+                        group_id: Some(group_id),
+                        code: WsErrorCode::NotGroupMember,
+                        message: "You are not a member of this group".to_string(),
+                    }),
+                }
+            }
+            WsMuxCommand::Unsubscribe { group_id } => {
+                subscriptions.unsubscribe(group_id).await;
+                Some(WsMuxEvent::Unsubscribed { group_id })
+            }
+            WsMuxCommand::SendMessage { group_id, .. } | WsMuxCommand::Typing { group_id }
+                if !subscriptions.is_active(group_id) =>
+            {
+                Some(WsMuxEvent::Error {
+                    group_id: Some(group_id),
+                    code: WsErrorCode::NotGroupMember,
+                    message: "You are not subscribed to this group".to_string(),
+                })
+            }
+            WsMuxCommand::SendMessage { group_id, content, reply_to } => {
+                match save_message(&app_state.db_pool, group_id, user_id, &username, content, reply_to).await {
+                    Ok(event) => {
+                        broadcast_event(&app_state.chat_state, group_id, &event);
+                        if let WsServerEvent::Message(message) = &event {
+                            deliver_mentions(&app_state.db_pool, &app_state.user_channels, group_id, message).await;
+                        }
+                        None
+                    }
+                    Err(WsServerEvent::Error { code, message }) => {
+                        Some(WsMuxEvent::Error { group_id: Some(group_id), code, message })
+                    }
+                    Err(event) => Some(WsMuxEvent::Group { group_id, event }),
+                }
+            }
+            WsMuxCommand::SetAway { away } => {
+                if presence::set_away(&app_state.presence, user_id, away) {
+                    broadcast_presence(&app_state, user_id, None).await;
+                }
+                None
+            }
+            WsMuxCommand::Typing { group_id } => {
+                if typing_limiter.allow(group_id) {
+                    broadcast_event(
+                        &app_state.chat_state,
+                        group_id,
+                        &WsServerEvent::Typing { user_id, username: username.clone() },
+                    );
+                }
+                None
+            }
+        };
+
+        if let Some(reply) = reply {
+            if out_tx.send(reply).await.is_err() { break; }
+        }
+    }
+
+    send_task.abort();
+    if !send_task.is_finished() {
+        let _ = send_task.await;
+    }
+    user_disconnected(&app_state, user_id).await;
+    subscriptions.clear().await;
+    drop(user_rx);
+    app_state.user_channels.remove_if(&user_id, |_, channel| channel.receiver_count() == 0);
+    app_state.session_sockets.remove_if(&session_id, |_, channel| channel.receiver_count() == 0);
+}
+
+/// Limita gli eventi `Typing` di una connessione a uno ogni `TYPING_MIN_INTERVAL_MS` per gruppo.
+#[derive(Default)]
+struct TypingLimiter {
+    last_sent: HashMap<Uuid, Instant>,
+}
+
+impl TypingLimiter {
+    fn allow(&mut self, group_id: Uuid) -> bool {
+        let now = Instant::now();
+        match self.last_sent.get(&group_id) {
+            Some(last) if now.duration_since(*last) < Duration::from_millis(TYPING_MIN_INTERVAL_MS) => false,
+            _ => {
+                self.last_sent.insert(group_id, now);
+                true
+            }
+        }
+    }
+}
+
+/// Raccoglie i messaggi del gruppo successivi a `after`, per un client che si riconnette.
+async fn replay_messages(db_pool: &Pool<Sqlite>, group_id: Uuid, after: Uuid) -> Result<WsMuxEvent, AppError> {
+    match fetch_message_page(db_pool, group_id, PageCursor::After(after), REPLAY_LIMIT).await {
+        Ok(page) => Ok(WsMuxEvent::Replay { group_id, messages: page.messages, has_more: page.has_more }),
+        // Cursore sconosciuto: il client non può sapere cosa ha perso, gli chiediamo di ricaricare tutto
+        Err(AppError::MessageNotFound) => Ok(WsMuxEvent::Replay { group_id, messages: Vec::new(), has_more: true }),
+        Err(e) => Err(e),
+    }
+}
+
+/// Salva un nuovo messaggio e restituisce l'evento da diffondere, o l'errore da inviare al mittente.
+async fn save_message(
+    db_pool: &Pool<Sqlite>,
+    group_id: Uuid,
+    user_id: Uuid,
+    username: &str,
+    content: String,
+    reply_to: Option<Uuid>,
+) -> Result<WsServerEvent, WsServerEvent> {
+    if content.trim().is_empty() {
+        return Err(WsServerEvent::Error {
+            code: WsErrorCode::InvalidMessage,
+            message: "Message content cannot be empty".to_string(),
+        });
+    }
+
+    let internal_error = |e: sqlx::Error| {
+        tracing::error!("Failed to save message to DB: {}", e);
+        WsServerEvent::Error { code: WsErrorCode::Internal, message: "Failed to save message".to_string() }
+    };
+    let reply_preview = match reply_to {
+        Some(reply_id) => Some(fetch_reply_preview(db_pool, group_id, reply_id).await.map_err(internal_error)?.ok_or_else(|| {
+            WsServerEvent::Error {
+                code: WsErrorCode::InvalidMessage,
+                message: "The message you are replying to does not exist in this group".to_string(),
+            }
+        })?),
+        None => None,
+    };
+
+    let saved = sqlx::query!(
+        "INSERT INTO group_messages (group_id, user_id, content, reply_to) VALUES (?, ?, ?, ?) RETURNING id as \"id!: uuid::Uuid\", created_at as \"created_at!: sqlx::types::time::OffsetDateTime\"",
+        group_id, user_id, content, reply_to
+    )
+    .fetch_one(db_pool)
+    .await
+    .map_err(internal_error)?;
+    let sender_display_name = fetch_display_name(db_pool, user_id).await.map_err(internal_error)?;
+
+    Ok(WsServerEvent::Message(WsServerMessage {
+        id: saved.id,
+        sender_id: user_id,
+        sender_username: username.to_string(),
+        sender_display_name,
+        content,
+        created_at: saved.created_at,
+        edited_at: None,
+        deleted: false,
+        attachments: Vec::new(),
+        reactions: Vec::new(),
+        reply_to: reply_preview.map(Box::new),
+    }))
+}
@@ -1,11 +1,14 @@
 use axum::{
-    routing::{get, post, delete},
+    extract::DefaultBodyLimit,
+    routing::{delete, get, patch, post, put},
     Router,
 };
 use dashmap::DashMap;
+use models::{WsMuxEvent, WsServerEvent};
 use sqlx::{Pool, Sqlite};
 use std::env;
 use std::net::SocketAddr;
+use std::path::PathBuf;
 use std::sync::Arc;
 use std::time::Duration;
 use tokio::sync::broadcast;
@@ -14,19 +17,42 @@ use tracing_subscriber::{fmt, layer::SubscriberExt, util::SubscriberInitExt};
 use uuid::Uuid;
 
 // Dichiarazione di tutti i moduli
+mod attachments;
 pub mod auth;
 mod db;
 mod handlers;
 mod models;
+mod presence;
 pub mod error;
 
-pub type ChatState = Arc<DashMap<Uuid, broadcast::Sender<String>>>;
+/// Evento diffuso sul canale broadcast di un gruppo.
+#[derive(Clone, Debug)]
+pub enum ChatEvent {
+    /// Evento da inoltrare ai client: ogni connessione lo serializza nel proprio formato.
+    Event(WsServerEvent),
+    /// Chiude le connessioni dell'utente indicato (es. dopo un kick o un ban).
+    Disconnect(Uuid),
+    /// Chiude tutte le connessioni al gruppo, che è stato eliminato.
+    Close,
+}
+
+pub type ChatState = Arc<DashMap<Uuid, broadcast::Sender<ChatEvent>>>;
+
+/// Canali per utente, condivisi da tutte le sue connessioni `/ws` (es. per gli inviti ricevuti).
+pub type UserChannels = Arc<DashMap<Uuid, broadcast::Sender<WsMuxEvent>>>;
+
+/// Segnale di chiusura per sessione, ascoltato da tutte le connessioni WebSocket aperte con i suoi token.
+pub type SessionSockets = Arc<DashMap<Uuid, broadcast::Sender<()>>>;
 
 #[derive(Clone)]
 pub struct AppState {
     db_pool: Pool<Sqlite>,
     chat_state: ChatState,
+    user_channels: UserChannels,
+    session_sockets: SessionSockets,
+    presence: presence::PresenceRegistry,
     jwt_secret: String,
+    attachments_dir: PathBuf,
 }
 
 #[tokio::main]
@@ -40,39 +66,138 @@ async fn main() {
 
     let jwt_secret = env::var("JWT_SECRET").expect("JWT_SECRET must be set");
 
+    let attachments_dir = PathBuf::from(
+        env::var("ATTACHMENTS_DIR").unwrap_or_else(|_| attachments::DEFAULT_ATTACHMENTS_DIR.to_string()),
+    );
+    std::fs::create_dir_all(&attachments_dir).expect("Failed to create attachments directory");
+
     let db_pool = db::create_db_pool()
         .await
         .expect("Failed to create database pool");
     tracing::info!("Database pool created successfully.");
 
     let chat_state = ChatState::new(DashMap::new());
+    let user_channels = UserChannels::new(DashMap::new());
+    let session_sockets = SessionSockets::new(DashMap::new());
+    let presence = presence::PresenceRegistry::new(DashMap::new());
 
     let app_state = AppState {
         db_pool,
         chat_state,
+        user_channels,
+        session_sockets,
+        presence,
         jwt_secret,
+        attachments_dir,
     };
 
     let app = Router::new()
         .route("/users/register", post(handlers::register_user))
         .route("/users/login", post(handlers::login_user))
+        .route("/users/refresh", post(handlers::refresh_session))
+        .route("/users/logout", post(handlers::logout_user))
         .route(
             "/users/by_username/:username",
             get(handlers::get_user_by_username),
         )
+        .route(
+            "/users/me",
+            get(handlers::get_me).patch(handlers::update_me).delete(handlers::delete_account),
+        )
+        .route("/users/me/password", put(handlers::change_password))
+        .route("/users/password_reset", post(handlers::reset_password))
+        .route("/users/:user_id/password_reset", post(handlers::issue_password_reset))
+        .route("/users/:user_id", get(handlers::get_user_profile))
+        .route("/users/:user_id/avatar", get(handlers::get_user_avatar))
+        .route("/users/me/groups", get(handlers::get_my_groups))
+        .route("/users/me/mentions", get(handlers::get_mentions))
         .route("/groups", post(handlers::create_group))
+        .route(
+            "/groups/:group_id",
+            patch(handlers::update_group).delete(handlers::delete_group),
+        )
+        .route("/groups/:group_id/history", get(handlers::get_group_history))
+        .route("/groups/:group_id/avatar", get(handlers::get_group_avatar))
         .route("/groups/by_name/:name", get(handlers::get_group_by_name))
+        .route("/groups/directory", get(handlers::get_group_directory))
+        .route("/groups/:group_id/visibility", put(handlers::set_group_visibility))
+        .route("/groups/:group_id/join", post(handlers::join_public_group))
+        .route(
+            "/groups/:group_id/join_requests",
+            get(handlers::get_join_requests).post(handlers::create_join_request),
+        )
+        .route(
+            "/groups/:group_id/join_requests/:request_id/approve",
+            post(handlers::approve_join_request),
+        )
+        .route(
+            "/groups/:group_id/join_requests/:request_id/reject",
+            post(handlers::reject_join_request),
+        )
         .route(
             "/groups/:group_id/messages", // Rotta per la cronologia
             get(handlers::get_group_messages),
         )
+        .route(
+            "/groups/:group_id/messages/search",
+            get(handlers::search_group_messages),
+        )
+        .route("/messages/search", get(handlers::search_messages))
+        .route(
+            "/groups/:group_id/messages/:message_id",
+            patch(handlers::edit_message).delete(handlers::delete_message),
+        )
+        .route(
+            "/groups/:group_id/messages/:message_id/reactions/:emoji",
+            put(handlers::add_reaction).delete(handlers::remove_reaction),
+        )
+        .route(
+            "/groups/:group_id/messages/:message_id/thread",
+            get(handlers::get_message_thread),
+        )
+        .route(
+            "/groups/:group_id/attachments",
+            // Margine oltre la dimensione del file per il resto del corpo multipart
+            post(handlers::upload_attachment)
+                .layer(DefaultBodyLimit::max(ruggine_protocol::MAX_ATTACHMENT_SIZE as usize + 64 * 1024)),
+        )
+        .route(
+            "/groups/:group_id/attachments/:attachment_id",
+            get(handlers::download_attachment),
+        )
+        .route("/groups/:group_id/read", put(handlers::mark_group_read))
         .route("/groups/:group_id/members",get(handlers::get_group_members))
+        .route(
+            "/groups/:group_id/members/:user_id",
+            delete(handlers::kick_member),
+        )
+        .route(
+            "/groups/:group_id/members/:user_id/role",
+            put(handlers::update_member_role),
+        )
+        .route("/groups/:group_id/transfer_ownership", post(handlers::transfer_ownership))
+        .route(
+            "/groups/:group_id/bans/:user_id",
+            put(handlers::ban_member).delete(handlers::unban_member),
+        )
         .route(
             "/groups/:group_id/leave", // <-- AGGIUNGI QUESTA ROTTA
             delete(handlers::leave_group),
         )
         .route("/groups/:group_id/invite", post(handlers::invite_to_group))
-        .route("/groups/:group_id/chat", get(handlers::chat_handler))
+        .route(
+            "/groups/:group_id/invite_links",
+            get(handlers::get_invite_links).post(handlers::create_invite_link),
+        )
+        .route(
+            "/groups/:group_id/invite_links/:code",
+            delete(handlers::revoke_invite_link),
+        )
+        .route("/invites/:code/join", post(handlers::join_with_invite_link))
+        .route("/groups/:group_id/chat", get(handlers::chat_handler)) // Una connessione per gruppo, per i client più vecchi
+        .route("/ws", get(handlers::mux_handler))
+        .route("/direct", get(handlers::get_direct_conversations))
+        .route("/direct/:user_id/messages", post(handlers::send_direct_message))
         .route("/invitations", get(handlers::get_pending_invitations))
         .route(
             "/invitations/:invitation_id/accept",
@@ -3,75 +3,148 @@ use sqlx::FromRow;
 use time::OffsetDateTime;
 use uuid::Uuid;
 
-// --- Modelli per API REST ---
+// DTO di rete condivisi con il client: vedi il crate `ruggine_protocol`
+pub use ruggine_protocol::*;
 
-#[derive(Debug, Serialize, FromRow, Clone)]
-pub struct User {
+// --- Modelli interni del server ---
+
+/// Riga completa di `users`, hash della password incluso: non lascia mai il server.
+#[derive(Debug, FromRow, Clone)]
+pub struct UserRecord {
     pub id: Uuid,
     pub username: String,
-    #[serde(skip_serializing)]
     pub password_hash: String,
-    #[serde(with = "time::serde::rfc3339")]
     pub created_at: OffsetDateTime,
+    pub display_name: Option<String>,
+    pub bio: Option<String>,
+    pub status_message: Option<String>,
+    pub avatar_sha256: Option<String>,
+    pub is_admin: bool,
 }
 
-#[derive(Deserialize)]
-pub struct RegisterUserPayload {
-    pub username: String,
-    pub password: String,
+impl From<UserRecord> for User {
+    fn from(record: UserRecord) -> Self {
+        User {
+            id: record.id,
+            username: record.username,
+            created_at: record.created_at,
+            display_name: record.display_name,
+            bio: record.bio,
+            status_message: record.status_message,
+            avatar_sha256: record.avatar_sha256,
+        }
+    }
 }
 
-#[derive(Deserialize)]
-pub struct LoginPayload {
-    pub username: String,
-    pub password: String,
+/// Colonne di un messaggio lette da `group_messages`; allegati, reazioni e messaggio citato vengono caricati a parte.
+#[derive(Debug, FromRow)]
+pub struct MessageRecord {
+    pub id: Uuid,
+    pub sender_id: Uuid,
+    pub sender_username: String,
+    pub sender_display_name: Option<String>,
+    pub content: String,
+    pub created_at: OffsetDateTime,
+    pub edited_at: Option<OffsetDateTime>,
+    pub deleted: bool,
+    pub reply_to: Option<Uuid>,
 }
 
-#[derive(Serialize)]
-pub struct LoginResponse {
-    pub token: String,
-    pub user: User, // Ottimizzazione: restituisce l'utente al login
-    pub groups: Vec<Group>,
+/// `reply_to` resta vuoto: l'anteprima del messaggio citato va letta a parte.
+impl From<MessageRecord> for WsServerMessage {
+    fn from(record: MessageRecord) -> Self {
+        WsServerMessage {
+            id: record.id,
+            sender_id: record.sender_id,
+            sender_username: record.sender_username,
+            sender_display_name: record.sender_display_name,
+            content: record.content,
+            created_at: record.created_at,
+            edited_at: record.edited_at,
+            deleted: record.deleted,
+            attachments: Vec::new(),
+            reactions: Vec::new(),
+            reply_to: None,
+        }
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct Claims {
-    pub sub: Uuid,
-    pub exp: i64,
-    pub iat: i64,
-    pub username: String,
+/// Colonne dell'anteprima di un messaggio citato.
+#[derive(Debug, FromRow)]
+pub struct ReplyPreviewRecord {
+    pub message_id: Uuid,
+    pub sender_username: String,
+    pub content: String,
+    pub deleted: bool,
 }
 
-#[derive(Debug, Serialize, FromRow, Clone)]
-pub struct Group {
-    pub id: Uuid,
-    pub name: String,
-    #[serde(with = "time::serde::rfc3339")]
-    pub created_at: OffsetDateTime,
+impl From<ReplyPreviewRecord> for ReplyPreview {
+    fn from(record: ReplyPreviewRecord) -> Self {
+        ReplyPreview {
+            message_id: record.message_id,
+            sender_username: record.sender_username,
+            content: record.content,
+            deleted: record.deleted,
+        }
+    }
 }
 
-#[derive(Deserialize)]
-pub struct CreateGroupPayload {
-    pub name: String,
-    // Rimosso creator_id, verrà dal token JWT
+/// Messaggio insieme al gruppo in cui si trova, per le viste che attraversano più gruppi.
+#[derive(Debug, FromRow)]
+pub struct GroupMessageRecord {
+    #[sqlx(flatten)]
+    pub message: MessageRecord,
+    pub group_id: Uuid,
+    pub group_name: String,
 }
 
-#[derive(Deserialize)]
-pub struct InviteToGroupPayload {
-    // Rimosso inviter_id, verrà dal token JWT
-    pub user_to_invite_id: Uuid,
+/// Risultato della ricerca, con l'estratto evidenziato.
+#[derive(Debug, FromRow)]
+pub struct SearchHitRecord {
+    #[sqlx(flatten)]
+    pub hit: GroupMessageRecord,
+    pub snippet: String,
 }
 
-// --- NUOVI Modelli per Inviti ---
-
-#[derive(Debug, Serialize, FromRow)]
-pub struct Invitation {
+/// Riga di `attachments`: l'hash del file su disco non viene esposto ai client.
+#[derive(Debug, FromRow)]
+pub struct AttachmentRecord {
     pub id: Uuid,
-    pub group_id: Uuid,
-    pub group_name: String,
-    pub inviter_username: String,
+    pub message_id: Uuid,
+    pub file_name: String,
+    pub mime_type: String,
+    pub size: i64,
+}
+
+impl From<AttachmentRecord> for Attachment {
+    fn from(record: AttachmentRecord) -> Self {
+        Attachment {
+            id: record.id,
+            file_name: record.file_name,
+            mime_type: record.mime_type,
+            size: record.size as u64,
+        }
+    }
 }
 
+/// Singola riga di `message_reactions`; i messaggi le espongono raggruppate per emoji.
+#[derive(Debug, FromRow)]
+pub struct ReactionRecord {
+    pub message_id: Uuid,
+    pub user_id: Uuid,
+    pub emoji: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Claims {
+    pub sub: Uuid,
+    pub exp: i64,
+    pub iat: i64,
+    pub username: String,
+    pub sid: Uuid, // Id della sessione in `sessions`, verificato a ogni richiesta
+}
+
+#[allow(dead_code)] // Rispecchia il CHECK sulla colonna group_invitations.status
 #[derive(sqlx::Type, Debug, PartialEq)]
 #[sqlx(type_name = "invitation_status", rename_all = "lowercase")]
 pub enum InvitationStatus {
@@ -79,17 +152,3 @@ pub enum InvitationStatus {
     Accepted,
     Declined,
 }
-
-// --- Modelli per WebSocket ---
-
-#[derive(Deserialize)]
-pub struct WsClientMessage {
-    pub content: String,
-}
-
-#[derive(Serialize, Clone)]
-pub struct WsServerMessage {
-    pub sender_id: Uuid,
-    pub sender_username: String,
-    pub content: String,
-}
\ No newline at end of file
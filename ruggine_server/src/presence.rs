@@ -0,0 +1,57 @@
+use dashmap::mapref::entry::Entry;
+use dashmap::DashMap;
+use ruggine_protocol::PresenceStatus;
+use std::sync::Arc;
+use uuid::Uuid;
+
+/// Utente con almeno una connessione aperta (per gruppo o `/ws`).
+#[derive(Default)]
+pub struct UserPresence {
+    connections: usize,
+    away: bool,
+}
+
+/// Registro degli utenti connessi: chi non compare è offline.
+pub type PresenceRegistry = Arc<DashMap<Uuid, UserPresence>>;
+
+/// Registra una nuova connessione; `true` se è la prima dell'utente (che torna online).
+pub fn connect(registry: &PresenceRegistry, user_id: Uuid) -> bool {
+    let mut presence = registry.entry(user_id).or_default();
+    presence.connections += 1;
+    presence.connections == 1
+}
+
+/// Registra la chiusura di una connessione; `true` se era l'ultima (l'utente va offline).
+pub fn disconnect(registry: &PresenceRegistry, user_id: Uuid) -> bool {
+    match registry.entry(user_id) {
+        Entry::Occupied(mut entry) => {
+            entry.get_mut().connections -= 1;
+            if entry.get().connections == 0 {
+                entry.remove();
+                true
+            } else {
+                false
+            }
+        }
+        Entry::Vacant(_) => false,
+    }
+}
+
+/// Segna l'utente come assente o di nuovo attivo; `true` se lo stato è cambiato.
+pub fn set_away(registry: &PresenceRegistry, user_id: Uuid, away: bool) -> bool {
+    match registry.get_mut(&user_id) {
+        Some(mut presence) if presence.away != away => {
+            presence.away = away;
+            true
+        }
+        _ => false,
+    }
+}
+
+pub fn status(registry: &PresenceRegistry, user_id: Uuid) -> PresenceStatus {
+    match registry.get(&user_id) {
+        Some(presence) if presence.away => PresenceStatus::Away,
+        Some(_) => PresenceStatus::Online,
+        None => PresenceStatus::Offline,
+    }
+}